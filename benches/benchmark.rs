@@ -1,4 +1,4 @@
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, Criterion, SamplingMode};
 use monkey_lib::{
     compiler::Compiler,
     lexer::{token::Tokens, Lexer},
@@ -28,8 +28,8 @@ fn compile() {
             let tokens = Tokens::new(&r);
             let parsed = Parser::parse_tokens(tokens);
             match parsed {
-                Ok((_, program)) => {
-                    compiler.compile(program);
+                Ok(program) => {
+                    compiler.compile(program).unwrap();
                     let mut machine = VM::new(compiler.bytecode());
                     machine.run().unwrap();
 
@@ -44,7 +44,11 @@ fn compile() {
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("compile", |b| b.iter(|| compile()));
+    // a run takes seconds, so take a few samples of one run each
+    let mut group = c.benchmark_group("compile");
+    group.sample_size(10).sampling_mode(SamplingMode::Flat);
+    group.bench_function("fibonacci(35)", |b| b.iter(compile));
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,35 @@
+let primes = fn(n) {
+  let found = [];
+  let i = 2;
+  while (i <= n) {
+    let prime = true;
+    for (p in found) {
+      if (p * p > i) {
+        break;
+      };
+      if (i % p == 0) {
+        prime = false;
+        break;
+      }
+    };
+    if (prime) {
+      found = push(i, found)
+    };
+    i += 1
+  };
+  found
+};
+
+print(primes(50));
+
+let counts = {"m": 0, "p": 0, "s": 0};
+for (c in "mississippi") {
+  if (c == "i") {
+    continue;
+  };
+  counts[c] += 1
+};
+
+for (c in counts) {
+  print([c, counts[c]])
+};
@@ -0,0 +1,93 @@
+extern crate monkey_lib;
+#[macro_use]
+extern crate clap;
+
+use monkey_lib::error::FormatError;
+use monkey_lib::formatter::{format_source, DEFAULT_WIDTH};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
+
+/// Format `source`, printing why it can't be formatted on failure
+fn format(name: &str, source: &str, width: usize) -> Option<String> {
+    match format_source(source, width) {
+        Ok(formatted) => Some(formatted),
+        Err(FormatError::Parse(errors)) => {
+            for e in errors {
+                eprintln!("{}:{}: {}", name, e.span, e);
+            }
+            None
+        }
+        Err(err) => {
+            eprintln!("{}: {}", name, err);
+            None
+        }
+    }
+}
+
+fn main() {
+    let matches = clap_app!(monkey_fmt =>
+        (version: "0.5.0")
+        (about: "Format Monkey source files in place, or standard input to standard output")
+        (@arg check: -c --check "Only report files that aren't formatted, without changing them")
+        (@arg width: -w --width +takes_value "Maximum line width (default: 80)")
+        (@arg files: ... "Files to format")
+    )
+    .get_matches();
+
+    let check = matches.is_present("check");
+    let width = match matches.value_of("width").map(str::parse) {
+        None => DEFAULT_WIDTH,
+        Some(Ok(width)) => width,
+        Some(Err(_)) => {
+            eprintln!("--width must be a number");
+            process::exit(2);
+        }
+    };
+
+    let mut failed = false;
+    match matches.values_of("files") {
+        None => {
+            let mut source = String::new();
+            if let Err(err) = io::stdin().read_to_string(&mut source) {
+                eprintln!("<stdin>: {}", err);
+                process::exit(2);
+            }
+            match format("<stdin>", &source, width) {
+                Some(formatted) if check => failed = formatted != source,
+                Some(formatted) => io::stdout().write_all(formatted.as_bytes()).unwrap(),
+                None => failed = true,
+            }
+        }
+        Some(files) => {
+            for file in files {
+                let source = match fs::read_to_string(file) {
+                    Ok(source) => source,
+                    Err(err) => {
+                        eprintln!("{}: {}", file, err);
+                        failed = true;
+                        continue;
+                    }
+                };
+                match format(file, &source, width) {
+                    Some(formatted) if formatted == source => {}
+                    Some(_) if check => {
+                        println!("{} is not formatted", file);
+                        failed = true;
+                    }
+                    Some(formatted) => {
+                        if let Err(err) = fs::write(file, formatted) {
+                            eprintln!("{}: {}", file, err);
+                            failed = true;
+                        }
+                    }
+                    None => failed = true,
+                }
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+}
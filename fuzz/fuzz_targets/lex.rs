@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use monkey_lib::lexer::Lexer;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Lexer::lex_tokens(data);
+});
@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use monkey_lib::lexer::token::Tokens;
+use monkey_lib::lexer::Lexer;
+use monkey_lib::parser::Parser;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((_, tokens)) = Lexer::lex_tokens(data) {
+        let first = Parser::parse_tokens(Tokens::new(&tokens));
+        let (program, errors) = Parser::parse_recovering(Tokens::new(&tokens));
+
+        // recovery only changes what happens after the first error
+        match first {
+            Ok(p) => assert!(errors.is_empty() && p == program),
+            Err(e) => assert_eq!(errors.first(), Some(&e)),
+        }
+    }
+});
@@ -1,7 +1,38 @@
 use byteorder::{BigEndian, ByteOrder};
 
+use crate::{error::MonkeyError, span::Span};
+
 pub type Instructions = Vec<u8>;
 
+/// Maps instruction offsets to the source span they were compiled from. Each
+/// entry covers every instruction up to the next entry's offset.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap(Vec<(usize, Span)>);
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Record that the instruction at `pos` was compiled from `span`
+    pub fn add(&mut self, pos: usize, span: Span) {
+        self.truncate(pos);
+        self.0.push((pos, span));
+    }
+
+    /// Forget the entries of every instruction from `pos` onwards
+    pub fn truncate(&mut self, pos: usize) {
+        let keep = self.0.partition_point(|(p, _)| *p < pos);
+        self.0.truncate(keep);
+    }
+
+    /// Span of the instruction at `pos`
+    pub fn lookup(&self, pos: usize) -> Option<Span> {
+        let index = self.0.partition_point(|(p, _)| *p <= pos);
+        index.checked_sub(1).map(|i| self.0[i].1)
+    }
+}
+
 pub fn make(op: Opcode, operands: Option<Vec<u16>>) -> Instructions {
     let widths = op.look_up();
     let operands = operands.unwrap_or(vec![]);
@@ -30,10 +61,17 @@ pub fn string(ins: Instructions) -> String {
     let mut i = 0;
 
     while i < ins.len() {
-        let op: Opcode = (&ins[i]).into();
+        let op = match Opcode::try_from(&ins[i]) {
+            Ok(op) => op,
+            Err(err) => {
+                buffer.push_str(&format!("{:04} ERROR: {}\n", i, err));
+                i += 1;
+                continue;
+            }
+        };
         let widths = op.look_up();
 
-        let (operands, read) = read_operands(&widths, ins[(i + 1) as usize..].to_vec());
+        let (operands, read) = read_operands(&widths, ins[(i + 1)..].to_vec());
         let fmtted = fmt_ins(op, &widths, operands);
 
         buffer.push_str(&format!("{:04} {fmtted}\n", i));
@@ -57,13 +95,31 @@ fn fmt_ins(op: Opcode, widths: &[u8], operands: Vec<u16>) -> String {
 
     let opcode_str: String = op.into();
     match operand_cnt {
-        0 => format!("{}", opcode_str),
+        0 => opcode_str,
         1 => format!("{} {}", opcode_str, operands[0]),
         2 => format!("{} {} {}", opcode_str, operands[0], operands[1]),
         _ => format!("ERROR: unhandled operandCount for {}\n", opcode_str),
     }
 }
 
+/// Offset of the instruction that the byte at `pos` belongs to
+pub fn instruction_start(ins: &[u8], pos: usize) -> usize {
+    let mut offset = 0;
+
+    while offset < ins.len() {
+        let width = match Opcode::try_from(&ins[offset]) {
+            Ok(op) => op.width(),
+            Err(_) => 1,
+        };
+        if offset + width > pos {
+            break;
+        }
+        offset += width;
+    }
+
+    offset
+}
+
 /// Read operands from instruction given operands widths
 pub fn read_operands(widths: &[u8], ins: Instructions) -> (Vec<u16>, u8) {
     let mut operands = vec![0; widths.len()];
@@ -84,7 +140,7 @@ pub fn read_operands(widths: &[u8], ins: Instructions) -> (Vec<u16>, u8) {
 
 /// Read u16 from instruction
 pub fn read_u16(ins: &[u8]) -> u16 {
-    BigEndian::read_u16(&ins)
+    BigEndian::read_u16(ins)
 }
 
 /// Read u8 from instruction
@@ -92,6 +148,8 @@ pub fn read_u8(ins: &u8) -> u8 {
     *ins
 }
 
+/// Declared in the order of the bytes they are encoded as, which makes
+/// decoding a byte a range check
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Opcode {
     OpConstant,
@@ -123,9 +181,34 @@ pub enum Opcode {
     OpGetBuiltin,
     OpClosure,
     OpGetFree,
+    OpGreaterThanEqual,
+    OpMod,
+    OpPow,
+    OpBitAnd,
+    OpBitOr,
+    OpBitXor,
+    OpShiftLeft,
+    OpShiftRight,
+    OpBitNot,
+    OpSetFree,
+    OpCaptureLocal,
+    OpCaptureFree,
+    OpSetIndex,
+    OpPeekIndex,
+    OpIter,
+    OpIterNext,
+    /// A call whose value the calling function returns, which reuses its frame
+    OpTailCall,
+    OpLessThan,
+    OpLessThanEqual,
 }
 
 impl Opcode {
+    /// Length of an instruction with this opcode, operands included
+    pub fn width(&self) -> usize {
+        1 + self.look_up().iter().sum::<u8>() as usize
+    }
+
     /// Look up the operand width for given opcode
     fn look_up(&self) -> Vec<u8> {
         match self {
@@ -140,6 +223,7 @@ impl Opcode {
             Opcode::OpEqual => vec![],
             Opcode::OpNotEqual => vec![],
             Opcode::OpGreaterThan => vec![],
+            Opcode::OpGreaterThanEqual => vec![],
             Opcode::OpMinus => vec![],
             Opcode::OpBang => vec![],
             Opcode::OpJumpNotTruthy => vec![2],
@@ -158,6 +242,24 @@ impl Opcode {
             Opcode::OpGetBuiltin => vec![1],
             Opcode::OpClosure => vec![2, 1],
             Opcode::OpGetFree => vec![1],
+            Opcode::OpMod => vec![],
+            Opcode::OpPow => vec![],
+            Opcode::OpBitAnd => vec![],
+            Opcode::OpBitOr => vec![],
+            Opcode::OpBitXor => vec![],
+            Opcode::OpShiftLeft => vec![],
+            Opcode::OpShiftRight => vec![],
+            Opcode::OpBitNot => vec![],
+            Opcode::OpSetFree => vec![1],
+            Opcode::OpCaptureLocal => vec![1],
+            Opcode::OpCaptureFree => vec![1],
+            Opcode::OpSetIndex => vec![2],
+            Opcode::OpPeekIndex => vec![2],
+            Opcode::OpIter => vec![],
+            Opcode::OpIterNext => vec![2],
+            Opcode::OpTailCall => vec![1],
+            Opcode::OpLessThan => vec![],
+            Opcode::OpLessThanEqual => vec![],
         }
     }
 
@@ -193,13 +295,34 @@ impl Opcode {
             Opcode::OpGetBuiltin => 26,
             Opcode::OpClosure => 27,
             Opcode::OpGetFree => 28,
+            Opcode::OpGreaterThanEqual => 29,
+            Opcode::OpMod => 30,
+            Opcode::OpPow => 31,
+            Opcode::OpBitAnd => 32,
+            Opcode::OpBitOr => 33,
+            Opcode::OpBitXor => 34,
+            Opcode::OpShiftLeft => 35,
+            Opcode::OpShiftRight => 36,
+            Opcode::OpBitNot => 37,
+            Opcode::OpSetFree => 38,
+            Opcode::OpCaptureLocal => 39,
+            Opcode::OpCaptureFree => 40,
+            Opcode::OpSetIndex => 41,
+            Opcode::OpPeekIndex => 42,
+            Opcode::OpIter => 43,
+            Opcode::OpIterNext => 44,
+            Opcode::OpTailCall => 45,
+            Opcode::OpLessThan => 46,
+            Opcode::OpLessThanEqual => 47,
         }
     }
 }
 
-impl From<&u8> for Opcode {
-    fn from(v: &u8) -> Opcode {
-        match v {
+impl TryFrom<&u8> for Opcode {
+    type Error = MonkeyError;
+
+    fn try_from(v: &u8) -> Result<Opcode, MonkeyError> {
+        let op = match v {
             0 => Opcode::OpConstant,
             1 => Opcode::OpAdd,
             2 => Opcode::OpPop,
@@ -229,14 +352,35 @@ impl From<&u8> for Opcode {
             26 => Opcode::OpGetBuiltin,
             27 => Opcode::OpClosure,
             28 => Opcode::OpGetFree,
-            _ => todo!(),
-        }
+            29 => Opcode::OpGreaterThanEqual,
+            30 => Opcode::OpMod,
+            31 => Opcode::OpPow,
+            32 => Opcode::OpBitAnd,
+            33 => Opcode::OpBitOr,
+            34 => Opcode::OpBitXor,
+            35 => Opcode::OpShiftLeft,
+            36 => Opcode::OpShiftRight,
+            37 => Opcode::OpBitNot,
+            38 => Opcode::OpSetFree,
+            39 => Opcode::OpCaptureLocal,
+            40 => Opcode::OpCaptureFree,
+            41 => Opcode::OpSetIndex,
+            42 => Opcode::OpPeekIndex,
+            43 => Opcode::OpIter,
+            44 => Opcode::OpIterNext,
+            45 => Opcode::OpTailCall,
+            46 => Opcode::OpLessThan,
+            47 => Opcode::OpLessThanEqual,
+            _ => return Err(MonkeyError::InvalidOpcode(*v)),
+        };
+
+        Ok(op)
     }
 }
 
-impl Into<String> for Opcode {
-    fn into(self) -> String {
-        match self {
+impl From<Opcode> for String {
+    fn from(op: Opcode) -> String {
+        match op {
             Opcode::OpConstant => "OpConstant",
             Opcode::OpAdd => "OpAdd",
             Opcode::OpPop => "OpPop",
@@ -248,6 +392,7 @@ impl Into<String> for Opcode {
             Opcode::OpEqual => "OpEqual",
             Opcode::OpNotEqual => "OpNotEqual",
             Opcode::OpGreaterThan => "OpGreaterThan",
+            Opcode::OpGreaterThanEqual => "OpGreaterThanEqual",
             Opcode::OpMinus => "OpMinus",
             Opcode::OpBang => "OpBang",
             Opcode::OpJumpNotTruthy => "OpJumpNotTruthy",
@@ -266,6 +411,24 @@ impl Into<String> for Opcode {
             Opcode::OpGetBuiltin => "OpGetBuiltin",
             Opcode::OpClosure => "OpClosure",
             Opcode::OpGetFree => "OpGetFree",
+            Opcode::OpMod => "OpMod",
+            Opcode::OpPow => "OpPow",
+            Opcode::OpBitAnd => "OpBitAnd",
+            Opcode::OpBitOr => "OpBitOr",
+            Opcode::OpBitXor => "OpBitXor",
+            Opcode::OpShiftLeft => "OpShiftLeft",
+            Opcode::OpShiftRight => "OpShiftRight",
+            Opcode::OpBitNot => "OpBitNot",
+            Opcode::OpSetFree => "OpSetFree",
+            Opcode::OpCaptureLocal => "OpCaptureLocal",
+            Opcode::OpCaptureFree => "OpCaptureFree",
+            Opcode::OpSetIndex => "OpSetIndex",
+            Opcode::OpPeekIndex => "OpPeekIndex",
+            Opcode::OpIter => "OpIter",
+            Opcode::OpIterNext => "OpIterNext",
+            Opcode::OpTailCall => "OpTailCall",
+            Opcode::OpLessThan => "OpLessThan",
+            Opcode::OpLessThanEqual => "OpLessThanEqual",
         }
         .to_string()
     }
@@ -26,7 +26,7 @@ fn test_make() {
 
 #[test]
 fn test_instructions() {
-    let instructions = vec![
+    let instructions = [
         make(Opcode::OpAdd, None),
         make(Opcode::OpGetLocal, Some(vec![1])),
         make(Opcode::OpConstant, Some(vec![2])),
@@ -53,7 +53,7 @@ struct TestCase {
 
 #[test]
 fn test_read_operands() {
-    let tests = vec![
+    let tests = [
         TestCase {
             op: Opcode::OpConstant,
             operands: vec![65535],
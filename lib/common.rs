@@ -7,14 +7,13 @@ use crate::{
 pub fn parse(input: String) -> Program {
     let (_, l) = Lexer::lex_tokens(input.as_bytes()).unwrap();
     let tokens = Tokens::new(&l);
-    let (_, p) = Parser::parse_tokens(tokens).unwrap();
-
-    p
+    Parser::parse_tokens(tokens).unwrap()
 }
 
 pub fn oth(object: Object) -> Object {
     match object {
         Object::Integer(i) => Object::Integer(i),
+        Object::BigInt(i) => Object::BigInt(i),
         Object::Boolean(b) => Object::Boolean(b),
         Object::String(s) => Object::String(s),
         Object::Error(s) => Object::Error(s),
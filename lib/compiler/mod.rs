@@ -1,15 +1,22 @@
 use std::{cell::RefCell, rc::Rc, vec};
 
 use crate::{
-    code::{make, Instructions, Opcode},
-    evaluator::{builtins::BuiltinsFunctions, object::Object},
-    parser::ast::{Expr, Ident, Infix, Literal, Prefix, Program, Stmt},
+    code::{make, read_u16, read_u8, Instructions, Opcode, SourceMap},
+    error::CompileError,
+    evaluator::{
+        builtins::BuiltinsFunctions,
+        object::{CompiledFunction, Object},
+    },
+    parser::ast::{Expr, ExprKind, Ident, Infix, Literal, Prefix, Program, Stmt, StmtKind},
+    span::Span,
 };
 
 use self::symbol_table::{Symbol, SymbolScope, SymbolTable};
 
+#[derive(Clone)]
 pub struct CompilationScope {
     instructions: Instructions,
+    source_map: SourceMap,
     last_ins: Option<EmittedInstruction>,
     prev_ins: Option<EmittedInstruction>,
 }
@@ -19,12 +26,32 @@ pub struct Compiler {
     symbol_table: Rc<RefCell<SymbolTable>>,
     scopes: Vec<CompilationScope>,
     scope_index: usize,
+    span: Span, // span of the node being compiled, attached to emitted instructions
+    fn_name: Option<String>, // name for the function literal about to be compiled
+    loops: Vec<Loop>, // loops being compiled, innermost last
+    in_loop: bool, // whether the code compiled now is in a loop of the current function
+    // whether the code compiled now is part of an expression whose value is
+    // used, which a `break` or `continue` can't jump out of
+    in_expr: bool,
+}
+
+/// Where the jumps of `break` and `continue` in a loop body go
+struct Loop {
+    start: usize,
+    breaks: Vec<usize>, // positions of the jumps to patch with the loop's end
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
     pub fn new() -> Self {
         let main_scope = CompilationScope {
             instructions: Vec::new(),
+            source_map: SourceMap::new(),
             last_ins: None,
             prev_ins: None,
         };
@@ -39,62 +66,134 @@ impl Compiler {
             symbol_table: Rc::new(RefCell::new(symbol_table)),
             scopes: vec![main_scope],
             scope_index: 0,
+            span: Span::default(),
+            fn_name: None,
+            loops: Vec::new(),
+            in_loop: false,
+            in_expr: false,
         }
     }
 
-    pub fn compile(&mut self, program: Program) {
-        for stmt in program.iter() {
-            self.compile_statement(stmt.clone());
+    /// Compile `program`. On error everything emitted for it is discarded, so
+    /// the compiler can keep being used for further programs.
+    pub fn compile(&mut self, program: Program) -> Result<(), CompileError> {
+        let checkpoint = self.checkpoint();
+
+        for stmt in program {
+            if let Err(err) = self.compile_statement(stmt) {
+                self.restore(checkpoint);
+                return Err(err);
+            }
         }
+
+        Ok(())
     }
 
-    pub fn compile_statement(&mut self, stmt: Stmt) {
-        match stmt {
-            Stmt::ExprStmt(expr) => {
-                self.compile_expr(expr);
+    pub fn compile_statement(&mut self, stmt: Stmt) -> Result<(), CompileError> {
+        let outer_span = std::mem::replace(&mut self.span, stmt.span);
+
+        match stmt.kind {
+            StmtKind::ExprStmt(expr) => {
+                self.compile_expr(expr)?;
                 self.emit(Opcode::OpPop, None);
             }
-            Stmt::LetStmt(ident, expr) => {
-                let symbol = self.symbol_table.borrow_mut().define(ident.0);
-                self.compile_expr(expr);
-                match symbol.scope {
-                    SymbolScope::GLOBAL => self.emit(Opcode::OpSetGlobal, Some(vec![symbol.index])),
-                    SymbolScope::LOCAL => self.emit(Opcode::OpSetLocal, Some(vec![symbol.index])),
-                    _ => unimplemented!(),
-                };
+            StmtKind::LetStmt(ident, expr) => {
+                let symbol = self.define_symbol(ident.0.clone())?;
+                if let ExprKind::FnExpr { .. } = expr.kind {
+                    self.fn_name = Some(ident.0);
+                }
+                self.compile_value(expr)?;
+                self.store_symbol(symbol);
             }
-            Stmt::ReturnStmt(expr) => {
-                self.compile_expr(expr);
+            StmtKind::ReturnStmt(expr) => {
+                self.compile_value(expr)?;
                 self.emit(Opcode::OpReturnValue, None);
             }
+            StmtKind::BreakStmt => {
+                let jump = self.emit(Opcode::OpJump, Some(vec![9999])); // => jump to the end of the loop
+                self.current_loop("break")?.breaks.push(jump);
+            }
+            StmtKind::ContinueStmt => {
+                let start = self.current_loop("continue")?.start;
+                self.emit(Opcode::OpJump, Some(vec![start as u16]));
+            }
         };
+
+        self.span = outer_span;
+
+        Ok(())
     }
 
-    pub fn compile_expr(&mut self, expr: Expr) {
-        match expr {
-            Expr::IdentExpr(i) => self.compile_ident(i),
-            Expr::LitExpr(l) => self.compile_literal(l),
-            Expr::PrefixExpr(prefix, expr) => self.compile_prefix(&prefix, *expr),
-            Expr::InfixExpr(infix, expr1, expr2) => self.compile_infix(&infix, *expr1, *expr2),
-            Expr::IfExpr {
+    pub fn compile_expr(&mut self, expr: Expr) -> Result<(), CompileError> {
+        let outer_span = std::mem::replace(&mut self.span, expr.span);
+        let in_expr = std::mem::replace(&mut self.in_expr, true);
+
+        match expr.kind {
+            ExprKind::IdentExpr(i) => self.compile_ident(i),
+            ExprKind::LitExpr(l) => {
+                self.compile_literal(l);
+                Ok(())
+            }
+            ExprKind::PrefixExpr(prefix, expr) => self.compile_prefix(&prefix, *expr),
+            ExprKind::InfixExpr(infix, expr1, expr2) => self.compile_infix(&infix, *expr1, *expr2),
+            ExprKind::IfExpr {
                 cond,
                 consequence,
                 alternative,
-            } => self.compile_if(*cond, consequence, alternative),
-            Expr::FnExpr { params, body } => self.compile_fn(params, body),
-            Expr::CallExpr {
+            } => {
+                // the branches are as much part of an expression as the `if` is
+                self.in_expr = in_expr;
+                self.compile_if(*cond, consequence, alternative)
+            }
+            ExprKind::WhileExpr { cond, body } => self.compile_while(*cond, body),
+            ExprKind::ForExpr {
+                ident,
+                iterable,
+                body,
+            } => self.compile_for(ident, *iterable, body),
+            ExprKind::FnExpr { params, body } => self.compile_fn(params, body),
+            ExprKind::CallExpr {
                 function: fn_exp,
                 arguments,
             } => self.compile_call(*fn_exp, arguments),
-            Expr::ArrayExpr(exprs) => self.compile_array(exprs),
-            Expr::HashExpr(hash_exprs) => self.compile_hash(hash_exprs),
-            Expr::IndexExpr { array, index } => self.compile_index(*array, *index),
-        };
+            ExprKind::ArrayExpr(exprs) => self.compile_array(exprs),
+            ExprKind::HashExpr(hash_exprs) => self.compile_hash(hash_exprs),
+            ExprKind::IndexExpr { array, index } => self.compile_index(*array, *index),
+            ExprKind::AssignExpr {
+                target,
+                operator,
+                value,
+            } => self.compile_assign(*target, operator, *value),
+        }?;
+
+        self.span = outer_span;
+        self.in_expr = in_expr;
+
+        Ok(())
     }
 
-    pub fn compile_ident(&mut self, ident: Ident) {
-        let symbol = self.symbol_table.borrow_mut().resolve(ident.0).unwrap();
-        self.load_symbol(symbol);
+    /// Compile `expr` for its value, which a `break` or `continue` in it can't
+    /// jump out of
+    fn compile_value(&mut self, expr: Expr) -> Result<(), CompileError> {
+        let in_expr = std::mem::replace(&mut self.in_expr, true);
+        self.compile_expr(expr)?;
+        self.in_expr = in_expr;
+
+        Ok(())
+    }
+
+    pub fn compile_ident(&mut self, ident: Ident) -> Result<(), CompileError> {
+        let symbol = self.symbol_table.borrow_mut().resolve(ident.0.clone());
+        match symbol {
+            Some(symbol) => {
+                self.load_symbol(symbol);
+                Ok(())
+            }
+            None => Err(CompileError::UndefinedVariable {
+                name: ident.0,
+                span: self.span,
+            }),
+        }
     }
 
     pub fn compile_literal(&mut self, lit: Literal) {
@@ -104,6 +203,16 @@ impl Compiler {
                 let const_index = self.register_constant(&lit) as u16;
                 self.emit(Opcode::OpConstant, Some(vec![const_index]));
             }
+            Literal::BigIntLiteral(v) => {
+                let lit = Object::from(v);
+                let const_index = self.register_constant(&lit) as u16;
+                self.emit(Opcode::OpConstant, Some(vec![const_index]));
+            }
+            Literal::FloatLiteral(v) => {
+                let lit = Object::Float(v);
+                let const_index = self.register_constant(&lit) as u16;
+                self.emit(Opcode::OpConstant, Some(vec![const_index]));
+            }
             Literal::BoolLiteral(v) => {
                 match v {
                     true => self.emit(Opcode::OpTrue, None),
@@ -111,47 +220,87 @@ impl Compiler {
                 };
             }
             Literal::StringLiteral(v) => {
-                let lit = Object::String(v);
+                let lit = Object::String(v.into());
                 let const_index = self.register_constant(&lit) as u16;
                 self.emit(Opcode::OpConstant, Some(vec![const_index]));
             }
         };
     }
 
-    pub fn compile_prefix(&mut self, pre: &Prefix, expr: Expr) {
-        self.compile_expr(expr);
+    pub fn compile_prefix(&mut self, pre: &Prefix, expr: Expr) -> Result<(), CompileError> {
+        self.compile_expr(expr)?;
 
         match pre {
             Prefix::Not => self.emit(Opcode::OpBang, None),
-            Prefix::PrefixPlus => todo!(),
+            Prefix::PrefixPlus => return Err(self.unsupported_operator("+")),
             Prefix::PrefixMinus => self.emit(Opcode::OpMinus, None),
+            Prefix::BitNot => self.emit(Opcode::OpBitNot, None),
         };
+
+        Ok(())
     }
 
-    pub fn compile_infix(&mut self, infix: &Infix, expr1: Expr, expr2: Expr) {
+    pub fn compile_infix(
+        &mut self,
+        infix: &Infix,
+        expr1: Expr,
+        expr2: Expr,
+    ) -> Result<(), CompileError> {
         match infix {
-            Infix::LessThan => {
-                self.compile_expr(expr2);
-                self.compile_expr(expr1);
-                self.emit(Opcode::OpGreaterThan, None);
-            }
+            Infix::And | Infix::Or => self.compile_logical(infix, expr1, expr2)?,
             _ => {
-                self.compile_expr(expr1);
-                self.compile_expr(expr2);
-                match infix {
-                    Infix::Plus => self.emit(Opcode::OpAdd, None),
-                    Infix::Minus => self.emit(Opcode::OpSub, None),
-                    Infix::Divide => self.emit(Opcode::OpDiv, None),
-                    Infix::Multiply => self.emit(Opcode::OpMul, None),
-                    Infix::Equal => self.emit(Opcode::OpEqual, None),
-                    Infix::NotEqual => self.emit(Opcode::OpNotEqual, None),
-                    Infix::GreaterThanEqual => todo!(),
-                    Infix::LessThanEqual => todo!(),
-                    Infix::GreaterThan => self.emit(Opcode::OpGreaterThan, None),
-                    _ => unimplemented!(),
-                };
+                self.compile_expr(expr1)?;
+                self.compile_expr(expr2)?;
+                self.emit(binary_opcode(infix), None);
             }
         };
+
+        Ok(())
+    }
+
+    /// Leaves `true` or `false` on the stack. The right operand is jumped over
+    /// when the left one already decides the result.
+    fn compile_logical(
+        &mut self,
+        infix: &Infix,
+        expr1: Expr,
+        expr2: Expr,
+    ) -> Result<(), CompileError> {
+        self.compile_expr(expr1)?;
+        let left_falsy = self.emit(Opcode::OpJumpNotTruthy, Some(vec![9999]));
+
+        // `false && x` is false, `true || x` is true
+        let mut jumps_to_false = vec![];
+        let mut jump_to_true = None;
+        match infix {
+            Infix::And => jumps_to_false.push(left_falsy),
+            _ => {
+                jump_to_true = Some(self.emit(Opcode::OpJump, Some(vec![9999])));
+                let right_pos = self.current_ins().len();
+                self.change_operand(left_falsy, right_pos as u16);
+            }
+        }
+
+        self.compile_expr(expr2)?;
+        jumps_to_false.push(self.emit(Opcode::OpJumpNotTruthy, Some(vec![9999])));
+
+        if let Some(jump) = jump_to_true {
+            let true_pos = self.current_ins().len();
+            self.change_operand(jump, true_pos as u16);
+        }
+        self.emit(Opcode::OpTrue, None);
+        let jump_to_end = self.emit(Opcode::OpJump, Some(vec![9999]));
+
+        let false_pos = self.current_ins().len();
+        for jump in jumps_to_false {
+            self.change_operand(jump, false_pos as u16);
+        }
+        self.emit(Opcode::OpFalse, None);
+
+        let end_pos = self.current_ins().len();
+        self.change_operand(jump_to_end, end_pos as u16);
+
+        Ok(())
     }
 
     pub fn compile_if(
@@ -159,13 +308,13 @@ impl Compiler {
         cond: Expr,
         consequence: Vec<Stmt>,
         alternative: Option<Vec<Stmt>>,
-    ) {
-        self.compile_expr(cond);
+    ) -> Result<(), CompileError> {
+        self.compile_expr(cond)?;
 
         let jump_not_truthy_index = self.emit(Opcode::OpJumpNotTruthy, Some(vec![9999])); // condition => jump to the alternative
 
         for stmt in consequence {
-            self.compile_statement(stmt);
+            self.compile_statement(stmt)?;
         }
 
         if self.last_ins_is(Opcode::OpPop) {
@@ -177,26 +326,123 @@ impl Compiler {
         let after_conseq_pos = self.current_ins().len();
         self.change_operand(jump_not_truthy_index, after_conseq_pos as u16);
 
-        if alternative == None {
-            self.emit(Opcode::OpNull, None);
-        } else {
-            for stmt in alternative.unwrap() {
-                self.compile_statement(stmt)
+        match alternative {
+            None => {
+                self.emit(Opcode::OpNull, None);
             }
-
-            if self.last_ins_is(Opcode::OpPop) {
-                self.remove_last_pop()
+            Some(alternative) => {
+                for stmt in alternative {
+                    self.compile_statement(stmt)?;
+                }
+
+                if self.last_ins_is(Opcode::OpPop) {
+                    self.remove_last_pop()
+                }
             }
         }
 
         let after_alter_pos = self.current_ins().len();
         self.change_operand(jump_index, after_alter_pos as u16);
+
+        Ok(())
     }
 
-    pub fn compile_fn(&mut self, params: Vec<Ident>, body: Vec<Stmt>) {
-        self.enter_scope();
+    pub fn compile_while(&mut self, cond: Expr, body: Vec<Stmt>) -> Result<(), CompileError> {
+        let start = self.current_ins().len();
+        self.compile_expr(cond)?;
+
+        let jump_not_truthy_index = self.emit(Opcode::OpJumpNotTruthy, Some(vec![9999])); // condition => jump to the end of the loop
+
+        let breaks = self.compile_loop_body(start, body)?;
+
+        let end_pos = self.current_ins().len();
+        for jump in breaks.into_iter().chain([jump_not_truthy_index]) {
+            self.change_operand(jump, end_pos as u16);
+        }
+        self.emit(Opcode::OpNull, None);
+
+        Ok(())
+    }
+
+    pub fn compile_for(
+        &mut self,
+        ident: Ident,
+        iterable: Expr,
+        body: Vec<Stmt>,
+    ) -> Result<(), CompileError> {
+        self.compile_expr(iterable)?;
+        // leaves the items and the index of the next one on the stack
+        self.emit(Opcode::OpIter, None);
+
+        let symbol = self.define_symbol(ident.0)?;
+        let start = self.current_ins().len();
+        let next_index = self.emit(Opcode::OpIterNext, Some(vec![9999])); // no items left => jump to the end of the loop
+        self.store_symbol(symbol);
+
+        let breaks = self.compile_loop_body(start, body)?;
+
+        let end_pos = self.current_ins().len();
+        for jump in breaks.into_iter().chain([next_index]) {
+            self.change_operand(jump, end_pos as u16);
+        }
+        self.emit(Opcode::OpPop, None);
+        self.emit(Opcode::OpPop, None);
+        self.emit(Opcode::OpNull, None);
+
+        Ok(())
+    }
+
+    /// Compile the statements of a loop starting at `start`, and the jump
+    /// back to it. Returns the jumps of its `break`s, which go to the end.
+    fn compile_loop_body(
+        &mut self,
+        start: usize,
+        body: Vec<Stmt>,
+    ) -> Result<Vec<usize>, CompileError> {
+        self.loops.push(Loop {
+            start,
+            breaks: vec![],
+        });
+        let in_loop = std::mem::replace(&mut self.in_loop, true);
+        let in_expr = std::mem::replace(&mut self.in_expr, false);
+
+        for stmt in body {
+            self.compile_statement(stmt)?;
+        }
+        self.emit(Opcode::OpJump, Some(vec![start as u16]));
+
+        self.in_loop = in_loop;
+        self.in_expr = in_expr;
+        let innermost = self.loops.pop().expect("pushed above");
+        Ok(innermost.breaks)
+    }
+
+    /// The loop a `break` or `continue` statement being compiled belongs to
+    fn current_loop(&mut self, keyword: &'static str) -> Result<&mut Loop, CompileError> {
+        if self.in_expr {
+            return Err(CompileError::InsideExpression {
+                keyword,
+                span: self.span,
+            });
+        }
+        match self.loops.last_mut() {
+            Some(innermost) if self.in_loop => Ok(innermost),
+            _ => Err(CompileError::OutsideLoop {
+                keyword,
+                span: self.span,
+            }),
+        }
+    }
+
+    pub fn compile_fn(&mut self, params: Vec<Ident>, body: Vec<Stmt>) -> Result<(), CompileError> {
+        let name = self.fn_name.take();
 
         let num_params = params.len();
+        if num_params > u8::MAX as usize {
+            return Err(CompileError::TooManyLocals { span: self.span });
+        }
+
+        self.enter_scope();
         {
             let mut symbol_table = self.symbol_table.borrow_mut();
             for param in params {
@@ -204,9 +450,14 @@ impl Compiler {
             }
         }
 
+        // the body runs when the function is called, outside of any loop
+        let in_loop = std::mem::replace(&mut self.in_loop, false);
+        let in_expr = std::mem::replace(&mut self.in_expr, false);
         for stmt in body {
-            self.compile_statement(stmt);
+            self.compile_statement(stmt)?;
         }
+        self.in_loop = in_loop;
+        self.in_expr = in_expr;
 
         if self.last_ins_is(Opcode::OpPop) {
             self.replace_last_pop_with_return();
@@ -215,60 +466,166 @@ impl Compiler {
         if !self.last_ins_is(Opcode::OpReturnValue) {
             self.emit(Opcode::OpReturn, None);
         }
+        self.mark_tail_calls();
 
         let (free_symbols, num_locals) = {
             let symbol_table = self.symbol_table.borrow();
             (symbol_table.free_symbols.clone(), symbol_table.num_defs)
         };
 
-        let ins = self.leave_scope();
+        let (ins, source_map) = self.leave_scope();
 
         for s in &free_symbols {
-            self.load_symbol(s.clone());
+            self.capture_symbol(s.clone());
         }
 
-        let compiled_fn = Object::CompiledFn(ins, num_locals, num_params as u8);
+        let compiled_fn = Object::CompiledFn(Rc::new(CompiledFunction {
+            instructions: ins.into(),
+            num_locals,
+            num_params: num_params as u8,
+            source_map,
+            name,
+        }));
         let fn_index = self.register_constant(&compiled_fn) as u16;
         self.emit(
             Opcode::OpClosure,
             Some(vec![fn_index, free_symbols.len() as u16]),
         );
+
+        Ok(())
     }
 
-    pub fn compile_call(&mut self, fn_exp: Expr, args: Vec<Expr>) {
-        self.compile_expr(fn_exp);
+    pub fn compile_call(&mut self, fn_exp: Expr, args: Vec<Expr>) -> Result<(), CompileError> {
+        self.compile_expr(fn_exp)?;
         let len = args.len();
         for arg in args {
-            self.compile_expr(arg);
+            self.compile_expr(arg)?;
         }
 
         self.emit(Opcode::OpCall, Some(vec![len as u16]));
+
+        Ok(())
     }
 
-    pub fn compile_array(&mut self, exprs: Vec<Expr>) {
+    pub fn compile_array(&mut self, exprs: Vec<Expr>) -> Result<(), CompileError> {
         let len = exprs.len();
         for expr in exprs {
-            self.compile_expr(expr)
+            self.compile_expr(expr)?;
         }
 
         self.emit(Opcode::OpArray, Some(vec![len as u16]));
+
+        Ok(())
     }
 
-    pub fn compile_hash(&mut self, hash_exprs: Vec<(Literal, Expr)>) {
+    pub fn compile_hash(&mut self, hash_exprs: Vec<(Expr, Expr)>) -> Result<(), CompileError> {
         // TODO: need to find a way to sort so tests wont break
         let len = hash_exprs.len() as u16;
-        for (lit, key) in hash_exprs {
-            self.compile_literal(lit);
-            self.compile_expr(key);
+        for (key, value) in hash_exprs {
+            self.compile_expr(key)?;
+            self.compile_expr(value)?;
         }
 
         self.emit(Opcode::OpHash, Some(vec![len * 2]));
+
+        Ok(())
     }
 
-    pub fn compile_index(&mut self, array: Expr, index: Expr) {
-        self.compile_expr(array);
-        self.compile_expr(index);
+    pub fn compile_index(&mut self, array: Expr, index: Expr) -> Result<(), CompileError> {
+        self.compile_expr(array)?;
+        self.compile_expr(index)?;
         self.emit(Opcode::OpIndex, None);
+
+        Ok(())
+    }
+
+    /// Define `name` in the current scope, as `let` does
+    fn define_symbol(&mut self, name: String) -> Result<Symbol, CompileError> {
+        let symbol = self.symbol_table.borrow_mut().define(name);
+        if symbol.scope == SymbolScope::LOCAL && symbol.index > u8::MAX as u16 {
+            return Err(CompileError::TooManyLocals { span: self.span });
+        }
+        Ok(symbol)
+    }
+
+    /// Leaves the assigned value on the stack. Assigning to an element stores
+    /// a copy of the variable's array or hash with the element replaced.
+    pub fn compile_assign(
+        &mut self,
+        target: Expr,
+        operator: Option<Infix>,
+        value: Expr,
+    ) -> Result<(), CompileError> {
+        let (name, path) = match target.assignment_path() {
+            Some((Ident(name), path)) => {
+                (name.clone(), path.into_iter().cloned().collect::<Vec<_>>())
+            }
+            None => {
+                return Err(CompileError::InvalidAssignment {
+                    target: target.to_string(),
+                    span: self.span,
+                })
+            }
+        };
+        let symbol = match self.symbol_table.borrow_mut().resolve(name.clone()) {
+            Some(symbol) if symbol.scope == SymbolScope::BUILTIN => {
+                return Err(CompileError::InvalidAssignment {
+                    target: format!("builtin {}", name),
+                    span: self.span,
+                })
+            }
+            Some(symbol) => symbol,
+            None => {
+                return Err(CompileError::UndefinedVariable {
+                    name,
+                    span: self.span,
+                })
+            }
+        };
+
+        if path.is_empty() {
+            match &operator {
+                Some(op) => {
+                    self.load_symbol(symbol.clone());
+                    self.compile_expr(value)?;
+                    self.emit(binary_opcode(op), None);
+                }
+                None => {
+                    if let ExprKind::FnExpr { .. } = value.kind {
+                        self.fn_name = Some(name);
+                    }
+                    self.compile_expr(value)?;
+                }
+            }
+            self.store_symbol(symbol.clone());
+            self.load_symbol(symbol);
+            return Ok(());
+        }
+
+        let depth = path.len() as u16;
+        self.load_symbol(symbol.clone());
+        for key in path {
+            self.compile_expr(key)?;
+        }
+        if operator.is_some() {
+            self.emit(Opcode::OpPeekIndex, Some(vec![depth]));
+        }
+        self.compile_expr(value)?;
+        if let Some(op) = &operator {
+            self.emit(binary_opcode(op), None);
+        }
+        // leaves the value under the updated array or hash
+        self.emit(Opcode::OpSetIndex, Some(vec![depth]));
+        self.store_symbol(symbol);
+
+        Ok(())
+    }
+
+    fn unsupported_operator(&self, op: &'static str) -> CompileError {
+        CompileError::UnsupportedOperator {
+            op,
+            span: self.span,
+        }
     }
 
     fn load_symbol(&mut self, symbol: Symbol) {
@@ -280,6 +637,26 @@ impl Compiler {
         };
     }
 
+    /// Pop the top of the stack into the variable
+    fn store_symbol(&mut self, symbol: Symbol) {
+        match symbol.scope {
+            SymbolScope::GLOBAL => self.emit(Opcode::OpSetGlobal, Some(vec![symbol.index])),
+            SymbolScope::LOCAL => self.emit(Opcode::OpSetLocal, Some(vec![symbol.index])),
+            SymbolScope::FREE => self.emit(Opcode::OpSetFree, Some(vec![symbol.index])),
+            SymbolScope::BUILTIN => unreachable!("builtins can't be assigned to"),
+        };
+    }
+
+    /// Push the variable itself rather than its value, for a closure to
+    /// share with the function it is defined in
+    fn capture_symbol(&mut self, symbol: Symbol) {
+        match symbol.scope {
+            SymbolScope::LOCAL => self.emit(Opcode::OpCaptureLocal, Some(vec![symbol.index])),
+            SymbolScope::FREE => self.emit(Opcode::OpCaptureFree, Some(vec![symbol.index])),
+            _ => unreachable!("only local and free symbols are captured"),
+        };
+    }
+
     /// Append obj to constants, return its index as identifier for the OpConstant instruction
     fn register_constant(&mut self, obj: &Object) -> usize {
         self.constants.push(obj.clone());
@@ -290,6 +667,7 @@ impl Compiler {
     fn emit(&mut self, op: Opcode, operands: Option<Vec<u16>>) -> usize {
         let ins = make(op, operands);
         let pos = self.add_instruction(ins);
+        self.scopes[self.scope_index].source_map.add(pos, self.span);
 
         self.set_last_instruction(op, pos);
 
@@ -297,10 +675,7 @@ impl Compiler {
     }
 
     fn set_last_instruction(&mut self, op: Opcode, pos: usize) {
-        let prev = match self.scopes[self.scope_index].last_ins {
-            Some(ins) => Some(ins),
-            None => None,
-        };
+        let prev = self.scopes[self.scope_index].last_ins;
 
         let last = EmittedInstruction::new(op, pos);
 
@@ -320,23 +695,25 @@ impl Compiler {
     }
 
     fn last_ins_is(&self, op: Opcode) -> bool {
-        if self.current_ins().len() == 0 {
-            return false;
-        }
-
-        self.scopes[self.scope_index].last_ins.unwrap().opcode == op
+        matches!(self.scopes[self.scope_index].last_ins, Some(last) if last.opcode == op)
     }
 
     /// Remove last instruction, previous instruction becomes the last
     fn remove_last_pop(&mut self) {
-        let last = self.scopes[self.scope_index].last_ins.unwrap();
-        let prev = self.scopes[self.scope_index].prev_ins.unwrap();
+        let last = match self.scopes[self.scope_index].last_ins {
+            Some(last) => last,
+            None => return,
+        };
+        let prev = self.scopes[self.scope_index].prev_ins;
 
         let old = self.current_ins().clone();
         let new = &old[..last.position];
 
         self.scopes[self.scope_index].instructions = new.to_vec();
-        self.scopes[self.scope_index].last_ins = Some(prev);
+        self.scopes[self.scope_index]
+            .source_map
+            .truncate(last.position);
+        self.scopes[self.scope_index].last_ins = prev;
     }
 
     fn replace_ins(&mut self, pos: usize, new_ins: Vec<u8>) {
@@ -356,9 +733,31 @@ impl Compiler {
         })
     }
 
+    /// Turn the calls whose value is returned straight away, possibly after
+    /// jumping out of `if` branches, into tail calls
+    fn mark_tail_calls(&mut self) {
+        let ins = self.current_ins();
+        let mut tail_calls = vec![];
+        let mut pos = 0;
+        while pos < ins.len() {
+            let op = Opcode::try_from(&ins[pos]).expect("the compiler emitted the instructions");
+            let next = pos + op.width();
+            if op == Opcode::OpCall && returns_at(ins, next) {
+                tail_calls.push(pos);
+            }
+            pos = next;
+        }
+
+        for pos in tail_calls {
+            let num_args = read_u8(&self.current_ins()[pos + 1]) as u16;
+            self.replace_ins(pos, make(Opcode::OpTailCall, Some(vec![num_args])));
+        }
+    }
+
     /// Update the instruction at index op_pos with operand
     fn change_operand(&mut self, op_pos: usize, operand: u16) {
-        let op = Opcode::from(&self.current_ins()[op_pos]);
+        let op = Opcode::try_from(&self.current_ins()[op_pos])
+            .expect("operand changed on an instruction the compiler emitted");
         let new_ins = make(op, Some(vec![operand]));
 
         self.replace_ins(op_pos, new_ins);
@@ -371,6 +770,7 @@ impl Compiler {
     pub fn enter_scope(&mut self) {
         let scope = CompilationScope {
             instructions: Vec::new(),
+            source_map: SourceMap::new(),
             last_ins: None,
             prev_ins: None,
         };
@@ -382,30 +782,99 @@ impl Compiler {
         ))));
     }
 
-    pub fn leave_scope(&mut self) -> Instructions {
-        let ins = self.current_ins().clone();
-
-        self.scopes.pop();
+    pub fn leave_scope(&mut self) -> (Instructions, SourceMap) {
+        let scope = self.scopes.pop().unwrap();
         self.scope_index -= 1;
 
         if let Some(paren) = self.symbol_table.take().outer {
             self.symbol_table = Rc::clone(&paren);
         }
 
-        ins
+        (scope.instructions, scope.source_map)
+    }
+
+    /// Save the state to roll back to, should what is compiled next fail to
+    /// compile or to run
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            num_constants: self.constants.len(),
+            symbol_table: self.symbol_table.borrow().clone(),
+            num_scopes: self.scopes.len(),
+            scope: self.scopes[self.scope_index].clone(),
+        }
+    }
+
+    /// Roll back to the state saved by `checkpoint`
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.constants.truncate(checkpoint.num_constants);
+        self.symbol_table = Rc::new(RefCell::new(checkpoint.symbol_table));
+        self.scopes.truncate(checkpoint.num_scopes);
+        self.scope_index = checkpoint.num_scopes - 1;
+        self.scopes[self.scope_index] = checkpoint.scope;
+        self.span = Span::default();
+        self.fn_name = None;
+        self.loops.clear();
+        self.in_loop = false;
+        self.in_expr = false;
     }
 
     pub fn bytecode(&self) -> Bytecode {
         Bytecode {
             instructions: self.current_ins().clone(),
             constants: self.constants.clone(),
+            source_map: self.scopes[self.scope_index].source_map.clone(),
         }
     }
 }
 
+/// Opcode of an operator that takes both operands from the stack as they are
+fn binary_opcode(infix: &Infix) -> Opcode {
+    match infix {
+        Infix::Plus => Opcode::OpAdd,
+        Infix::Minus => Opcode::OpSub,
+        Infix::Divide => Opcode::OpDiv,
+        Infix::Multiply => Opcode::OpMul,
+        Infix::Modulo => Opcode::OpMod,
+        Infix::Power => Opcode::OpPow,
+        Infix::BitAnd => Opcode::OpBitAnd,
+        Infix::BitOr => Opcode::OpBitOr,
+        Infix::BitXor => Opcode::OpBitXor,
+        Infix::ShiftLeft => Opcode::OpShiftLeft,
+        Infix::ShiftRight => Opcode::OpShiftRight,
+        Infix::Equal => Opcode::OpEqual,
+        Infix::NotEqual => Opcode::OpNotEqual,
+        Infix::GreaterThanEqual => Opcode::OpGreaterThanEqual,
+        Infix::GreaterThan => Opcode::OpGreaterThan,
+        Infix::LessThanEqual => Opcode::OpLessThanEqual,
+        Infix::LessThan => Opcode::OpLessThan,
+        Infix::And | Infix::Or => unreachable!("{} is compiled with jumps", infix),
+    }
+}
+
+/// Whether the instructions from `pos` return the value on top of the stack,
+/// doing nothing but jump before
+fn returns_at(ins: &[u8], mut pos: usize) -> bool {
+    loop {
+        match ins.get(pos).map(Opcode::try_from) {
+            Some(Ok(Opcode::OpReturnValue)) => return true,
+            Some(Ok(Opcode::OpJump)) => pos = read_u16(&ins[pos + 1..pos + 3]) as usize,
+            _ => return false,
+        }
+    }
+}
+
+/// Compiler state to roll back to when a program fails to compile or to run
+pub struct Checkpoint {
+    num_constants: usize,
+    symbol_table: SymbolTable,
+    num_scopes: usize,
+    scope: CompilationScope,
+}
+
 pub struct Bytecode {
     pub instructions: Instructions,
     pub constants: Vec<Object>,
+    pub source_map: SourceMap,
 }
 
 #[derive(Debug, Clone, Copy)]
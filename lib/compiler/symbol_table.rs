@@ -54,6 +54,12 @@ impl SymbolTable {
             None => SymbolScope::GLOBAL,
         };
 
+        // binding a name again in the same scope reuses its slot, so that
+        // closures which captured the name see the new value
+        if let Some(symbol) = self.store.get(&name).filter(|s| s.scope == scope) {
+            return symbol.clone();
+        }
+
         let symbol = Symbol {
             name: name.clone(),
             scope,
@@ -111,7 +117,7 @@ impl SymbolTable {
                                 }
 
                                 let free = self.define_free(symbol);
-                                return Some(free);
+                                Some(free)
                             }
                             None => None,
                         }
@@ -162,6 +168,20 @@ mod symbol_table_test {
         assert_eq!(expected["f"], f);
     }
 
+    #[test]
+    fn test_redefine() {
+        let mut global = SymbolTable::new();
+        global.define("a".to_string());
+        global.define("b".to_string());
+        let a = global.define("a".to_string());
+        assert_eq!(a, Symbol::new("a".to_string(), SymbolScope::GLOBAL, 0));
+
+        let mut local = SymbolTable::new_enclosed(Rc::new(RefCell::new(global)));
+        let a = local.define("a".to_string());
+        assert_eq!(a, Symbol::new("a".to_string(), SymbolScope::LOCAL, 0));
+        assert_eq!(local.define("a".to_string()), a);
+    }
+
     #[test]
     fn test_resolve_global() {
         let mut global = SymbolTable::new();
@@ -259,7 +279,7 @@ mod symbol_table_test {
         let first_local = SymbolTable::new_enclosed(Rc::new(RefCell::new(global.clone())));
         let second_local = SymbolTable::new_enclosed(Rc::new(RefCell::new(first_local.clone())));
 
-        for mut table in vec![global, first_local, second_local] {
+        for mut table in [global, first_local, second_local] {
             for s in expected.clone() {
                 let actual = table.resolve(s.name.clone()).unwrap();
                 assert_eq!(s, actual);
@@ -282,7 +302,7 @@ mod symbol_table_test {
         second_local.define("e".to_string());
         second_local.define("f".to_string());
 
-        let first_expected = vec![
+        let first_expected = [
             vec![
                 Symbol::new("a".to_string(), SymbolScope::GLOBAL, 0),
                 Symbol::new("b".to_string(), SymbolScope::GLOBAL, 1),
@@ -292,7 +312,7 @@ mod symbol_table_test {
             vec![],
         ];
 
-        let second_expected = vec![
+        let second_expected = [
             vec![
                 Symbol::new("a".to_string(), SymbolScope::GLOBAL, 0),
                 Symbol::new("b".to_string(), SymbolScope::GLOBAL, 1),
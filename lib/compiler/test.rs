@@ -1,6 +1,8 @@
 use crate::{
     code::{make, Opcode},
     common::parse,
+    error::CompileError,
+    span::Span,
 };
 
 use super::*;
@@ -127,13 +129,39 @@ fn test_bool_expr() {
         TestCase {
             input: "1 < 2".to_string(),
             expected_constants: vec![
+                Constant::Object(Object::Integer(1)),
                 Constant::Object(Object::Integer(2)),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpConstant, Some(vec![0])),
+                make(Opcode::OpConstant, Some(vec![1])),
+                make(Opcode::OpLessThan, None),
+                make(Opcode::OpPop, None),
+            ],
+        },
+        TestCase {
+            input: "1 >= 2".to_string(),
+            expected_constants: vec![
                 Constant::Object(Object::Integer(1)),
+                Constant::Object(Object::Integer(2)),
             ],
             expected_instructions: vec![
                 make(Opcode::OpConstant, Some(vec![0])),
                 make(Opcode::OpConstant, Some(vec![1])),
-                make(Opcode::OpGreaterThan, None),
+                make(Opcode::OpGreaterThanEqual, None),
+                make(Opcode::OpPop, None),
+            ],
+        },
+        TestCase {
+            input: "1 <= 2".to_string(),
+            expected_constants: vec![
+                Constant::Object(Object::Integer(1)),
+                Constant::Object(Object::Integer(2)),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpConstant, Some(vec![0])),
+                make(Opcode::OpConstant, Some(vec![1])),
+                make(Opcode::OpLessThanEqual, None),
                 make(Opcode::OpPop, None),
             ],
         },
@@ -251,9 +279,9 @@ fn test_global_let_stmts() {
             ],
             expected_instructions: vec![
                 make(Opcode::OpConstant, Some(vec![0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
                 make(Opcode::OpConstant, Some(vec![1])),
-                make(Opcode::OpSetGlobal, Some(vec![7])),
+                make(Opcode::OpSetGlobal, Some(vec![9])),
             ],
         },
         TestCase {
@@ -261,8 +289,8 @@ fn test_global_let_stmts() {
             expected_constants: vec![Constant::Object(Object::Integer(1))],
             expected_instructions: vec![
                 make(Opcode::OpConstant, Some(vec![0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
-                make(Opcode::OpGetGlobal, Some(vec![6])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
                 make(Opcode::OpPop, None),
             ],
         },
@@ -271,10 +299,235 @@ fn test_global_let_stmts() {
             expected_constants: vec![],
             expected_instructions: vec![
                 make(Opcode::OpConstant, Some(vec![0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
-                make(Opcode::OpGetGlobal, Some(vec![6])),
-                make(Opcode::OpSetGlobal, Some(vec![7])),
-                make(Opcode::OpGetGlobal, Some(vec![7])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
+                make(Opcode::OpSetGlobal, Some(vec![9])),
+                make(Opcode::OpGetGlobal, Some(vec![9])),
+                make(Opcode::OpPop, None),
+            ],
+        },
+    ];
+
+    run_tests(tests);
+}
+
+#[test]
+fn test_float_expr() {
+    let tests = vec![TestCase {
+        input: "1.5 * 2".to_string(),
+        expected_constants: vec![
+            Constant::Object(Object::Float(1.5)),
+            Constant::Object(Object::Integer(2)),
+        ],
+        expected_instructions: vec![
+            make(Opcode::OpConstant, Some(vec![0])),
+            make(Opcode::OpConstant, Some(vec![1])),
+            make(Opcode::OpMul, None),
+            make(Opcode::OpPop, None),
+        ],
+    }];
+
+    run_tests(tests);
+}
+
+#[test]
+fn test_modulo_power_and_bitwise_expr() {
+    let operators = [
+        ("%", Opcode::OpMod),
+        ("**", Opcode::OpPow),
+        ("&", Opcode::OpBitAnd),
+        ("|", Opcode::OpBitOr),
+        ("^", Opcode::OpBitXor),
+        ("<<", Opcode::OpShiftLeft),
+        (">>", Opcode::OpShiftRight),
+    ];
+    let mut tests = operators
+        .into_iter()
+        .map(|(op, opcode)| TestCase {
+            input: format!("1 {} 2", op),
+            expected_constants: vec![
+                Constant::Object(Object::Integer(1)),
+                Constant::Object(Object::Integer(2)),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpConstant, Some(vec![0])),
+                make(Opcode::OpConstant, Some(vec![1])),
+                make(opcode, None),
+                make(Opcode::OpPop, None),
+            ],
+        })
+        .collect::<Vec<_>>();
+    tests.push(TestCase {
+        input: "~1".to_string(),
+        expected_constants: vec![Constant::Object(Object::Integer(1))],
+        expected_instructions: vec![
+            make(Opcode::OpConstant, Some(vec![0])),
+            make(Opcode::OpBitNot, None),
+            make(Opcode::OpPop, None),
+        ],
+    });
+
+    run_tests(tests);
+}
+
+#[test]
+fn test_logical_expr() {
+    let tests = vec![
+        TestCase {
+            input: "true && false".to_string(),
+            expected_constants: vec![],
+            expected_instructions: vec![
+                make(Opcode::OpTrue, None),
+                make(Opcode::OpJumpNotTruthy, Some(vec![12])),
+                make(Opcode::OpFalse, None),
+                make(Opcode::OpJumpNotTruthy, Some(vec![12])),
+                make(Opcode::OpTrue, None),
+                make(Opcode::OpJump, Some(vec![13])),
+                make(Opcode::OpFalse, None),
+                make(Opcode::OpPop, None),
+            ],
+        },
+        TestCase {
+            input: "true || false".to_string(),
+            expected_constants: vec![],
+            expected_instructions: vec![
+                make(Opcode::OpTrue, None),
+                make(Opcode::OpJumpNotTruthy, Some(vec![7])),
+                make(Opcode::OpJump, Some(vec![11])),
+                make(Opcode::OpFalse, None),
+                make(Opcode::OpJumpNotTruthy, Some(vec![15])),
+                make(Opcode::OpTrue, None),
+                make(Opcode::OpJump, Some(vec![16])),
+                make(Opcode::OpFalse, None),
+                make(Opcode::OpPop, None),
+            ],
+        },
+    ];
+
+    run_tests(tests);
+}
+
+#[test]
+fn test_assign_expr() {
+    let tests = vec![
+        TestCase {
+            input: "let x = 1; x += 2".to_string(),
+            expected_constants: vec![
+                Constant::Object(Object::Integer(1)),
+                Constant::Object(Object::Integer(2)),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpConstant, Some(vec![0])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
+                make(Opcode::OpConstant, Some(vec![1])),
+                make(Opcode::OpAdd, None),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
+                make(Opcode::OpPop, None),
+            ],
+        },
+        TestCase {
+            input: "let a = [1]; a[0] = 2".to_string(),
+            expected_constants: vec![
+                Constant::Object(Object::Integer(1)),
+                Constant::Object(Object::Integer(0)),
+                Constant::Object(Object::Integer(2)),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpConstant, Some(vec![0])),
+                make(Opcode::OpArray, Some(vec![1])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
+                make(Opcode::OpConstant, Some(vec![1])),
+                make(Opcode::OpConstant, Some(vec![2])),
+                make(Opcode::OpSetIndex, Some(vec![1])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpPop, None),
+            ],
+        },
+        TestCase {
+            input: "let a = [[1]]; a[0][0] *= 2".to_string(),
+            expected_constants: vec![
+                Constant::Object(Object::Integer(1)),
+                Constant::Object(Object::Integer(0)),
+                Constant::Object(Object::Integer(0)),
+                Constant::Object(Object::Integer(2)),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpConstant, Some(vec![0])),
+                make(Opcode::OpArray, Some(vec![1])),
+                make(Opcode::OpArray, Some(vec![1])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
+                make(Opcode::OpConstant, Some(vec![1])),
+                make(Opcode::OpConstant, Some(vec![2])),
+                make(Opcode::OpPeekIndex, Some(vec![2])),
+                make(Opcode::OpConstant, Some(vec![3])),
+                make(Opcode::OpMul, None),
+                make(Opcode::OpSetIndex, Some(vec![2])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpPop, None),
+            ],
+        },
+        TestCase {
+            input: "fn() { let n = 0; fn() { n = 1 } }".to_string(),
+            expected_constants: vec![
+                Constant::Object(Object::Integer(0)),
+                Constant::Object(Object::Integer(1)),
+                Constant::Instructions(vec![
+                    make(Opcode::OpConstant, Some(vec![1])),
+                    make(Opcode::OpSetFree, Some(vec![0])),
+                    make(Opcode::OpGetFree, Some(vec![0])),
+                    make(Opcode::OpReturnValue, None),
+                ]),
+                Constant::Instructions(vec![
+                    make(Opcode::OpConstant, Some(vec![0])),
+                    make(Opcode::OpSetLocal, Some(vec![0])),
+                    make(Opcode::OpCaptureLocal, Some(vec![0])),
+                    make(Opcode::OpClosure, Some(vec![2, 1])),
+                    make(Opcode::OpReturnValue, None),
+                ]),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpClosure, Some(vec![3, 0])),
+                make(Opcode::OpPop, None),
+            ],
+        },
+    ];
+
+    run_tests(tests);
+}
+
+#[test]
+fn test_loops() {
+    let tests = vec![
+        TestCase {
+            input: "while (true) { break }".to_string(),
+            expected_constants: vec![],
+            expected_instructions: vec![
+                make(Opcode::OpTrue, None),
+                make(Opcode::OpJumpNotTruthy, Some(vec![10])),
+                make(Opcode::OpJump, Some(vec![10])),
+                make(Opcode::OpJump, Some(vec![0])),
+                make(Opcode::OpNull, None),
+                make(Opcode::OpPop, None),
+            ],
+        },
+        TestCase {
+            input: "for (x in [1]) { continue }".to_string(),
+            expected_constants: vec![Constant::Object(Object::Integer(1))],
+            expected_instructions: vec![
+                make(Opcode::OpConstant, Some(vec![0])),
+                make(Opcode::OpArray, Some(vec![1])),
+                make(Opcode::OpIter, None),
+                make(Opcode::OpIterNext, Some(vec![19])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpJump, Some(vec![7])),
+                make(Opcode::OpJump, Some(vec![7])),
+                make(Opcode::OpPop, None),
+                make(Opcode::OpPop, None),
+                make(Opcode::OpNull, None),
                 make(Opcode::OpPop, None),
             ],
         },
@@ -288,7 +541,7 @@ fn test_string_expr() {
     let tests = vec![
         TestCase {
             input: "\"monkey\"".to_string(),
-            expected_constants: vec![Constant::Object(Object::String("monkey".to_string()))],
+            expected_constants: vec![Constant::Object(Object::String("monkey".into()))],
             expected_instructions: vec![
                 make(Opcode::OpConstant, Some(vec![0])),
                 make(Opcode::OpPop, None),
@@ -297,8 +550,8 @@ fn test_string_expr() {
         TestCase {
             input: " \"mon\" + \"key\" ".to_string(),
             expected_constants: vec![
-                Constant::Object(Object::String("mon".to_string())),
-                Constant::Object(Object::String("key".to_string())),
+                Constant::Object(Object::String("mon".into())),
+                Constant::Object(Object::String("key".into())),
             ],
             expected_instructions: vec![
                 make(Opcode::OpConstant, Some(vec![0])),
@@ -577,8 +830,8 @@ fn test_function_calls() {
             ],
             expected_instructions: vec![
                 make(Opcode::OpClosure, Some(vec![1, 0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
-                make(Opcode::OpGetGlobal, Some(vec![6])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
                 make(Opcode::OpCall, Some(vec![0])),
                 make(Opcode::OpPop, None),
             ],
@@ -591,8 +844,8 @@ fn test_function_calls() {
             ],
             expected_instructions: vec![
                 make(Opcode::OpClosure, Some(vec![0, 0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
-                make(Opcode::OpGetGlobal, Some(vec![6])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
                 make(Opcode::OpConstant, Some(vec![1])),
                 make(Opcode::OpCall, Some(vec![1])),
                 make(Opcode::OpPop, None),
@@ -608,8 +861,8 @@ fn test_function_calls() {
             ],
             expected_instructions: vec![
                 make(Opcode::OpClosure, Some(vec![0, 0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
-                make(Opcode::OpGetGlobal, Some(vec![6])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
                 make(Opcode::OpConstant, Some(vec![1])),
                 make(Opcode::OpConstant, Some(vec![2])),
                 make(Opcode::OpConstant, Some(vec![3])),
@@ -628,8 +881,8 @@ fn test_function_calls() {
             ],
             expected_instructions: vec![
                 make(Opcode::OpClosure, Some(vec![0, 0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
-                make(Opcode::OpGetGlobal, Some(vec![6])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
                 make(Opcode::OpConstant, Some(vec![1])),
                 make(Opcode::OpCall, Some(vec![1])),
                 make(Opcode::OpPop, None),
@@ -652,8 +905,8 @@ fn test_function_calls() {
             ],
             expected_instructions: vec![
                 make(Opcode::OpClosure, Some(vec![0, 0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
-                make(Opcode::OpGetGlobal, Some(vec![6])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
+                make(Opcode::OpGetGlobal, Some(vec![8])),
                 make(Opcode::OpConstant, Some(vec![1])),
                 make(Opcode::OpConstant, Some(vec![2])),
                 make(Opcode::OpConstant, Some(vec![3])),
@@ -666,6 +919,75 @@ fn test_function_calls() {
     run_tests(tests);
 }
 
+#[test]
+fn test_tail_calls() {
+    let tests = vec![
+        TestCase {
+            input: "fn(f) { return f(1); 2 }".to_string(),
+            expected_constants: vec![
+                Constant::Object(Object::Integer(1)),
+                Constant::Object(Object::Integer(2)),
+                Constant::Instructions(vec![
+                    make(Opcode::OpGetLocal, Some(vec![0])),
+                    make(Opcode::OpConstant, Some(vec![0])),
+                    make(Opcode::OpTailCall, Some(vec![1])),
+                    make(Opcode::OpReturnValue, None),
+                    make(Opcode::OpConstant, Some(vec![1])),
+                    make(Opcode::OpReturnValue, None),
+                ]),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpClosure, Some(vec![2, 0])),
+                make(Opcode::OpPop, None),
+            ],
+        },
+        TestCase {
+            input: "fn(f, x) { if (x) { f(1) } else { f(2) } }".to_string(),
+            expected_constants: vec![
+                Constant::Object(Object::Integer(1)),
+                Constant::Object(Object::Integer(2)),
+                Constant::Instructions(vec![
+                    make(Opcode::OpGetLocal, Some(vec![1])),
+                    make(Opcode::OpJumpNotTruthy, Some(vec![15])),
+                    make(Opcode::OpGetLocal, Some(vec![0])),
+                    make(Opcode::OpConstant, Some(vec![0])),
+                    make(Opcode::OpTailCall, Some(vec![1])),
+                    make(Opcode::OpJump, Some(vec![22])),
+                    make(Opcode::OpGetLocal, Some(vec![0])),
+                    make(Opcode::OpConstant, Some(vec![1])),
+                    make(Opcode::OpTailCall, Some(vec![1])),
+                    make(Opcode::OpReturnValue, None),
+                ]),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpClosure, Some(vec![2, 0])),
+                make(Opcode::OpPop, None),
+            ],
+        },
+        TestCase {
+            input: "fn(f) { f(1) + 1 }".to_string(),
+            expected_constants: vec![
+                Constant::Object(Object::Integer(1)),
+                Constant::Object(Object::Integer(1)),
+                Constant::Instructions(vec![
+                    make(Opcode::OpGetLocal, Some(vec![0])),
+                    make(Opcode::OpConstant, Some(vec![0])),
+                    make(Opcode::OpCall, Some(vec![1])),
+                    make(Opcode::OpConstant, Some(vec![1])),
+                    make(Opcode::OpAdd, None),
+                    make(Opcode::OpReturnValue, None),
+                ]),
+            ],
+            expected_instructions: vec![
+                make(Opcode::OpClosure, Some(vec![2, 0])),
+                make(Opcode::OpPop, None),
+            ],
+        },
+    ];
+
+    run_tests(tests);
+}
+
 #[test]
 fn test_compilation_scopes() {
     let mut compiler = Compiler::new();
@@ -708,13 +1030,13 @@ fn test_let_stmt_scopes() {
             expected_constants: vec![
                 Constant::Object(Object::Integer(55)),
                 Constant::Instructions(vec![
-                    make(Opcode::OpGetGlobal, Some(vec![6])),
+                    make(Opcode::OpGetGlobal, Some(vec![8])),
                     make(Opcode::OpReturnValue, None),
                 ]),
             ],
             expected_instructions: vec![
                 make(Opcode::OpConstant, Some(vec![0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
                 make(Opcode::OpClosure, Some(vec![1, 0])),
                 make(Opcode::OpPop, None),
             ],
@@ -793,7 +1115,7 @@ fn test_builtins() {
             expected_constants: vec![Constant::Instructions(vec![
                 make(Opcode::OpGetBuiltin, Some(vec![1])),
                 make(Opcode::OpArray, Some(vec![0])),
-                make(Opcode::OpCall, Some(vec![1])),
+                make(Opcode::OpTailCall, Some(vec![1])),
                 make(Opcode::OpReturnValue, None),
             ])],
             expected_instructions: vec![
@@ -825,7 +1147,7 @@ fn test_closures() {
                     make(Opcode::OpReturnValue, None),
                 ]),
                 Constant::Instructions(vec![
-                    make(Opcode::OpGetLocal, Some(vec![0])),
+                    make(Opcode::OpCaptureLocal, Some(vec![0])),
                     make(Opcode::OpClosure, Some(vec![0, 1])),
                     make(Opcode::OpReturnValue, None),
                 ]),
@@ -855,13 +1177,13 @@ fn test_closures() {
                     make(Opcode::OpReturnValue, None),
                 ]),
                 Constant::Instructions(vec![
-                    make(Opcode::OpGetFree, Some(vec![0])),
-                    make(Opcode::OpGetLocal, Some(vec![0])),
+                    make(Opcode::OpCaptureFree, Some(vec![0])),
+                    make(Opcode::OpCaptureLocal, Some(vec![0])),
                     make(Opcode::OpClosure, Some(vec![0, 2])),
                     make(Opcode::OpReturnValue, None),
                 ]),
                 Constant::Instructions(vec![
-                    make(Opcode::OpGetLocal, Some(vec![0])),
+                    make(Opcode::OpCaptureLocal, Some(vec![0])),
                     make(Opcode::OpClosure, Some(vec![1, 1])),
                     make(Opcode::OpReturnValue, None),
                 ]),
@@ -894,7 +1216,7 @@ fn test_closures() {
                 Constant::Instructions(vec![
                     make(Opcode::OpConstant, Some(vec![3])),
                     make(Opcode::OpSetLocal, Some(vec![0])),
-                    make(Opcode::OpGetGlobal, Some(vec![6])),
+                    make(Opcode::OpGetGlobal, Some(vec![8])),
                     make(Opcode::OpGetFree, Some(vec![0])),
                     make(Opcode::OpAdd, None),
                     make(Opcode::OpGetFree, Some(vec![1])),
@@ -906,22 +1228,22 @@ fn test_closures() {
                 Constant::Instructions(vec![
                     make(Opcode::OpConstant, Some(vec![2])),
                     make(Opcode::OpSetLocal, Some(vec![0])),
-                    make(Opcode::OpGetFree, Some(vec![0])),
-                    make(Opcode::OpGetLocal, Some(vec![0])),
+                    make(Opcode::OpCaptureFree, Some(vec![0])),
+                    make(Opcode::OpCaptureLocal, Some(vec![0])),
                     make(Opcode::OpClosure, Some(vec![4, 2])),
                     make(Opcode::OpReturnValue, None),
                 ]),
                 Constant::Instructions(vec![
                     make(Opcode::OpConstant, Some(vec![1])),
                     make(Opcode::OpSetLocal, Some(vec![0])),
-                    make(Opcode::OpGetLocal, Some(vec![0])),
+                    make(Opcode::OpCaptureLocal, Some(vec![0])),
                     make(Opcode::OpClosure, Some(vec![5, 1])),
                     make(Opcode::OpReturnValue, None),
                 ]),
             ],
             expected_instructions: vec![
                 make(Opcode::OpConstant, Some(vec![0])),
-                make(Opcode::OpSetGlobal, Some(vec![6])),
+                make(Opcode::OpSetGlobal, Some(vec![8])),
                 make(Opcode::OpClosure, Some(vec![6, 0])),
                 make(Opcode::OpPop, None),
             ],
@@ -931,9 +1253,125 @@ fn test_closures() {
     run_tests(tests);
 }
 
+#[test]
+fn test_source_map() {
+    let program = parse("let a = 1;\na + [2]".to_string());
+    let mut compiler = Compiler::new();
+    compiler.compile(program).unwrap();
+
+    let bytecode = compiler.bytecode();
+    let expected = vec![
+        (0, Span::new(8, 9, 1, 9)),  // OpConstant 0
+        (3, Span::new(0, 10, 1, 1)), // OpSetGlobal
+        (6, Span::new(11, 12, 2, 1)),
+        (9, Span::new(16, 17, 2, 6)),
+        (12, Span::new(15, 18, 2, 5)), // OpArray
+        (15, Span::new(11, 18, 2, 1)), // OpAdd
+        (16, Span::new(11, 18, 2, 1)), // OpPop
+    ];
+
+    for (pos, span) in expected {
+        assert_eq!(Some(span), bytecode.source_map.lookup(pos));
+    }
+}
+
+#[test]
+fn test_compile_errors() {
+    let tests = [
+        (
+            "let a = 1;\nfn() { a + b }",
+            CompileError::UndefinedVariable {
+                name: "b".to_string(),
+                span: Span::new(22, 23, 2, 12),
+            },
+        ),
+        (
+            "+1",
+            CompileError::UnsupportedOperator {
+                op: "+",
+                span: Span::new(0, 2, 1, 1),
+            },
+        ),
+        (
+            "break",
+            CompileError::OutsideLoop {
+                keyword: "break",
+                span: Span::new(0, 5, 1, 1),
+            },
+        ),
+        (
+            "while (true) { 1 + if (true) { continue } }",
+            CompileError::InsideExpression {
+                keyword: "continue",
+                span: Span::new(31, 39, 1, 32),
+            },
+        ),
+        (
+            "for (x in []) { let y = if (x) { break } else { x }; }",
+            CompileError::InsideExpression {
+                keyword: "break",
+                span: Span::new(33, 38, 1, 34),
+            },
+        ),
+        (
+            "for (x in []) { fn() { break } }",
+            CompileError::OutsideLoop {
+                keyword: "break",
+                span: Span::new(23, 28, 1, 24),
+            },
+        ),
+    ];
+
+    for (input, expected) in tests {
+        let mut compiler = Compiler::new();
+        assert_eq!(Err(expected), compiler.compile(parse(input.to_string())));
+    }
+}
+
+#[test]
+fn test_too_many_locals() {
+    let lets = (0..300)
+        .map(|i| format!("let x{} = {};", i, i))
+        .collect::<String>();
+    let program = parse(format!("fn() {{ {} }}", lets));
+
+    let mut compiler = Compiler::new();
+    match compiler.compile(program) {
+        Err(CompileError::TooManyLocals { .. }) => {}
+        other => panic!("expected too many locals error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_recover_after_error() {
+    let mut compiler = Compiler::new();
+    compiler.compile(parse("let a = 1;".to_string())).unwrap();
+    assert!(compiler
+        .compile(parse("let b = fn() { c };".to_string()))
+        .is_err());
+    compiler.compile(parse("a".to_string())).unwrap();
+
+    let bytecode = compiler.bytecode();
+    test_instructions(
+        vec![
+            make(Opcode::OpConstant, Some(vec![0])),
+            make(Opcode::OpSetGlobal, Some(vec![8])),
+            make(Opcode::OpGetGlobal, Some(vec![8])),
+            make(Opcode::OpPop, None),
+        ],
+        bytecode.instructions,
+    );
+    assert_eq!(1, bytecode.constants.len());
+    assert!(compiler
+        .symbol_table
+        .borrow_mut()
+        .resolve("b".to_string())
+        .is_none());
+}
+
 fn test_string_object(expected: String, actual: Object) {
     match actual {
-        Object::String(v) => assert_eq!(expected, v),
+        Object::String(v) => assert_eq!(expected, v.as_ref()),
         _ => panic!("object is not String. got={:?}", actual),
     }
 }
@@ -955,23 +1393,15 @@ fn test_constants(expected: Vec<Constant>, actual: Vec<Object>) {
     for (i, constant) in expected.iter().enumerate() {
         match constant {
             Constant::Object(obj) => match obj {
+                Object::Float(_) | Object::BigInt(_) => assert_eq!(obj, &actual[i]),
                 Object::Integer(v) => test_int_object(*v, actual[i].clone()),
-                Object::Boolean(_) => todo!(),
                 Object::String(v) => test_string_object(v.to_string(), actual[i].clone()),
-                Object::Array(_) => todo!(),
-                Object::Hash(_) => todo!(),
-                Object::Function(_, _, _) => todo!(),
-                Object::Builtin(_, _, _) => todo!(),
-                Object::Null => todo!(),
-                Object::ReturnValue(_) => todo!(),
-                Object::Error(_) => todo!(),
-                Object::CompiledFn(_, _, _) => todo!(),
-                Object::Closure(_, _) => todo!(),
+                other => panic!("unexpected constant {:?}", other),
             },
             Constant::Instructions(ins) => {
                 let func = actual[i].clone();
                 let result = match func {
-                    Object::CompiledFn(actual_ins, _, _) => actual_ins,
+                    Object::CompiledFn(func) => func.instructions.to_vec(),
                     _ => unimplemented!(),
                 };
                 test_instructions(ins.to_vec(), result);
@@ -984,7 +1414,7 @@ fn run_tests(tests: Vec<TestCase>) {
     for test in tests {
         let program = parse(test.input);
         let mut compiler = Compiler::new();
-        compiler.compile(program);
+        compiler.compile(program).unwrap();
 
         let bytecode = compiler.bytecode();
 
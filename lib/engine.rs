@@ -0,0 +1,100 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    compiler::Compiler,
+    evaluator::{object::Object, Evaluator},
+    parser::ast::Program,
+    vm::VM,
+};
+
+/// Backend used to run a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Tree-walking `Evaluator`
+    Eval,
+    /// Bytecode `Compiler` and `VM`
+    Vm,
+}
+
+impl Engine {
+    pub const NAMES: [&'static str; 2] = ["eval", "vm"];
+
+    /// Run `program` to completion and report what it evaluated to
+    pub fn run(self, program: Program) -> Outcome {
+        match self {
+            Engine::Eval => Outcome::from(Evaluator::new().eval_program(program)),
+            Engine::Vm => {
+                let mut compiler = Compiler::new();
+                if let Err(err) = compiler.compile(program) {
+                    return Outcome::Error(err.to_string());
+                }
+
+                let mut machine = VM::new(compiler.bytecode());
+                match machine.run() {
+                    Ok(()) => Outcome::from(machine.last_popped_stack_ele()),
+                    Err(err) => Outcome::Error(err.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// The result of running a program, with the parts that legitimately differ
+/// between engines erased so that outcomes can be compared.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Value(Object),
+    /// Each engine has its own representation of functions
+    Function,
+    /// Error messages are engine specific, only the failure is comparable
+    Error(String),
+}
+
+impl Outcome {
+    /// Whether two engines producing these outcomes behaved the same
+    pub fn agrees_with(&self, other: &Outcome) -> bool {
+        match (self, other) {
+            (Outcome::Error(_), Outcome::Error(_)) => true,
+            // NaN is not equal to itself, but both engines computed it
+            (Outcome::Value(Object::Float(x)), Outcome::Value(Object::Float(y))) => {
+                x == y || (x.is_nan() && y.is_nan())
+            }
+            _ => self == other,
+        }
+    }
+}
+
+impl From<Object> for Outcome {
+    fn from(object: Object) -> Self {
+        match object {
+            Object::Error(msg) => Outcome::Error(msg),
+            Object::ReturnValue(o) => Outcome::from(*o),
+            Object::Function(_, _, _)
+            | Object::Builtin(_, _, _)
+            | Object::CompiledFn(_)
+            | Object::Closure(_, _) => Outcome::Function,
+            o => Outcome::Value(o),
+        }
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eval" => Ok(Engine::Eval),
+            "vm" => Ok(Engine::Vm),
+            _ => Err(format!("unknown engine: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Engine::Eval => write!(f, "eval"),
+            Engine::Vm => write!(f, "vm"),
+        }
+    }
+}
@@ -1,19 +1,155 @@
+use std::fmt;
+
 use thiserror::Error;
 
-use crate::{code::Opcode, evaluator::object::Object};
+use crate::{code::Opcode, evaluator::object::Object, parser::error::ParseError, span::Span};
 
 #[derive(Debug, Error)]
-pub enum MonkeyError<'a> {
+pub enum MonkeyError {
     #[error("Opcode not found: {:?}", .0)]
-    OpcodeNotFound(&'a Opcode),
-    #[error("Unknown integer operator")]
-    UnknownOperator,
+    OpcodeNotFound(Opcode),
+    #[error("Invalid opcode: {}", .0)]
+    InvalidOpcode(u8),
+    #[error("Unknown operator: {left} {op} {right}")]
+    UnknownOperator {
+        op: &'static str,
+        left: &'static str,
+        right: &'static str,
+    },
+    #[error("Type mismatch: {left} {op} {right}")]
+    TypeMismatch {
+        op: &'static str,
+        left: &'static str,
+        right: &'static str,
+    },
     #[error("Max stack size reached")]
     StackOverflow,
     #[error("Empty stack")]
     EmptyStackException,
     #[error("Unsupported type for negation: {}", .0)]
     UnsupportedType(Object),
+    #[error("Not a function: {}", .0)]
+    NotCallable(Object),
+    #[error("Not iterable: {}", .0)]
+    NotIterable(Object),
+    #[error("Wrong number of arguments: {expected} expected but {got} given")]
+    WrongArgumentCount { expected: usize, got: usize },
+    #[error("Unusable as hash key: {}", .0)]
+    Unhashable(Object),
+    #[error("Index out of bounds: {index} for array of length {len}")]
+    IndexOutOfBounds { index: i64, len: usize },
+    #[error("Index operator not supported: {left}[{index}]")]
+    IndexNotSupported {
+        left: &'static str,
+        index: &'static str,
+    },
+    #[error(transparent)]
+    Arithmetic(#[from] ArithmeticError),
+}
+
+pub type Result<T> = std::result::Result<T, MonkeyError>;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CompileError {
+    #[error("Undefined variable: {name}")]
+    UndefinedVariable { name: String, span: Span },
+    #[error("Unsupported operator: {op}")]
+    UnsupportedOperator { op: &'static str, span: Span },
+    #[error("Too many local bindings in function (at most 256)")]
+    TooManyLocals { span: Span },
+    #[error("Cannot assign to {target}")]
+    InvalidAssignment { target: String, span: Span },
+    #[error("`{keyword}` outside of a loop")]
+    OutsideLoop { keyword: &'static str, span: Span },
+    #[error("`{keyword}` cannot be used inside an expression")]
+    InsideExpression { keyword: &'static str, span: Span },
+}
+
+impl CompileError {
+    /// Source span of the node that failed to compile
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::UndefinedVariable { span, .. }
+            | CompileError::UnsupportedOperator { span, .. }
+            | CompileError::TooManyLocals { span }
+            | CompileError::InvalidAssignment { span, .. }
+            | CompileError::OutsideLoop { span, .. }
+            | CompileError::InsideExpression { span, .. } => *span,
+        }
+    }
+}
+
+/// Arithmetic without a representable result, reported the same way by both
+/// engines
+#[derive(Debug, Error, Clone, Copy, PartialEq)]
+pub enum ArithmeticError {
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("negative shift count")]
+    NegativeShift,
+    /// The result would take more than `MAX_BITS` bits
+    #[error("result too large")]
+    TooLarge,
+}
+
+/// Reasons the formatter can't lay out a source file
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("Could not lex the source")]
+    Lex,
+    /// Every syntax error in the source, since none of it can be formatted
+    #[error("{} syntax error(s)", .0.len())]
+    Parse(Vec<ParseError>),
+}
+
+/// A runtime error together with the call stack it was raised in
+#[derive(Debug, Error)]
+#[error("{error}")]
+pub struct RuntimeError {
+    pub error: MonkeyError,
+    pub trace: StackTrace,
+}
+
+impl RuntimeError {
+    /// Source span of the instruction the error was raised at
+    pub fn span(&self) -> Option<Span> {
+        self.trace.0.first().and_then(|frame| frame.span)
+    }
+}
+
+/// Call frames active when an error was raised, innermost first
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StackTrace(pub Vec<TraceFrame>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: Option<String>,
+    pub offset: usize,
+    pub span: Option<Span>,
+}
+
+// deep recursion would otherwise print a line per frame
+const MAX_TRACE_FRAMES: usize = 16;
+
+impl fmt::Display for StackTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for frame in self.0.iter().take(MAX_TRACE_FRAMES) {
+            writeln!(f, "  at {}", frame)?;
+        }
+        if self.0.len() > MAX_TRACE_FRAMES {
+            writeln!(f, "  ... {} more frames", self.0.len() - MAX_TRACE_FRAMES)?;
+        }
+
+        Ok(())
+    }
 }
 
-pub type Result<'a, T> = std::result::Result<T, MonkeyError<'a>>;
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.function.as_deref().unwrap_or("<anonymous>");
+        match self.span {
+            Some(span) => write!(f, "{} (offset {:04}, line {})", name, self.offset, span),
+            None => write!(f, "{} (offset {:04})", name, self.offset),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use crate::evaluator::object::*;
 use crate::parser::ast::*;
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
 
 pub struct BuiltinsFunctions;
 
@@ -22,6 +24,8 @@ impl BuiltinsFunctions {
             "tail".to_string(),
             "cons".to_string(),
             "push".to_string(),
+            "float".to_string(),
+            "int".to_string(),
         ]
     }
 
@@ -33,6 +37,8 @@ impl BuiltinsFunctions {
             add_builtin("tail", 1, btail_fn),
             add_builtin("cons", 2, bcons_fn),
             add_builtin("push", 2, bpush_fn),
+            add_builtin("float", 1, bfloat_fn),
+            add_builtin("int", 1, bint_fn),
         ]
     }
 }
@@ -43,7 +49,7 @@ fn add_builtin(name: &str, param_num: usize, func: BuiltinFunction) -> (Ident, O
 }
 
 fn bprint_fn(args: Vec<Object>) -> Result<Object, String> {
-    match args.get(0) {
+    match args.first() {
         Some(Object::String(t)) => {
             println!("{}", t);
             Ok(Object::Null)
@@ -57,7 +63,7 @@ fn bprint_fn(args: Vec<Object>) -> Result<Object, String> {
 }
 
 fn blen_fn(args: Vec<Object>) -> Result<Object, String> {
-    match args.get(0) {
+    match args.first() {
         Some(Object::String(s)) => Ok(Object::Integer(s.len() as i64)),
         Some(Object::Array(arr)) => Ok(Object::Integer(arr.len() as i64)),
         _ => Err(String::from("invalid arguments for len")),
@@ -66,9 +72,9 @@ fn blen_fn(args: Vec<Object>) -> Result<Object, String> {
 
 fn bhead_fn(args: Vec<Object>) -> Result<Object, String> {
     match args.into_iter().next() {
-        Some(Object::Array(arr)) => match arr.into_iter().next() {
+        Some(Object::Array(arr)) => match arr.front() {
             None => Err(String::from("empty array")),
-            Some(x) => Ok(x),
+            Some(x) => Ok(x.clone()),
         },
         _ => Err(String::from("invalid arguments for head")),
     }
@@ -76,12 +82,9 @@ fn bhead_fn(args: Vec<Object>) -> Result<Object, String> {
 
 fn btail_fn(args: Vec<Object>) -> Result<Object, String> {
     match args.into_iter().next() {
-        Some(Object::Array(mut arr)) => match arr.len() {
+        Some(Object::Array(arr)) => match arr.len() {
             0 => Err(String::from("empty array")),
-            _ => {
-                arr.remove(0);
-                Ok(Object::Array(arr))
-            }
+            _ => Ok(Object::Array(arr.skip(1))),
         },
         _ => Err(String::from("invalid arguments for tail")),
     }
@@ -91,7 +94,7 @@ fn bcons_fn(args: Vec<Object>) -> Result<Object, String> {
     let mut args = args.into_iter();
     match (args.next(), args.next()) {
         (Some(o), Some(Object::Array(mut os))) => {
-            os.insert(0, o);
+            os.push_front(o);
             Ok(Object::Array(os))
         }
         _ => Err(String::from("invalid arguments for cons")),
@@ -102,9 +105,42 @@ fn bpush_fn(args: Vec<Object>) -> Result<Object, String> {
     let mut args = args.into_iter();
     match (args.next(), args.next()) {
         (Some(o), Some(Object::Array(mut os))) => {
-            os.push(o);
+            os.push_back(o);
             Ok(Object::Array(os))
         }
         _ => Err(String::from("invalid arguments for push")),
     }
 }
+
+fn bfloat_fn(args: Vec<Object>) -> Result<Object, String> {
+    match args.first() {
+        Some(Object::Integer(i)) => Ok(Object::Float(*i as f64)),
+        Some(Object::BigInt(i)) => match i.to_f64() {
+            Some(x) => Ok(Object::Float(x)),
+            None => Err(format!("{} is out of range for float", i)),
+        },
+        Some(Object::Float(x)) => Ok(Object::Float(*x)),
+        Some(Object::String(s)) => match s.trim().parse::<f64>() {
+            Ok(x) => Ok(Object::Float(x)),
+            Err(_) => Err(format!("could not convert {:?} to float", s)),
+        },
+        _ => Err(String::from("invalid arguments for float")),
+    }
+}
+
+/// Floats are truncated towards zero
+fn bint_fn(args: Vec<Object>) -> Result<Object, String> {
+    match args.first() {
+        Some(Object::Integer(i)) => Ok(Object::Integer(*i)),
+        Some(Object::BigInt(i)) => Ok(Object::BigInt(i.clone())),
+        Some(Object::Float(x)) => match BigInt::from_f64(x.trunc()) {
+            Some(i) => Ok(Object::from(i)),
+            None => Err(format!("{:?} is out of range for int", x)),
+        },
+        Some(Object::String(s)) => match s.trim().parse::<BigInt>() {
+            Ok(i) => Ok(Object::from(i)),
+            Err(_) => Err(format!("could not convert {:?} to int", s)),
+        },
+        _ => Err(String::from("invalid arguments for int")),
+    }
+}
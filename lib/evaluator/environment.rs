@@ -19,35 +19,48 @@ impl Default for Environment {
 
 impl Environment {
     pub fn new() -> Self {
-        let mut hashmap = HashMap::new();
-        Self::fill_env_with_builtins(&mut hashmap);
         Environment {
-            store: hashmap,
+            store: HashMap::new(),
             parent: None,
         }
     }
 
     pub fn new_with_outer(outer: Rc<RefCell<Environment>>) -> Self {
-        let mut hashmap = HashMap::new();
-        Self::fill_env_with_builtins(&mut hashmap);
         Environment {
-            store: hashmap,
+            store: HashMap::new(),
             parent: Some(outer),
         }
     }
 
-    fn fill_env_with_builtins(hashmap: &mut HashMap<String, Object>) {
-        let builtins_functions = BuiltinsFunctions::new();
-        let builtins = builtins_functions.get_builtins();
-        for (Ident(name), object) in builtins {
-            hashmap.insert(name, object);
-        }
+    /// The builtin function called `name`. Builtins are found after every
+    /// binding, so a `let` can shadow them.
+    pub fn builtin(name: &str) -> Option<Object> {
+        BuiltinsFunctions::new()
+            .get_builtins()
+            .into_iter()
+            .find(|(Ident(builtin), _)| builtin == name)
+            .map(|(_, object)| object)
     }
 
     pub fn set(&mut self, name: &str, val: Object) {
         self.store.insert(name.to_string(), val);
     }
 
+    /// Rebind `name` in the innermost environment that binds it. Returns
+    /// `false` if none does.
+    pub fn assign(&mut self, name: &str, val: Object) -> bool {
+        match self.store.get_mut(name) {
+            Some(slot) => {
+                *slot = val;
+                true
+            }
+            None => match self.parent {
+                Some(ref parent_env) => parent_env.borrow_mut().assign(name, val),
+                None => false,
+            },
+        }
+    }
+
     pub fn get(&self, name: &str) -> Option<Object> {
         match self.store.get(name) {
             Some(o) => Some(o.clone()),
@@ -56,7 +69,7 @@ impl Environment {
                     let env = parent_env.borrow();
                     env.get(name)
                 }
-                None => None,
+                None => Self::builtin(name),
             },
         }
     }
@@ -2,9 +2,11 @@ pub mod builtins;
 pub mod environment;
 pub mod object;
 
+use crate::error::ArithmeticError;
 use crate::evaluator::environment::*;
 use crate::evaluator::object::*;
 use crate::parser::ast::*;
+use im_rc::HashMap;
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -28,6 +30,8 @@ impl Evaluator {
     fn returned(&mut self, object: Object) -> Object {
         match object {
             Object::ReturnValue(v) => *v,
+            Object::Break => Object::Error("break outside of a loop".to_string()),
+            Object::Continue => Object::Error("continue outside of a loop".to_string()),
             o => o,
         }
     }
@@ -44,7 +48,9 @@ impl Evaluator {
             _ => {
                 let s = prog.remove(0);
                 let object = self.eval_statement(s);
-                if object.is_returned() {
+                if object.is_returned()
+                    || matches!(object, Object::Error(_) | Object::Break | Object::Continue)
+                {
                     object
                 } else {
                     self.eval_blockstmt(prog)
@@ -54,13 +60,25 @@ impl Evaluator {
     }
 
     pub fn eval_statement(&mut self, stmt: Stmt) -> Object {
-        match stmt {
-            Stmt::ExprStmt(expr) => self.eval_expr(expr),
-            Stmt::ReturnStmt(expr) => Object::ReturnValue(Box::new(self.eval_expr(expr))),
-            Stmt::LetStmt(ident, expr) => {
+        match stmt.kind {
+            // an `if` statement passes on the `break` or `continue` it ran into
+            StmtKind::ExprStmt(Expr {
+                kind:
+                    ExprKind::IfExpr {
+                        cond,
+                        consequence,
+                        alternative,
+                    },
+                ..
+            }) => self.eval_if(*cond, consequence, alternative),
+            StmtKind::ExprStmt(expr) => self.eval_expr(expr),
+            StmtKind::ReturnStmt(expr) => Object::ReturnValue(Box::new(self.eval_expr(expr))),
+            StmtKind::LetStmt(ident, expr) => {
                 let object = self.eval_expr(expr);
                 self.register_ident(ident, object)
             }
+            StmtKind::BreakStmt => Object::Break,
+            StmtKind::ContinueStmt => Object::Continue,
         }
     }
 
@@ -71,24 +89,43 @@ impl Evaluator {
     }
 
     pub fn eval_expr(&mut self, expr: Expr) -> Object {
-        match expr {
-            Expr::IdentExpr(i) => self.eval_ident(i),
-            Expr::LitExpr(l) => self.eval_literal(l),
-            Expr::PrefixExpr(prefix, expr) => self.eval_prefix(&prefix, *expr),
-            Expr::InfixExpr(infix, expr1, expr2) => self.eval_infix(&infix, *expr1, *expr2),
-            Expr::IfExpr {
+        match expr.kind {
+            ExprKind::IdentExpr(i) => self.eval_ident(i),
+            ExprKind::LitExpr(l) => self.eval_literal(l),
+            ExprKind::PrefixExpr(prefix, expr) => self.eval_prefix(&prefix, *expr),
+            ExprKind::InfixExpr(infix, expr1, expr2) => self.eval_infix(&infix, *expr1, *expr2),
+            ExprKind::IfExpr {
                 cond,
                 consequence,
                 alternative,
-            } => self.eval_if(*cond, consequence, alternative),
-            Expr::FnExpr { params, body } => self.eval_fn(params, body),
-            Expr::CallExpr {
+            } => match self.eval_if(*cond, consequence, alternative) {
+                Object::Break => {
+                    Object::Error("break cannot be used inside an expression".to_string())
+                }
+                Object::Continue => {
+                    Object::Error("continue cannot be used inside an expression".to_string())
+                }
+                object => object,
+            },
+            ExprKind::WhileExpr { cond, body } => self.eval_while(*cond, body),
+            ExprKind::ForExpr {
+                ident,
+                iterable,
+                body,
+            } => self.eval_for(ident, *iterable, body),
+            ExprKind::FnExpr { params, body } => self.eval_fn(params, body),
+            ExprKind::CallExpr {
                 function: fn_exp,
                 arguments,
             } => self.eval_call(*fn_exp, arguments),
-            Expr::ArrayExpr(exprs) => self.eval_array(exprs),
-            Expr::HashExpr(hash_exprs) => self.eval_hash(hash_exprs),
-            Expr::IndexExpr { array, index } => self.eval_index(*array, *index),
+            ExprKind::ArrayExpr(exprs) => self.eval_array(exprs),
+            ExprKind::HashExpr(hash_exprs) => self.eval_hash(hash_exprs),
+            ExprKind::IndexExpr { array, index } => self.eval_index(*array, *index),
+            ExprKind::AssignExpr {
+                target,
+                operator,
+                value,
+            } => self.eval_assign(*target, operator, *value),
         }
     }
 
@@ -105,8 +142,10 @@ impl Evaluator {
     pub fn eval_literal(&mut self, literal: Literal) -> Object {
         match literal {
             Literal::IntLiteral(i) => Object::Integer(i),
+            Literal::BigIntLiteral(i) => Object::from(i),
+            Literal::FloatLiteral(x) => Object::Float(x),
             Literal::BoolLiteral(b) => Object::Boolean(b),
-            Literal::StringLiteral(s) => Object::String(s),
+            Literal::StringLiteral(s) => Object::String(s.into()),
         }
     }
 
@@ -117,80 +156,102 @@ impl Evaluator {
                 Ok(b) => Object::Boolean(!b),
                 Err(err) => err,
             },
-            Prefix::PrefixPlus => match self.oti(object) {
-                Ok(i) => Object::Integer(i),
-                Err(err) => err,
+            Prefix::PrefixPlus => match object {
+                Object::Integer(_) | Object::BigInt(_) | Object::Float(_) | Object::Error(_) => {
+                    object
+                }
+                o => Object::Error(format!("{} is not a number", o)),
             },
-            Prefix::PrefixMinus => match self.oti(object) {
-                Ok(i) => Object::Integer(-i),
-                Err(err) => err,
+            Prefix::BitNot => match object.bit_not() {
+                Some(inverted) => inverted,
+                None => match object {
+                    Object::Error(_) => object,
+                    o => Object::Error(format!("{} is not an integer", o)),
+                },
+            },
+            Prefix::PrefixMinus => match object.negated() {
+                Some(negated) => negated,
+                None => match object {
+                    Object::Error(_) => object,
+                    o => Object::Error(format!("{} is not a number", o)),
+                },
             },
         }
     }
 
     pub fn eval_infix(&mut self, infix: &Infix, expr1: Expr, expr2: Expr) -> Object {
+        if let Infix::And | Infix::Or = infix {
+            return self.eval_logical(infix, expr1, expr2);
+        }
         let object1 = self.eval_expr(expr1);
         let object2 = self.eval_expr(expr2);
+        match (object1, object2) {
+            (Object::Error(s), _) | (_, Object::Error(s)) => Object::Error(s),
+            (object1, object2) => self.eval_operator(infix, object1, object2),
+        }
+    }
+
+    /// `&&` and `||` only evaluate their right operand when the left one
+    /// doesn't already decide the result
+    fn eval_logical(&mut self, infix: &Infix, expr1: Expr, expr2: Expr) -> Object {
+        let object = self.eval_expr(expr1);
+        match (infix, self.otb(object)) {
+            (_, Err(err)) => err,
+            (Infix::And, Ok(false)) => Object::Boolean(false),
+            (Infix::Or, Ok(true)) => Object::Boolean(true),
+            _ => {
+                let object = self.eval_expr(expr2);
+                self.otb(object).map_or_else(|err| err, Object::Boolean)
+            }
+        }
+    }
+
+    fn eval_operator(&mut self, infix: &Infix, object1: Object, object2: Object) -> Object {
         match *infix {
             Infix::Plus => self.object_add(object1, object2),
-            Infix::Minus => {
-                let i1 = self.oti(object1);
-                let i2 = self.oti(object2);
-                match (i1, i2) {
-                    (Ok(i1), Ok(i2)) => Object::Integer(i1 - i2),
-                    (Err(err), _) | (_, Err(err)) => err,
-                }
-            }
-            Infix::Divide => {
-                let i1 = self.oti(object1);
-                let i2 = self.oti(object2);
-                match (i1, i2) {
-                    (Ok(i1), Ok(i2)) => Object::Integer(i1 / i2),
-                    (Err(err), _) | (_, Err(err)) => err,
-                }
-            }
-            Infix::Multiply => {
-                let i1 = self.oti(object1);
-                let i2 = self.oti(object2);
-                match (i1, i2) {
-                    (Ok(i1), Ok(i2)) => Object::Integer(i1 * i2),
-                    (Err(err), _) | (_, Err(err)) => err,
-                }
-            }
-            Infix::Equal => Object::Boolean(object1 == object2),
-            Infix::NotEqual => Object::Boolean(object1 != object2),
-            Infix::GreaterThanEqual => {
-                let i1 = self.oti(object1);
-                let i2 = self.oti(object2);
-                match (i1, i2) {
-                    (Ok(i1), Ok(i2)) => Object::Boolean(i1 >= i2),
-                    (Err(err), _) | (_, Err(err)) => err,
-                }
-            }
-            Infix::GreaterThan => {
-                let i1 = self.oti(object1);
-                let i2 = self.oti(object2);
-                match (i1, i2) {
-                    (Ok(i1), Ok(i2)) => Object::Boolean(i1 > i2),
-                    (Err(err), _) | (_, Err(err)) => err,
-                }
-            }
-            Infix::LessThanEqual => {
-                let i1 = self.oti(object1);
-                let i2 = self.oti(object2);
-                match (i1, i2) {
-                    (Ok(i1), Ok(i2)) => Object::Boolean(i1 <= i2),
-                    (Err(err), _) | (_, Err(err)) => err,
-                }
-            }
-            Infix::LessThan => {
-                let i1 = self.oti(object1);
-                let i2 = self.oti(object2);
-                match (i1, i2) {
-                    (Ok(i1), Ok(i2)) => Object::Boolean(i1 < i2),
-                    (Err(err), _) | (_, Err(err)) => err,
-                }
-            }
+            Infix::Equal => Object::Boolean(Numbers::equal(&object1, &object2)),
+            Infix::NotEqual => Object::Boolean(!Numbers::equal(&object1, &object2)),
+            _ => match self.otn(object1, object2) {
+                Ok(numbers) => self.eval_numbers(infix, numbers),
+                Err(err) => err,
+            },
+        }
+    }
+
+    fn eval_numbers(&mut self, infix: &Infix, numbers: Numbers) -> Object {
+        let ordering = numbers.compare();
+        match *infix {
+            Infix::Minus => numbers.sub(),
+            Infix::Multiply => numbers.mul(),
+            Infix::Divide => self.arithmetic(numbers.div()),
+            Infix::Modulo => self.arithmetic(numbers.rem()),
+            Infix::Power => self.arithmetic(numbers.pow()),
+            Infix::BitAnd => self.bitwise(infix, numbers.bit_and().map(Ok)),
+            Infix::BitOr => self.bitwise(infix, numbers.bit_or().map(Ok)),
+            Infix::BitXor => self.bitwise(infix, numbers.bit_xor().map(Ok)),
+            Infix::ShiftLeft => self.bitwise(infix, numbers.shift_left()),
+            Infix::ShiftRight => self.bitwise(infix, numbers.shift_right()),
+            Infix::GreaterThanEqual => Object::Boolean(ordering.is_some_and(|o| o.is_ge())),
+            Infix::GreaterThan => Object::Boolean(ordering.is_some_and(|o| o.is_gt())),
+            Infix::LessThanEqual => Object::Boolean(ordering.is_some_and(|o| o.is_le())),
+            Infix::LessThan => Object::Boolean(ordering.is_some_and(|o| o.is_lt())),
+            _ => unreachable!("{:?} is not a numeric operator", infix),
+        }
+    }
+
+    fn arithmetic(&mut self, result: Result<Object, ArithmeticError>) -> Object {
+        result.unwrap_or_else(|err| Object::Error(err.to_string()))
+    }
+
+    /// `result` is `None` when the operands weren't both integers
+    fn bitwise(
+        &mut self,
+        infix: &Infix,
+        result: Option<Result<Object, ArithmeticError>>,
+    ) -> Object {
+        match result {
+            Some(result) => self.arithmetic(result),
+            None => Object::Error(format!("{} needs integer operands", infix)),
         }
     }
 
@@ -211,6 +272,47 @@ impl Evaluator {
         }
     }
 
+    pub fn eval_while(&mut self, cond: Expr, body: Program) -> Object {
+        loop {
+            let object = self.eval_expr(cond.clone());
+            match self.otb(object) {
+                Ok(true) => {}
+                Ok(false) => return Object::Null,
+                Err(err) => return err,
+            }
+            if let Some(object) = self.eval_iteration(&body) {
+                return object;
+            }
+        }
+    }
+
+    pub fn eval_for(&mut self, ident: Ident, iterable: Expr, body: Program) -> Object {
+        let object = self.eval_expr(iterable);
+        let items = match object.iterated() {
+            Some(items) => items,
+            None => match object {
+                Object::Error(_) => return object,
+                o => return Object::Error(format!("{} is not iterable", o)),
+            },
+        };
+        for item in items {
+            self.env.borrow_mut().set(&ident.0, item);
+            if let Some(object) = self.eval_iteration(&body) {
+                return object;
+            }
+        }
+        Object::Null
+    }
+
+    /// Run the body of a loop once, giving the value of the loop if it ends
+    fn eval_iteration(&mut self, body: &Program) -> Option<Object> {
+        match self.eval_blockstmt(body.clone()) {
+            Object::Break => Some(Object::Null),
+            object if object.is_returned() || matches!(object, Object::Error(_)) => Some(object),
+            _ => None,
+        }
+    }
+
     pub fn eval_fn(&mut self, params: Vec<Ident>, body: Program) -> Object {
         Object::Function(params, body, Rc::clone(&self.env))
     }
@@ -243,14 +345,14 @@ impl Evaluator {
                 args_expr.len()
             ))
         } else {
-            let args = args_expr
-                .into_iter()
-                .map(|e| self.eval_expr(e))
-                .collect::<Vec<_>>();
+            let args = match self.eval_exprs(args_expr) {
+                Ok(args) => args,
+                Err(err) => return err,
+            };
             let old_env = Rc::clone(&self.env);
             let mut new_env = Environment::new_with_outer(Rc::clone(f_env));
             let zipped = params.into_iter().zip(args);
-            for (_, (Ident(name), o)) in zipped.enumerate() {
+            for (Ident(name), o) in zipped {
                 new_env.set(&name, o);
             }
             self.env = Rc::new(RefCell::new(new_env));
@@ -273,62 +375,185 @@ impl Evaluator {
                 args_expr.len()
             ))
         } else {
-            let args = args_expr
-                .into_iter()
-                .map(|e| self.eval_expr(e))
-                .collect::<Vec<_>>();
-            b_fn(args).unwrap_or_else(Object::Error)
+            match self.eval_exprs(args_expr) {
+                Ok(args) => b_fn(args).unwrap_or_else(Object::Error),
+                Err(err) => err,
+            }
         }
     }
 
     pub fn eval_array(&mut self, exprs: Vec<Expr>) -> Object {
-        let new_vec = exprs.into_iter().map(|e| self.eval_expr(e)).collect();
-        Object::Array(new_vec)
+        match self.eval_exprs(exprs) {
+            Ok(new_vec) => Object::Array(new_vec.into()),
+            Err(err) => err,
+        }
+    }
+
+    /// Evaluate `exprs` in order, stopping at the first error
+    fn eval_exprs(&mut self, exprs: Vec<Expr>) -> Result<Vec<Object>, Object> {
+        exprs
+            .into_iter()
+            .map(|e| match self.eval_expr(e) {
+                Object::Error(s) => Err(Object::Error(s)),
+                o => Ok(o),
+            })
+            .collect()
     }
 
     pub fn object_add(&mut self, object1: Object, object2: Object) -> Object {
         match (object1, object2) {
-            (Object::Integer(i1), Object::Integer(i2)) => Object::Integer(i1 + i2),
-            (Object::String(s1), Object::String(s2)) => Object::String(s1 + &s2),
+            (Object::String(s1), Object::String(s2)) => {
+                Object::String(format!("{}{}", s1, s2).into())
+            }
             (Object::Error(s), _) | (_, Object::Error(s)) => Object::Error(s),
-            (x, y) => Object::Error(format!("{:?} and {:?} are not addable", x, y)),
+            (x, y) => match Numbers::new(&x, &y) {
+                Some(numbers) => numbers.add(),
+                None => Object::Error(format!("{:?} and {:?} are not addable", x, y)),
+            },
         }
     }
 
-    pub fn eval_hash(&mut self, hs: Vec<(Literal, Expr)>) -> Object {
-        let hashmap = hs.into_iter().map(|pair| self.eval_pair(pair)).collect();
+    #[allow(clippy::mutable_key_type)]
+    pub fn eval_hash(&mut self, hs: Vec<(Expr, Expr)>) -> Object {
+        // like the VM, evaluate every key and value before checking the keys
+        let exprs = hs.into_iter().flat_map(|(k, e)| [k, e]).collect();
+        let mut objects = match self.eval_exprs(exprs) {
+            Ok(objects) => objects.into_iter(),
+            Err(err) => return err,
+        };
+        let mut hashmap = HashMap::new();
+        while let (Some(key), Some(object)) = (objects.next(), objects.next()) {
+            match self.oth(key) {
+                Object::Error(s) => return Object::Error(s),
+                hash => hashmap.insert(hash, object),
+            };
+        }
         Object::Hash(hashmap)
     }
 
-    fn eval_pair(&mut self, tuple: (Literal, Expr)) -> (Object, Object) {
-        let (l, e) = tuple;
-        let hash = self.l2h(l);
-        let object = self.eval_expr(e);
-        (hash, object)
-    }
-
     pub fn eval_index(&mut self, target_exp: Expr, id_exp: Expr) -> Object {
         let target = self.eval_expr(target_exp);
         let index = self.eval_expr(id_exp);
+        self.index(target, index)
+    }
+
+    fn index(&mut self, target: Object, index: Object) -> Object {
         match target {
             Object::Array(arr) => match self.oti(index) {
                 Ok(index_number) => arr
-                    .into_iter()
-                    .nth(index_number as usize)
+                    .get(index_number as usize)
+                    .cloned()
                     .unwrap_or(Object::Null),
                 Err(err) => err,
             },
-            Object::Hash(mut hash) => {
+            Object::Hash(hash) => {
                 let name = self.oth(index);
                 match name {
                     Object::Error(_) => name,
-                    _ => hash.remove(&name).unwrap_or(Object::Null),
+                    _ => hash.get(&name).cloned().unwrap_or(Object::Null),
                 }
             }
             o => Object::Error(format!("unexpected index target: {}", o)),
         }
     }
 
+    /// Assigning to an element rebinds the variable to a copy of its array or
+    /// hash with the element replaced. The value is the one assigned.
+    pub fn eval_assign(&mut self, target: Expr, operator: Option<Infix>, value: Expr) -> Object {
+        let (name, path) = match target.assignment_path() {
+            Some((Ident(name), path)) => {
+                (name.clone(), path.into_iter().cloned().collect::<Vec<_>>())
+            }
+            None => return Object::Error(format!("cannot assign to {}", target)),
+        };
+
+        // the variable is only read when an element or the old value is needed
+        let root = match path.is_empty() && operator.is_none() {
+            true => Object::Null,
+            false => self.eval_ident(Ident(name.clone())),
+        };
+        if let Object::Error(_) = root {
+            return root;
+        }
+        let keys = match self.eval_exprs(path) {
+            Ok(keys) => keys,
+            Err(err) => return err,
+        };
+
+        let object = match operator {
+            Some(op) => {
+                let mut old = root.clone();
+                for key in &keys {
+                    old = self.index(old, key.clone());
+                }
+                let object = self.eval_expr(value);
+                match (old, object) {
+                    (Object::Error(s), _) | (_, Object::Error(s)) => Object::Error(s),
+                    (old, object) => self.eval_operator(&op, old, object),
+                }
+            }
+            None => self.eval_expr(value),
+        };
+        if let Object::Error(_) = object {
+            return object;
+        }
+
+        let updated = self.with_element(root, &keys, object.clone());
+        if let Object::Error(_) = updated {
+            return updated;
+        }
+        let assigned = self.env.borrow_mut().assign(&name, updated);
+        match assigned {
+            true => object,
+            false if Environment::builtin(&name).is_some() => {
+                Object::Error(format!("cannot assign to builtin {}", name))
+            }
+            false => Object::Error(format!("identifier not found: {}", name)),
+        }
+    }
+
+    /// `target` with the element at `keys` replaced by `object`
+    fn with_element(&mut self, target: Object, keys: &[Object], object: Object) -> Object {
+        let (key, rest) = match keys.split_first() {
+            Some(split) => split,
+            None => return object,
+        };
+        match target {
+            Object::Array(mut arr) => match self.oti(key.clone()) {
+                Ok(i) if 0 <= i && (i as usize) < arr.len() => {
+                    let element = std::mem::replace(&mut arr[i as usize], Object::Null);
+                    match self.with_element(element, rest, object) {
+                        Object::Error(s) => Object::Error(s),
+                        element => {
+                            arr[i as usize] = element;
+                            Object::Array(arr)
+                        }
+                    }
+                }
+                Ok(i) => Object::Error(format!(
+                    "index out of bounds: {} for array of length {}",
+                    i,
+                    arr.len()
+                )),
+                Err(err) => err,
+            },
+            Object::Hash(mut hash) => match self.oth(key.clone()) {
+                Object::Error(s) => Object::Error(s),
+                key => {
+                    let element = hash.remove(&key).unwrap_or(Object::Null);
+                    match self.with_element(element, rest, object) {
+                        Object::Error(s) => Object::Error(s),
+                        element => {
+                            hash.insert(key, element);
+                            Object::Hash(hash)
+                        }
+                    }
+                }
+            },
+            o => Object::Error(format!("unexpected index target: {}", o)),
+        }
+    }
+
     pub fn otb(&mut self, object: Object) -> Result<bool, Object> {
         match object {
             Object::Boolean(b) => Ok(b),
@@ -345,6 +570,19 @@ impl Evaluator {
         }
     }
 
+    pub fn otn(&mut self, object1: Object, object2: Object) -> Result<Numbers, Object> {
+        match Numbers::new(&object1, &object2) {
+            Some(numbers) => Ok(numbers),
+            None => match (object1, object2) {
+                (Object::Error(s), _) | (_, Object::Error(s)) => Err(Object::Error(s)),
+                (Object::Integer(_), o)
+                | (Object::BigInt(_), o)
+                | (Object::Float(_), o)
+                | (o, _) => Err(Object::Error(format!("{} is not a number", o))),
+            },
+        }
+    }
+
     pub fn otf(&mut self, object: Object) -> Object {
         match object {
             Object::Function(_, _, _) | Object::Builtin(_, _, _) => object,
@@ -356,17 +594,13 @@ impl Evaluator {
     pub fn oth(&mut self, object: Object) -> Object {
         match object {
             Object::Integer(i) => Object::Integer(i),
+            Object::BigInt(i) => Object::BigInt(i),
             Object::Boolean(b) => Object::Boolean(b),
             Object::String(s) => Object::String(s),
             Object::Error(s) => Object::Error(s),
             x => Object::Error(format!("{} is not hashable", x)),
         }
     }
-
-    pub fn l2h(&mut self, literal: Literal) -> Object {
-        let object = self.eval_literal(literal);
-        self.oth(object)
-    }
 }
 
 #[cfg(test)]
@@ -375,11 +609,12 @@ mod tests {
     use crate::lexer::token::*;
     use crate::lexer::*;
     use crate::parser::*;
+    use im_rc::vector;
 
     fn compare(input: &[u8], object: Object) {
         let (_, r) = Lexer::lex_tokens(input).unwrap();
         let tokens = Tokens::new(&r);
-        let (_, result_parse) = Parser::parse_tokens(tokens).unwrap();
+        let result_parse = Parser::parse_tokens(tokens).unwrap();
         let mut evaluator = Evaluator::new();
         let eval = evaluator.eval_program(result_parse);
         assert_eq!(eval, object);
@@ -429,11 +664,11 @@ mod tests {
         compare("+20".as_bytes(), Object::Integer(20));
         compare(
             "+true".as_bytes(),
-            Object::Error("true is not an integer".to_string()),
+            Object::Error("true is not a number".to_string()),
         );
         compare(
             "+false".as_bytes(),
-            Object::Error("false is not an integer".to_string()),
+            Object::Error("false is not a number".to_string()),
         );
         // the prefix -
         compare("-1".as_bytes(), Object::Integer(-1));
@@ -441,11 +676,191 @@ mod tests {
         compare("-20".as_bytes(), Object::Integer(-20));
         compare(
             "-true".as_bytes(),
-            Object::Error("true is not an integer".to_string()),
+            Object::Error("true is not a number".to_string()),
         );
         compare(
             "-false".as_bytes(),
-            Object::Error("false is not an integer".to_string()),
+            Object::Error("false is not a number".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        compare(
+            "1 / 0".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
+        compare(
+            "9223372036854775808 / 0".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
+        compare("1.0 / 0".as_bytes(), Object::Float(f64::INFINITY));
+        // errors stop evaluation instead of being passed around as values
+        compare(
+            "let x = 1 / 0; 5".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
+        compare(
+            "len([1 / 0])".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
+        compare(
+            "fn(x) { 1 }(1 / 0)".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
+        compare(
+            "1 == 1 / 0".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
+        compare(
+            "{\"a\": 1 / 0}".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
+    }
+
+    fn big(digits: &str) -> Object {
+        Object::BigInt(digits.parse().unwrap())
+    }
+
+    #[test]
+    fn test_big_integers() {
+        compare("9223372036854775808".as_bytes(), big("9223372036854775808"));
+        compare("-9223372036854775808".as_bytes(), Object::Integer(i64::MIN));
+        compare(
+            "9223372036854775807 + 1".as_bytes(),
+            big("9223372036854775808"),
+        );
+        compare(
+            "-9223372036854775807 - 2".as_bytes(),
+            big("-9223372036854775809"),
+        );
+        compare(
+            "4611686018427387904 * 4".as_bytes(),
+            big("18446744073709551616"),
+        );
+        compare(
+            "-(-9223372036854775807 - 1)".as_bytes(),
+            big("9223372036854775808"),
+        );
+        compare(
+            "(-9223372036854775807 - 1) / -1".as_bytes(),
+            big("9223372036854775808"),
+        );
+        // results that fit again are plain integers
+        compare(
+            "9223372036854775808 - 1".as_bytes(),
+            Object::Integer(i64::MAX),
+        );
+        compare(
+            "100000000000000000000 / 100000000000000000000".as_bytes(),
+            Object::Integer(1),
+        );
+        compare(
+            "9223372036854775808 == 9223372036854775807 + 1".as_bytes(),
+            Object::Boolean(true),
+        );
+        compare(
+            "9223372036854775808 > 9223372036854775807".as_bytes(),
+            Object::Boolean(true),
+        );
+        compare(
+            "9223372036854775808 + 0.5".as_bytes(),
+            Object::Float(9223372036854775808.5),
+        );
+        compare(
+            "{9223372036854775808: 1}[9223372036854775807 + 1]".as_bytes(),
+            Object::Integer(1),
+        );
+        compare("int(1e20)".as_bytes(), big("100000000000000000000"));
+        compare(
+            "int(\"-100000000000000000000\")".as_bytes(),
+            big("-100000000000000000000"),
+        );
+    }
+
+    #[test]
+    fn test_modulo_power_and_bitwise() {
+        compare("7 % 3".as_bytes(), Object::Integer(1));
+        compare("-7 % 3".as_bytes(), Object::Integer(-1));
+        compare("7.5 % 2".as_bytes(), Object::Float(1.5));
+        compare("2 ** 3 ** 2".as_bytes(), Object::Integer(512));
+        compare("-2 ** 2".as_bytes(), Object::Integer(-4));
+        compare("(-2) ** 3".as_bytes(), Object::Integer(-8));
+        compare("2 ** -1".as_bytes(), Object::Float(0.5));
+        compare("4.0 ** 0.5".as_bytes(), Object::Float(2.0));
+        compare("2 ** 64".as_bytes(), big("18446744073709551616"));
+        compare("(-1) ** 100000000001".as_bytes(), Object::Integer(-1));
+        compare("6 & 3".as_bytes(), Object::Integer(2));
+        compare("6 | 3".as_bytes(), Object::Integer(7));
+        compare("6 ^ 3".as_bytes(), Object::Integer(5));
+        compare("~5".as_bytes(), Object::Integer(-6));
+        compare("1 << 64".as_bytes(), big("18446744073709551616"));
+        compare("18446744073709551616 >> 63".as_bytes(), Object::Integer(2));
+        compare("-1 >> 100".as_bytes(), Object::Integer(-1));
+        compare("1 & 3 == 1".as_bytes(), Object::Boolean(true));
+        compare(
+            "5 % 0".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
+        compare(
+            "1 << -1".as_bytes(),
+            Object::Error("negative shift count".to_string()),
+        );
+        compare(
+            "2 ** 100000000".as_bytes(),
+            Object::Error("result too large".to_string()),
+        );
+        compare(
+            "1.5 & 1".as_bytes(),
+            Object::Error("& needs integer operands".to_string()),
+        );
+        compare(
+            "~1.5".as_bytes(),
+            Object::Error("1.5 is not an integer".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        compare("true && true".as_bytes(), Object::Boolean(true));
+        compare("true && false".as_bytes(), Object::Boolean(false));
+        compare("false || true".as_bytes(), Object::Boolean(true));
+        compare("false || false".as_bytes(), Object::Boolean(false));
+        compare("1 < 2 && 2 < 3 || false".as_bytes(), Object::Boolean(true));
+        // the right operand isn't evaluated when the left one decides
+        compare("false && 1 / 0 == 1".as_bytes(), Object::Boolean(false));
+        compare("true || missing".as_bytes(), Object::Boolean(true));
+        compare(
+            "true && 1 / 0 == 1".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
+        compare(
+            "true && 1".as_bytes(),
+            Object::Error("1 is not a bool".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_float() {
+        compare("2.5".as_bytes(), Object::Float(2.5));
+        compare("-2.5".as_bytes(), Object::Float(-2.5));
+        compare("1 + 0.5".as_bytes(), Object::Float(1.5));
+        compare("0.5 + 1".as_bytes(), Object::Float(1.5));
+        compare("3.0 - 1".as_bytes(), Object::Float(2.0));
+        compare("1.5 * 2".as_bytes(), Object::Float(3.0));
+        compare("1 / 2.0".as_bytes(), Object::Float(0.5));
+        compare("1 == 1.0".as_bytes(), Object::Boolean(true));
+        compare("2 > 1.5".as_bytes(), Object::Boolean(true));
+        compare("1.5 <= 1".as_bytes(), Object::Boolean(false));
+        compare("float(1) / 4".as_bytes(), Object::Float(0.25));
+        compare("int(7.9)".as_bytes(), Object::Integer(7));
+        compare(
+            "int(\"x\")".as_bytes(),
+            Object::Error("could not convert \"x\" to int".to_string()),
+        );
+        compare(
+            "1.5 - true".as_bytes(),
+            Object::Error("true is not a number".to_string()),
         );
     }
 
@@ -541,21 +956,18 @@ mod tests {
 
     #[test]
     fn test_strings() {
-        compare(
-            "\"foobar\"".as_bytes(),
-            Object::String("foobar".to_string()),
-        );
+        compare("\"foobar\"".as_bytes(), Object::String("foobar".into()));
         compare(
             "\"foo\" + \"bar\"".as_bytes(),
-            Object::String("foobar".to_string()),
+            Object::String("foobar".into()),
         );
         compare(
             "\"foo\" + \" \" + \"bar\"".as_bytes(),
-            Object::String("foo bar".to_string()),
+            Object::String("foo bar".into()),
         );
         compare(
             "\"foo\" - \"bar\"".as_bytes(),
-            Object::Error("foo is not an integer".to_string()),
+            Object::Error("foo is not a number".to_string()),
         );
     }
 
@@ -655,11 +1067,135 @@ mod tests {
         compare(fn_input7, Object::Integer(4));
     }
 
+    #[test]
+    fn test_assignment() {
+        compare("let x = 1; x = 2; x".as_bytes(), Object::Integer(2));
+        compare(
+            "let x = 1; x += 2; x *= 3; x".as_bytes(),
+            Object::Integer(9),
+        );
+        compare(
+            "let x = 1; let y = x = 3; x + y".as_bytes(),
+            Object::Integer(6),
+        );
+        compare("fn(x) { x += 1; x }(1)".as_bytes(), Object::Integer(2));
+        compare(
+            "let a = [[1, 2], [3]]; a[0][1] *= 10; a[1][0] = 4; a".as_bytes(),
+            Object::Array(vector![
+                Object::Array(vector![Object::Integer(1), Object::Integer(20)]),
+                Object::Array(vector![Object::Integer(4)]),
+            ]),
+        );
+        compare(
+            "let h = {\"a\": 1}; h[\"b\"] = 2; h[\"a\"] -= 5; h[\"a\"] + h[\"b\"]".as_bytes(),
+            Object::Integer(-2),
+        );
+        compare(
+            "let a = [1]; let b = a; a[0] = 2; b[0]".as_bytes(),
+            Object::Integer(1),
+        );
+        compare(
+            "let counter = fn() { let n = 0; fn() { n += 1; n } };
+             let c = counter(); c(); c(); c()"
+                .as_bytes(),
+            Object::Integer(3),
+        );
+        compare(
+            "let f = fn(v) { let x = v; fn() { x } }; let a = f(1); let b = f(2); a() * 10 + b()"
+                .as_bytes(),
+            Object::Integer(12),
+        );
+        compare(
+            "let len = fn(x) { 0 }; len([1])".as_bytes(),
+            Object::Integer(0),
+        );
+        compare(
+            "len = 1".as_bytes(),
+            Object::Error("cannot assign to builtin len".to_string()),
+        );
+        compare(
+            "x = 1".as_bytes(),
+            Object::Error("identifier not found: x".to_string()),
+        );
+        compare(
+            "let a = [1]; a[3] = 1".as_bytes(),
+            Object::Error("index out of bounds: 3 for array of length 1".to_string()),
+        );
+        compare(
+            "let x = 1; x[0] = 2".as_bytes(),
+            Object::Error("unexpected index target: 1".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_loops() {
+        compare(
+            "let i = 0; let s = 0; while (i < 10) { i += 1; if (i % 2 == 0) { continue; } s += i; } s"
+                .as_bytes(),
+            Object::Integer(25),
+        );
+        compare(
+            "let s = 0; for (x in [1, 2, 3, 4]) { if (x == 3) { break } s += x } s".as_bytes(),
+            Object::Integer(3),
+        );
+        compare(
+            "let r = \"\"; for (c in \"abc\") { r = c + r } r".as_bytes(),
+            Object::String("cba".into()),
+        );
+        compare(
+            "let r = []; for (k in {\"b\": 1, 2: 0, true: 0, \"a\": 2, 10: 0}) { r = push(k, r) } r"
+                .as_bytes(),
+            Object::Array(vector![
+                Object::Integer(2),
+                Object::Integer(10),
+                Object::Boolean(true),
+                Object::String("a".into()),
+                Object::String("b".into()),
+            ]),
+        );
+        compare(
+            "let s = 0; for (x in [[1, 2], [3]]) { for (y in x) { if (y == 2) { break } s += y } } s"
+                .as_bytes(),
+            Object::Integer(4),
+        );
+        compare(
+            "let f = fn() { while (true) { return 5 } }; f()".as_bytes(),
+            Object::Integer(5),
+        );
+        compare("while (false) { 1 }".as_bytes(), Object::Null);
+        compare(
+            "for (x in 5) { x }".as_bytes(),
+            Object::Error("5 is not iterable".to_string()),
+        );
+        compare(
+            "break".as_bytes(),
+            Object::Error("break outside of a loop".to_string()),
+        );
+        compare(
+            "for (x in [1]) { fn() { continue }() }".as_bytes(),
+            Object::Error("continue outside of a loop".to_string()),
+        );
+        compare(
+            "let r = []; for (x in [1, 2, 3]) { r = push(if (x == 2) { continue } else { x }, r) }"
+                .as_bytes(),
+            Object::Error("continue cannot be used inside an expression".to_string()),
+        );
+        compare(
+            "while (true) { 1 + if (true) { break } else { 2 } }".as_bytes(),
+            Object::Error("break cannot be used inside an expression".to_string()),
+        );
+        compare(
+            "let r = 0; for (x in [1, 2, 3]) { if (x == 2) { if (true) { continue } } r += x }; r"
+                .as_bytes(),
+            Object::Integer(4),
+        );
+    }
+
     #[test]
     fn test_array() {
         compare(
             "[1, 2, 3, 4]".as_bytes(),
-            Object::Array(vec![
+            Object::Array(vector![
                 Object::Integer(1),
                 Object::Integer(2),
                 Object::Integer(3),
@@ -669,7 +1205,7 @@ mod tests {
 
         compare(
             "let double = fn(x) { x * 2 };[1, double(2), 3 * 3, 4 - 3]".as_bytes(),
-            Object::Array(vec![
+            Object::Array(vector![
                 Object::Integer(1),
                 Object::Integer(4),
                 Object::Integer(9),
@@ -723,10 +1259,7 @@ mod tests {
             (input_beg.clone() + "let s = \"two\"; h[s]").as_bytes(),
             Object::Integer(2),
         );
-        compare(
-            (input_beg.clone() + "h[3]").as_bytes(),
-            Object::Integer(3),
-        );
+        compare((input_beg.clone() + "h[3]").as_bytes(), Object::Integer(3));
         compare(
             (input_beg.clone() + "h[2 + 2]").as_bytes(),
             Object::Integer(4),
@@ -739,10 +1272,7 @@ mod tests {
             (input_beg.clone() + "h[5 < 1]").as_bytes(),
             Object::Boolean(false),
         );
-        compare(
-            (input_beg.clone() + "h[100]").as_bytes(),
-            Object::Null,
-        );
+        compare((input_beg.clone() + "h[100]").as_bytes(), Object::Null);
         compare(
             (input_beg.clone() + "h[[]]").as_bytes(),
             Object::Error("[] is not hashable".to_string()),
@@ -751,6 +1281,21 @@ mod tests {
             (input_beg + "3[true];").as_bytes(),
             Object::Error("unexpected index target: 3".to_string()),
         );
+
+        compare(
+            "let name = \"n\"; let f = fn(x) { x * 2 }; let h = {name: 1, f(2): 3, -1: 4}; [h[\"n\"], h[4], h[-1]]"
+                .as_bytes(),
+            Object::Array(vector![Object::Integer(1), Object::Integer(3), Object::Integer(4)]),
+        );
+        compare(
+            "{[1]: 2}".as_bytes(),
+            Object::Error("[1] is not hashable".to_string()),
+        );
+        // all of the pairs are evaluated before the keys are checked
+        compare(
+            "{[1]: 2, 3: 1 / 0}".as_bytes(),
+            Object::Error("division by zero".to_string()),
+        );
     }
 
     #[test]
@@ -780,10 +1325,10 @@ mod tests {
             Object::Error("empty array".to_string()),
         );
         // tail
-        compare("tail([1])".as_bytes(), Object::Array(vec![]));
+        compare("tail([1])".as_bytes(), Object::Array(vector![]));
         compare(
             "tail([1, 2, 3, 4])".as_bytes(),
-            Object::Array(vec![
+            Object::Array(vector![
                 Object::Integer(2),
                 Object::Integer(3),
                 Object::Integer(4),
@@ -796,17 +1341,30 @@ mod tests {
         // cons
         compare(
             "cons(1, [])".as_bytes(),
-            Object::Array(vec![Object::Integer(1)]),
+            Object::Array(vector![Object::Integer(1)]),
         );
         compare(
             "cons(1, [2, 3, 4])".as_bytes(),
-            Object::Array(vec![
+            Object::Array(vector![
                 Object::Integer(1),
                 Object::Integer(2),
                 Object::Integer(3),
                 Object::Integer(4),
             ]),
         );
+        // push, cons and tail leave their argument unchanged
+        compare(
+            "let a = [1, 2]; let b = push(3, a); let c = tail(cons(0, a)); [a, b, c]".as_bytes(),
+            Object::Array(vector![
+                Object::Array(vector![Object::Integer(1), Object::Integer(2)]),
+                Object::Array(vector![
+                    Object::Integer(1),
+                    Object::Integer(2),
+                    Object::Integer(3),
+                ]),
+                Object::Array(vector![Object::Integer(1), Object::Integer(2)]),
+            ]),
+        );
         // map reduce
         let map_decl = "let map = fn(f, arr) {\
               if (len(arr) == 0) {\
@@ -832,7 +1390,7 @@ mod tests {
 
         compare(
             (map_decl + "let double = fn(x) { x * 2 }; map(double, [1, 2, 3, 4])").as_bytes(),
-            Object::Array(vec![
+            Object::Array(vector![
                 Object::Integer(2),
                 Object::Integer(4),
                 Object::Integer(6),
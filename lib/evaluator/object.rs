@@ -1,30 +1,57 @@
-use crate::code::Instructions;
+use crate::code::SourceMap;
+use crate::error::ArithmeticError;
 use crate::evaluator::environment::*;
 use crate::parser::ast::*;
+use im_rc::{HashMap, Vector};
+use num_bigint::BigInt;
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cmp::Ordering;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
+#[allow(unpredictable_function_pointer_comparisons)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Object {
     Integer(i64),
+    /// An integer outside the range of `i64`
+    BigInt(BigInt),
+    Float(f64),
     Boolean(bool),
-    String(String),
-    Array(Vec<Object>),
+    String(Rc<str>),
+    Array(Vector<Object>),
     Hash(HashMap<Object, Object>),
     Function(Vec<Ident>, Program, Rc<RefCell<Environment>>),
     Builtin(String, usize, BuiltinFunction),
     Null,
     ReturnValue(Box<Object>),
-    CompiledFn(Instructions, u16, u8),
-    Closure(Rc<Object>, Vec<Object>),
+    /// A `break` leaving the loop body in the evaluator
+    Break,
+    /// A `continue` leaving the loop body in the evaluator
+    Continue,
+    CompiledFn(Rc<CompiledFunction>),
+    /// A compiled function with the variables it captured
+    Closure(Rc<CompiledFunction>, Rc<[Object]>),
+    /// A variable captured by a closure in the VM, shared between the closure
+    /// and the function that defines the variable
+    Cell(Rc<RefCell<Object>>),
     Error(String),
 }
 
 pub type BuiltinFunction = fn(Vec<Object>) -> Result<Object, String>;
 
+/// A function compiled to bytecode
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompiledFunction {
+    pub instructions: Rc<[u8]>,
+    pub num_locals: u16,
+    pub num_params: u8,
+    pub source_map: SourceMap,
+    /// Name the function was bound to by `let`, if any
+    pub name: Option<String>,
+}
+
 impl Object {
     pub fn is_returned(&self) -> bool {
         matches!(*self, Object::ReturnValue(_))
@@ -36,12 +63,333 @@ impl Object {
             o => o,
         }
     }
+
+    /// `-self`, or `None` if the object isn't a number
+    pub fn negated(&self) -> Option<Object> {
+        match self {
+            Object::Integer(i) => Some(match i.checked_neg() {
+                Some(i) => Object::Integer(i),
+                None => Object::from(-BigInt::from(*i)),
+            }),
+            Object::BigInt(i) => Some(Object::from(-i)),
+            Object::Float(x) => Some(Object::Float(-x)),
+            _ => None,
+        }
+    }
+
+    /// `~self`, or `None` if the object isn't an integer
+    pub fn bit_not(&self) -> Option<Object> {
+        match self {
+            Object::Integer(i) => Some(Object::Integer(!i)),
+            Object::BigInt(i) => Some(Object::from(!i)),
+            _ => None,
+        }
+    }
+
+    /// The values a `for` loop visits: the elements of an array, the keys of
+    /// a hash in sorted order, or the characters of a string
+    pub fn iterated(&self) -> Option<Vector<Object>> {
+        match self {
+            Object::Array(items) => Some(items.clone()),
+            Object::Hash(map) => {
+                let mut keys: Vec<_> = map.keys().cloned().collect();
+                keys.sort_by(compare_keys);
+                Some(keys.into())
+            }
+            Object::String(s) => Some(
+                s.chars()
+                    .map(|c| Object::String(c.to_string().into()))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Name of the object's type, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Integer(_) | Object::BigInt(_) => "integer",
+            Object::Float(_) => "float",
+            Object::Boolean(_) => "boolean",
+            Object::String(_) => "string",
+            Object::Array(_) => "array",
+            Object::Hash(_) => "hash",
+            Object::Function(_, _, _) | Object::CompiledFn(_) | Object::Closure(_, _) => "function",
+            Object::Builtin(_, _, _) => "builtin",
+            Object::Null => "null",
+            Object::ReturnValue(o) => o.type_name(),
+            Object::Break => "break",
+            Object::Continue => "continue",
+            Object::Cell(cell) => cell.borrow().type_name(),
+            Object::Error(_) => "error",
+        }
+    }
+}
+
+/// Operands of an arithmetic or comparison operator. When only one of them
+/// is a float, the integer is converted so that both are.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Numbers {
+    Integers(i64, i64),
+    BigInts(BigInt, BigInt),
+    Floats(f64, f64),
+}
+
+impl Numbers {
+    /// `None` unless both operands are numbers
+    pub fn new(left: &Object, right: &Object) -> Option<Self> {
+        let numbers = match (left, right) {
+            (Object::Integer(l), Object::Integer(r)) => Numbers::Integers(*l, *r),
+            (Object::Float(l), Object::Float(r)) => Numbers::Floats(*l, *r),
+            (Object::Float(l), r) => Numbers::Floats(*l, to_f64(r)?),
+            (l, Object::Float(r)) => Numbers::Floats(to_f64(l)?, *r),
+            (l, r) => Numbers::BigInts(to_bigint(l)?, to_bigint(r)?),
+        };
+        Some(numbers)
+    }
+
+    /// `left == right`, comparing an integer with a float by value
+    pub fn equal(left: &Object, right: &Object) -> bool {
+        match Numbers::new(left, right) {
+            Some(numbers) => numbers.compare() == Some(Ordering::Equal),
+            None => left == right,
+        }
+    }
+
+    /// `None` when a float operand is NaN
+    pub fn compare(&self) -> Option<Ordering> {
+        match self {
+            Numbers::Integers(l, r) => Some(l.cmp(r)),
+            Numbers::BigInts(l, r) => Some(l.cmp(r)),
+            Numbers::Floats(l, r) => l.partial_cmp(r),
+        }
+    }
+
+    // integer results that overflow `i64` are computed again as big integers
+
+    pub fn add(self) -> Object {
+        match self {
+            Numbers::Integers(l, r) => match l.checked_add(r) {
+                Some(i) => Object::Integer(i),
+                None => Numbers::promoted(l, r).add(),
+            },
+            Numbers::BigInts(l, r) => Object::from(l + r),
+            Numbers::Floats(l, r) => Object::Float(l + r),
+        }
+    }
+
+    pub fn sub(self) -> Object {
+        match self {
+            Numbers::Integers(l, r) => match l.checked_sub(r) {
+                Some(i) => Object::Integer(i),
+                None => Numbers::promoted(l, r).sub(),
+            },
+            Numbers::BigInts(l, r) => Object::from(l - r),
+            Numbers::Floats(l, r) => Object::Float(l - r),
+        }
+    }
+
+    pub fn mul(self) -> Object {
+        match self {
+            Numbers::Integers(l, r) => match l.checked_mul(r) {
+                Some(i) => Object::Integer(i),
+                None => Numbers::promoted(l, r).mul(),
+            },
+            Numbers::BigInts(l, r) => Object::from(l * r),
+            Numbers::Floats(l, r) => Object::Float(l * r),
+        }
+    }
+
+    /// Integer division truncates towards zero
+    pub fn div(self) -> Result<Object, ArithmeticError> {
+        match self {
+            Numbers::Integers(_, 0) => Err(ArithmeticError::DivisionByZero),
+            Numbers::Integers(l, r) => match l.checked_div(r) {
+                Some(i) => Ok(Object::Integer(i)),
+                None => Numbers::promoted(l, r).div(),
+            },
+            Numbers::BigInts(_, r) if r.is_zero() => Err(ArithmeticError::DivisionByZero),
+            Numbers::BigInts(l, r) => Ok(Object::from(l / r)),
+            Numbers::Floats(l, r) => Ok(Object::Float(l / r)),
+        }
+    }
+
+    pub fn rem(self) -> Result<Object, ArithmeticError> {
+        match self {
+            Numbers::Integers(_, 0) => Err(ArithmeticError::DivisionByZero),
+            Numbers::Integers(l, r) => match l.checked_rem(r) {
+                Some(i) => Ok(Object::Integer(i)),
+                None => Numbers::promoted(l, r).rem(),
+            },
+            Numbers::BigInts(_, r) if r.is_zero() => Err(ArithmeticError::DivisionByZero),
+            Numbers::BigInts(l, r) => Ok(Object::from(l % r)),
+            Numbers::Floats(l, r) => Ok(Object::Float(l % r)),
+        }
+    }
+
+    /// A negative integer exponent gives a float, as the result is a fraction
+    pub fn pow(self) -> Result<Object, ArithmeticError> {
+        match self {
+            Numbers::Integers(l, r) => match u32::try_from(r).ok().and_then(|r| l.checked_pow(r)) {
+                Some(i) => Ok(Object::Integer(i)),
+                None => Numbers::promoted(l, r).pow(),
+            },
+            Numbers::BigInts(l, r) if r.is_negative() => {
+                let (l, r) = (l.to_f64(), r.to_f64());
+                Ok(Object::Float(
+                    l.unwrap_or(f64::NAN).powf(r.unwrap_or(f64::NAN)),
+                ))
+            }
+            Numbers::BigInts(l, r) => {
+                if l.is_zero() || l.is_one() || r.is_zero() {
+                    return Ok(Object::from(if r.is_zero() { BigInt::one() } else { l }));
+                }
+                if l == -BigInt::one() {
+                    return Ok(Object::Integer(if (r % 2u8).is_zero() { 1 } else { -1 }));
+                }
+                match r.to_u64() {
+                    Some(r) if l.bits().saturating_mul(r) <= MAX_BITS => {
+                        Ok(Object::from(Pow::pow(l, r)))
+                    }
+                    _ => Err(ArithmeticError::TooLarge),
+                }
+            }
+            Numbers::Floats(l, r) => Ok(Object::Float(l.powf(r))),
+        }
+    }
+
+    // bitwise operators are only defined on integers, and give `None` for
+    // floats
+
+    pub fn bit_and(self) -> Option<Object> {
+        match self {
+            Numbers::Integers(l, r) => Some(Object::Integer(l & r)),
+            Numbers::BigInts(l, r) => Some(Object::from(l & r)),
+            Numbers::Floats(_, _) => None,
+        }
+    }
+
+    pub fn bit_or(self) -> Option<Object> {
+        match self {
+            Numbers::Integers(l, r) => Some(Object::Integer(l | r)),
+            Numbers::BigInts(l, r) => Some(Object::from(l | r)),
+            Numbers::Floats(_, _) => None,
+        }
+    }
+
+    pub fn bit_xor(self) -> Option<Object> {
+        match self {
+            Numbers::Integers(l, r) => Some(Object::Integer(l ^ r)),
+            Numbers::BigInts(l, r) => Some(Object::from(l ^ r)),
+            Numbers::Floats(_, _) => None,
+        }
+    }
+
+    pub fn shift_left(self) -> Option<Result<Object, ArithmeticError>> {
+        let (value, count) = self.into_bigints()?;
+        Some(match shift_count(&count) {
+            Err(err) => Err(err),
+            Ok(_) if value.is_zero() => Ok(Object::Integer(0)),
+            Ok(Some(n)) if value.bits().saturating_add(n) <= MAX_BITS => {
+                Ok(Object::from(value << n))
+            }
+            Ok(_) => Err(ArithmeticError::TooLarge),
+        })
+    }
+
+    /// Rounds towards negative infinity, like an arithmetic shift
+    pub fn shift_right(self) -> Option<Result<Object, ArithmeticError>> {
+        let (value, count) = self.into_bigints()?;
+        Some(match shift_count(&count) {
+            Err(err) => Err(err),
+            Ok(Some(n)) if n < value.bits() => Ok(Object::from(value >> n)),
+            // every bit is shifted out
+            Ok(_) if value.is_negative() => Ok(Object::Integer(-1)),
+            Ok(_) => Ok(Object::Integer(0)),
+        })
+    }
+
+    fn into_bigints(self) -> Option<(BigInt, BigInt)> {
+        match self {
+            Numbers::Integers(l, r) => Some((BigInt::from(l), BigInt::from(r))),
+            Numbers::BigInts(l, r) => Some((l, r)),
+            Numbers::Floats(_, _) => None,
+        }
+    }
+
+    fn promoted(left: i64, right: i64) -> Self {
+        Numbers::BigInts(BigInt::from(left), BigInt::from(right))
+    }
+}
+
+/// Largest integer the arithmetic operators will produce, in bits. Anything
+/// larger is much more likely a mistake than something a script wants.
+pub const MAX_BITS: u64 = 1 << 24;
+
+/// `None` when the count is too large to be of any use
+fn shift_count(count: &BigInt) -> Result<Option<u64>, ArithmeticError> {
+    match count.is_negative() {
+        true => Err(ArithmeticError::NegativeShift),
+        false => Ok(count.to_u64()),
+    }
+}
+
+fn to_bigint(object: &Object) -> Option<BigInt> {
+    match object {
+        Object::Integer(i) => Some(BigInt::from(*i)),
+        Object::BigInt(i) => Some(i.clone()),
+        _ => None,
+    }
+}
+
+fn to_f64(object: &Object) -> Option<f64> {
+    match object {
+        Object::Integer(i) => Some(*i as f64),
+        // out of range values become infinite rather than failing
+        Object::BigInt(i) => i.to_f64(),
+        Object::Float(x) => Some(*x),
+        _ => None,
+    }
+}
+
+/// Order of hash keys: numbers, then booleans, then strings
+fn compare_keys(a: &Object, b: &Object) -> Ordering {
+    fn rank(key: &Object) -> u8 {
+        match key {
+            Object::Boolean(_) => 1,
+            Object::String(_) => 2,
+            _ => 0,
+        }
+    }
+
+    match (a, b) {
+        (Object::Boolean(a), Object::Boolean(b)) => a.cmp(b),
+        (Object::String(a), Object::String(b)) => a.cmp(b),
+        _ => Numbers::new(a, b)
+            .and_then(|numbers| numbers.compare())
+            .unwrap_or_else(|| rank(a).cmp(&rank(b))),
+    }
+}
+
+/// Big integers that fit in an `i64` are always stored as `Object::Integer`,
+/// so that each integer has a single representation
+impl From<BigInt> for Object {
+    fn from(i: BigInt) -> Self {
+        match i.to_i64() {
+            Some(i) => Object::Integer(i),
+            None => Object::BigInt(i),
+        }
+    }
 }
 
 impl fmt::Display for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Object::Integer(ref i) => write!(f, "{}", i),
+            Object::BigInt(ref i) => write!(f, "{}", i),
+            // always shows a fractional part or exponent, so that floats
+            // can be told apart from integers
+            Object::Float(ref x) => write!(f, "{:?}", x),
             Object::Boolean(ref b) => {
                 if *b {
                     write!(f, "true")
@@ -78,9 +426,12 @@ impl fmt::Display for Object {
             Object::Builtin(ref name, _, _) => write!(f, "[built-in function: {}]", *name),
             Object::Null => write!(f, "null"),
             Object::ReturnValue(ref o) => write!(f, "{}", *o),
+            Object::Break => write!(f, "break"),
+            Object::Continue => write!(f, "continue"),
             Object::Error(ref s) => write!(f, "Error: {}", s),
-            Object::CompiledFn(_, _, _) => write!(f, "[compiled function]"),
+            Object::CompiledFn(_) => write!(f, "[compiled function]"),
             Object::Closure(_, _) => write!(f, "[closure]"),
+            Object::Cell(ref cell) => write!(f, "{}", cell.borrow()),
         }
     }
 }
@@ -92,6 +443,7 @@ impl Hash for Object {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match *self {
             Object::Integer(ref i) => i.hash(state),
+            Object::BigInt(ref i) => i.hash(state),
             Object::Boolean(ref b) => b.hash(state),
             Object::String(ref s) => s.hash(state),
             _ => "".hash(state),
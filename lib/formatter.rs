@@ -0,0 +1,582 @@
+use crate::error::FormatError;
+use crate::lexer::token::{SpannedToken, Token, Tokens};
+use crate::lexer::Lexer;
+use crate::parser::ast::*;
+use crate::parser::Parser;
+
+/// Column limit used when none is given
+pub const DEFAULT_WIDTH: usize = 80;
+
+const INDENT: &str = "  ";
+
+/// Lay out Monkey `source` in the canonical style, breaking lists and call
+/// arguments over several lines when they don't fit in `width` columns.
+/// Comments are kept, next to the code they were written beside.
+pub fn format_source(source: &str, width: usize) -> Result<String, FormatError> {
+    let (_, tokens) = Lexer::lex_with_trivia(source.as_bytes()).map_err(|_| FormatError::Lex)?;
+    let (comments, code): (Vec<_>, Vec<_>) = tokens.into_iter().partition(|t| t.token.is_trivia());
+
+    let (program, errors) = Parser::parse_recovering(Tokens::new(&code));
+    if !errors.is_empty() {
+        return Err(FormatError::Parse(errors));
+    }
+
+    let formatter = Formatter {
+        source,
+        width,
+        code: &code,
+        comments: &comments,
+    };
+    Ok(formatter.stmts(&program, 0, (0, source.len()), false))
+}
+
+/// Byte range of the source that a construct is responsible for
+type Region = (usize, usize);
+
+/// An element of a bracketed list: an expression, optionally with its key
+struct Item<'e> {
+    start: usize,
+    key: Option<&'e Expr>,
+    value: &'e Expr,
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    width: usize,
+    code: &'a [SpannedToken],
+    comments: &'a [SpannedToken],
+}
+
+impl<'a> Formatter<'a> {
+    /// Statements one per line at `indent`, together with the comments in
+    /// `region` that are outside of any statement. In a block, the final
+    /// expression statement is the value and isn't terminated.
+    fn stmts(&self, stmts: &[Stmt], indent: usize, region: Region, block: bool) -> String {
+        let mut out = String::new();
+        let mut last = None;
+        let mut gaps = self
+            .comments_in(region)
+            .filter(|c| {
+                !stmts
+                    .iter()
+                    .any(|s| contains((s.span.start, s.span.end), c))
+            })
+            .peekable();
+
+        for (i, stmt) in stmts.iter().enumerate() {
+            while let Some(c) = gaps.next_if(|c| c.span.start < stmt.span.start) {
+                self.comment_line(&mut out, &mut last, c, indent);
+            }
+            self.blank_line(&mut out, last, stmt.span.start);
+            for c in self.orphans((stmt.span.start, stmt.span.end), stmt_exprs(stmt)) {
+                self.comment_line(&mut out, &mut last, c, indent);
+            }
+
+            let value = block && i == stmts.len() - 1;
+            out.push_str(&INDENT.repeat(indent));
+            out.push_str(&self.stmt(stmt, indent, value));
+            last = Some(stmt.span.end);
+
+            let next = stmts.get(i + 1).map_or(region.1, |s| s.span.start);
+            if let Some(c) = gaps.next_if(|c| self.trailing(stmt.span.end, c, next)) {
+                out.push(' ');
+                out.push_str(&c.token.to_string());
+                last = Some(c.span.end);
+            }
+            out.push('\n');
+        }
+        for c in gaps {
+            self.comment_line(&mut out, &mut last, c, indent);
+        }
+
+        out
+    }
+
+    fn stmt(&self, stmt: &Stmt, indent: usize, value: bool) -> String {
+        let col = indent * INDENT.len();
+        match &stmt.kind {
+            StmtKind::LetStmt(name, e) => {
+                let prefix = format!("let {} = ", name);
+                let e = self.expr(e, indent, col + prefix.len());
+                format!("{}{};", prefix, e)
+            }
+            StmtKind::ReturnStmt(e) => format!("return {};", self.expr(e, indent, col + 7)),
+            StmtKind::ExprStmt(e) if value => self.expr(e, indent, col),
+            StmtKind::ExprStmt(e) => format!("{};", self.expr(e, indent, col)),
+            StmtKind::BreakStmt => "break;".to_owned(),
+            StmtKind::ContinueStmt => "continue;".to_owned(),
+        }
+    }
+
+    /// `e` laid out as if it started at column `col` of a line indented by
+    /// `indent` levels
+    fn expr(&self, e: &Expr, indent: usize, col: usize) -> String {
+        match &e.kind {
+            ExprKind::IdentExpr(_) | ExprKind::LitExpr(_) => e.to_string(),
+            ExprKind::PrefixExpr(op, right) => {
+                let right = self.operand(
+                    right,
+                    right.precedence() < Precedence::PPrefix,
+                    indent,
+                    col + 1,
+                );
+                format!("{}{}", op, right)
+            }
+            ExprKind::InfixExpr(op, left, right) => {
+                let left = self.operand(left, op.parenthesize_left(left), indent, col);
+                let col = end_column(&left, col) + op.to_string().len() + 2;
+                let right = self.operand(right, op.parenthesize_right(right), indent, col);
+                format!("{} {} {}", left, op, right)
+            }
+            ExprKind::IfExpr {
+                cond,
+                consequence,
+                alternative,
+            } => {
+                let mut out = format!("if ({}) ", self.expr(cond, indent, col + 4));
+                let after = consequence.last().map_or(cond.span.end, |s| s.span.end);
+                let close = self.closing_brace(after);
+                out.push_str(&self.block(consequence, indent, (cond.span.end, close)));
+                if let Some(alternative) = alternative {
+                    out.push_str(" else ");
+                    out.push_str(&self.block(alternative, indent, (close, e.span.end)));
+                }
+                out
+            }
+            ExprKind::WhileExpr { cond, body } => {
+                let cond = self.expr(cond, indent, col + 7);
+                let region = (e.span.start, e.span.end);
+                format!("while ({}) {}", cond, self.block(body, indent, region))
+            }
+            ExprKind::ForExpr {
+                ident,
+                iterable,
+                body,
+            } => {
+                let prefix = format!("for ({} in ", ident);
+                let iterable = self.expr(iterable, indent, col + prefix.len());
+                let region = (e.span.start, e.span.end);
+                format!(
+                    "{}{}) {}",
+                    prefix,
+                    iterable,
+                    self.block(body, indent, region)
+                )
+            }
+            ExprKind::FnExpr { params, body } => {
+                let params: Vec<_> = params.iter().map(|p| p.to_string()).collect();
+                let region = (e.span.start, e.span.end);
+                format!(
+                    "fn({}) {}",
+                    params.join(", "),
+                    self.block(body, indent, region)
+                )
+            }
+            ExprKind::CallExpr {
+                function,
+                arguments,
+            } => {
+                let region = (function.span.end, e.span.end);
+                let function = self.operand(
+                    function,
+                    function.precedence() < Precedence::PCall,
+                    indent,
+                    col,
+                );
+                let items: Vec<_> = arguments
+                    .iter()
+                    .map(|a| Item {
+                        start: a.span.start,
+                        key: None,
+                        value: a,
+                    })
+                    .collect();
+                let args = self.list("(", &items, ")", region, indent, end_column(&function, col));
+                format!("{}{}", function, args)
+            }
+            ExprKind::ArrayExpr(elements) => {
+                let items: Vec<_> = elements
+                    .iter()
+                    .map(|a| Item {
+                        start: a.span.start,
+                        key: None,
+                        value: a,
+                    })
+                    .collect();
+                self.list("[", &items, "]", (e.span.start, e.span.end), indent, col)
+            }
+            ExprKind::HashExpr(pairs) => {
+                let items: Vec<_> = pairs
+                    .iter()
+                    .map(|(key, value)| Item {
+                        start: key.span.start,
+                        key: Some(key),
+                        value,
+                    })
+                    .collect();
+                self.list("{", &items, "}", (e.span.start, e.span.end), indent, col)
+            }
+            ExprKind::IndexExpr { array, index } => {
+                let array =
+                    self.operand(array, array.precedence() < Precedence::PCall, indent, col);
+                let index = self.expr(index, indent, end_column(&array, col) + 1);
+                format!("{}[{}]", array, index)
+            }
+            ExprKind::AssignExpr {
+                target,
+                operator,
+                value,
+            } => {
+                let target = self.expr(target, indent, col);
+                let op = match operator {
+                    Some(op) => format!("{}=", op),
+                    None => "=".to_owned(),
+                };
+                let col = end_column(&target, col) + op.len() + 2;
+                let value = self.expr(value, indent, col);
+                format!("{} {} {}", target, op, value)
+            }
+        }
+    }
+
+    fn operand(&self, e: &Expr, parenthesize: bool, indent: usize, col: usize) -> String {
+        match parenthesize {
+            true => format!("({})", self.expr(e, indent, col + 1)),
+            false => self.expr(e, indent, col),
+        }
+    }
+
+    /// A braced block with its statements indented one level deeper
+    fn block(&self, stmts: &[Stmt], indent: usize, region: Region) -> String {
+        let body = self.stmts(stmts, indent + 1, region, true);
+        match body.is_empty() {
+            true => "{}".to_owned(),
+            false => format!("{{\n{}{}}}", body, INDENT.repeat(indent)),
+        }
+    }
+
+    /// `items` on one line if they fit and hold no comments, otherwise one
+    /// per line, each followed by a comma
+    fn list(
+        &self,
+        open: &str,
+        items: &[Item],
+        close: &str,
+        region: Region,
+        indent: usize,
+        col: usize,
+    ) -> String {
+        if self.comments_in(region).next().is_none() {
+            let mut flat = open.to_owned();
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    flat.push_str(", ");
+                }
+                flat.push_str(&self.item(item, indent, end_column(&flat, col)));
+            }
+            flat.push_str(close);
+            if items.is_empty() || self.fits(&flat, col) {
+                return flat;
+            }
+        }
+
+        let inner = indent + 1;
+        let mut out = format!("{}\n", open);
+        let mut last = None;
+        let mut gaps = self
+            .comments_in(region)
+            .filter(|c| {
+                !items
+                    .iter()
+                    .any(|item| contains((item.start, item.value.span.end), c))
+            })
+            .peekable();
+
+        for (i, item) in items.iter().enumerate() {
+            while let Some(c) = gaps.next_if(|c| c.span.start < item.start) {
+                self.comment_line(&mut out, &mut last, c, inner);
+            }
+            let exprs = item.key.into_iter().chain([item.value]).collect();
+            for c in self.orphans((item.start, item.value.span.end), exprs) {
+                self.comment_line(&mut out, &mut last, c, inner);
+            }
+
+            out.push_str(&INDENT.repeat(inner));
+            out.push_str(&self.item(item, inner, inner * INDENT.len()));
+            out.push(',');
+            last = Some(item.value.span.end);
+
+            let next = items.get(i + 1).map_or(region.1, |item| item.start);
+            if let Some(c) = gaps.next_if(|c| self.trailing(item.value.span.end, c, next)) {
+                out.push(' ');
+                out.push_str(&c.token.to_string());
+                last = Some(c.span.end);
+            }
+            out.push('\n');
+        }
+        for c in gaps {
+            self.comment_line(&mut out, &mut last, c, inner);
+        }
+
+        out.push_str(&INDENT.repeat(indent));
+        out.push_str(close);
+        out
+    }
+
+    fn item(&self, item: &Item, indent: usize, col: usize) -> String {
+        match item.key {
+            Some(key) => {
+                let key = self.expr(key, indent, col);
+                let value = self.expr(item.value, indent, end_column(&key, col) + 2);
+                format!("{}: {}", key, value)
+            }
+            None => self.expr(item.value, indent, col),
+        }
+    }
+
+    /// Whether `text` starting at column `col` stays within the width. Only
+    /// the first and last lines depend on how the list around them is laid
+    /// out, so those are the ones checked.
+    fn fits(&self, text: &str, col: usize) -> bool {
+        let first = text.lines().next().unwrap_or("");
+        col + first.chars().count() <= self.width && end_column(text, col) <= self.width
+    }
+
+    /// Comments starting within `region`, in source order
+    fn comments_in(&self, region: Region) -> impl Iterator<Item = &'a SpannedToken> {
+        self.comments
+            .iter()
+            .filter(move |c| region.0 <= c.span.start && c.span.start < region.1)
+    }
+
+    /// Comments inside `region` that no list or block of `exprs` takes care
+    /// of. They are moved in front of the statement or item they were in.
+    fn orphans(&self, region: Region, exprs: Vec<&Expr>) -> Vec<&'a SpannedToken> {
+        let mut nested = Vec::new();
+        for e in exprs {
+            self.regions(e, &mut nested);
+        }
+        self.comments_in(region)
+            .filter(|c| !nested.iter().any(|r| contains(*r, c)))
+            .collect()
+    }
+
+    /// Regions of the outermost lists and blocks within `e`
+    fn regions(&self, e: &Expr, out: &mut Vec<Region>) {
+        match &e.kind {
+            ExprKind::IdentExpr(_) | ExprKind::LitExpr(_) => {}
+            ExprKind::PrefixExpr(_, right) => self.regions(right, out),
+            ExprKind::InfixExpr(_, left, right)
+            | ExprKind::AssignExpr {
+                target: left,
+                value: right,
+                ..
+            } => {
+                self.regions(left, out);
+                self.regions(right, out);
+            }
+            ExprKind::IfExpr {
+                cond, consequence, ..
+            } => {
+                self.regions(cond, out);
+                let after = consequence.last().map_or(cond.span.end, |s| s.span.end);
+                let close = self.closing_brace(after);
+                out.push((cond.span.end, close));
+                out.push((close, e.span.end));
+            }
+            ExprKind::CallExpr { function, .. } => {
+                self.regions(function, out);
+                out.push((function.span.end, e.span.end));
+            }
+            ExprKind::IndexExpr { array, index } => {
+                self.regions(array, out);
+                self.regions(index, out);
+            }
+            ExprKind::WhileExpr { .. }
+            | ExprKind::ForExpr { .. }
+            | ExprKind::FnExpr { .. }
+            | ExprKind::ArrayExpr(_)
+            | ExprKind::HashExpr(_) => out.push((e.span.start, e.span.end)),
+        }
+    }
+
+    /// Offset of the `}` that ends a block whose last statement ends at `after`
+    fn closing_brace(&self, after: usize) -> usize {
+        self.code
+            .iter()
+            .find(|t| t.span.start >= after && t.token == Token::RBrace)
+            .map_or(after, |t| t.span.start)
+    }
+
+    fn same_line(&self, from: usize, to: usize) -> bool {
+        !self.source[from..to].contains('\n')
+    }
+
+    /// Whether `c` trails the code ending at `end` rather than leading the
+    /// code starting at `next`. Past a separator, a comment followed on its
+    /// line by the next code belongs to that code.
+    fn trailing(&self, end: usize, c: &SpannedToken, next: usize) -> bool {
+        let separated = self
+            .code
+            .iter()
+            .any(|t| end <= t.span.start && t.span.start < c.span.start);
+        c.span.start < next
+            && self.same_line(end, c.span.start)
+            && !(separated && self.same_line(c.span.end, next))
+    }
+
+    /// Keep one empty line where the source had at least one
+    fn blank_line(&self, out: &mut String, last: Option<usize>, next: usize) {
+        if let Some(last) = last.filter(|_| !out.ends_with("\n\n")) {
+            if self.source[last..next].matches('\n').count() > 1 {
+                out.push('\n');
+            }
+        }
+    }
+
+    fn comment_line(
+        &self,
+        out: &mut String,
+        last: &mut Option<usize>,
+        c: &SpannedToken,
+        indent: usize,
+    ) {
+        self.blank_line(out, *last, c.span.start);
+        out.push_str(&INDENT.repeat(indent));
+        out.push_str(&c.token.to_string());
+        out.push('\n');
+        *last = Some(c.span.end);
+    }
+}
+
+fn stmt_exprs(stmt: &Stmt) -> Vec<&Expr> {
+    match &stmt.kind {
+        StmtKind::LetStmt(_, e) | StmtKind::ReturnStmt(e) | StmtKind::ExprStmt(e) => vec![e],
+        StmtKind::BreakStmt | StmtKind::ContinueStmt => vec![],
+    }
+}
+
+fn contains(region: Region, token: &SpannedToken) -> bool {
+    region.0 <= token.span.start && token.span.end <= region.1
+}
+
+/// Column reached after writing `text` from column `col`
+fn end_column(text: &str, col: usize) -> usize {
+    match text.rfind('\n') {
+        Some(i) => text[i + 1..].chars().count(),
+        None => col + text.chars().count(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        format_source(source, DEFAULT_WIDTH).unwrap()
+    }
+
+    #[test]
+    fn layout() {
+        let source = "let add=fn(x,y){x+y};\n\n\n\nlet r = add( 1,2 );if(r>=3){r}else{-r;}";
+        let expected = "let add = fn(x, y) {\n  x + y\n};\n\n\
+                        let r = add(1, 2);\n\
+                        if (r >= 3) {\n  r\n} else {\n  -r\n};\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn assignment() {
+        let source = "x+=1;a[i][0]=b=[1,2];(y=2)*3";
+        let expected = "x += 1;\na[i][0] = b = [1, 2];\n(y = 2) * 3;\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn loops() {
+        let source = "while(i<3){i+=1;if(i==2){continue}}for(x in xs){break;}";
+        let expected = "while (i < 3) {\n  i += 1;\n  if (i == 2) {\n    continue;\n  }\n};\n\
+                        for (x in xs) {\n  break;\n};\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn computed_hash_keys() {
+        let source = "{name:1,f( x ):[2],-1:3}";
+        assert_eq!(format(source), "{name: 1, f(x): [2], -1: 3};\n");
+
+        let source = "let h = {a + b: 1, // sum\n\"c\": 2};";
+        let expected = "let h = {\n  a + b: 1, // sum\n  \"c\": 2,\n};\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn wrap_long_lists() {
+        let source = "let result = compute(first_argument, second_argument, [1, 2, 3], fourth);";
+        let expected = "let result = compute(\n\
+                        \x20 first_argument,\n\
+                        \x20 second_argument,\n\
+                        \x20 [1, 2, 3],\n\
+                        \x20 fourth,\n\
+                        );\n";
+        assert_eq!(format_source(source, 40).unwrap(), expected);
+        assert_eq!(format_source(expected, 80).unwrap(), format(source));
+    }
+
+    #[test]
+    fn keep_comments() {
+        let source = "// header\n\
+                      let a = [1, // one\n2];\n\
+                      let f = fn(x) { // body\n  x // value\n};\n\
+                      f(a) // done\n\
+                      // footer\n";
+        let expected = "// header\n\
+                        let a = [\n  1, // one\n  2,\n];\n\
+                        let f = fn(x) {\n  // body\n  x // value\n};\n\
+                        f(a); // done\n\
+                        // footer\n";
+        assert_eq!(format(source), expected);
+
+        let source = "let a = 1; let b = 2; // about b\n";
+        assert_eq!(format(source), "let a = 1;\nlet b = 2; // about b\n");
+
+        let source = "let h = {\"a\": 1, \"b\": 2 /* two */, \"c\": 3};\n\
+                      f(1, 2 /* two */);\n\
+                      f(1, /* two */ 2);";
+        let expected = "let h = {\n  \"a\": 1,\n  \"b\": 2, /* two */\n  \"c\": 3,\n};\n\
+                        f(\n  1,\n  2, /* two */\n);\n\
+                        f(\n  1,\n  /* two */\n  2,\n);\n";
+        assert_eq!(format(source), expected);
+
+        let source = "while (c) // loop\n\n{ x /* x */ + 1 }";
+        let expected = "while (c) {\n  // loop\n\n  /* x */\n  x + 1\n};\n";
+        assert_eq!(format(source), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn keep_block_comments() {
+        let source = "/* a /* nested */\n   block */ let a = f(1 /* one */, 2);\na /**/";
+        let expected = "/* a /* nested */\n   block */\n\
+                        let a = f(\n  1, /* one */\n  2,\n);\n\
+                        a; /**/\n";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn idempotent_on_examples() {
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let source = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            let formatted = format(&source);
+            assert_eq!(format(&formatted), formatted);
+        }
+    }
+
+    #[test]
+    fn reject_invalid_source() {
+        match format_source("let a = ;\nlet b 1;", DEFAULT_WIDTH) {
+            Err(FormatError::Parse(errors)) => assert_eq!(errors.len(), 2),
+            other => panic!("expected parse errors, got {:?}", other),
+        }
+    }
+}
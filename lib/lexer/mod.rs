@@ -1,21 +1,23 @@
 use nom::branch::*;
-use nom::bytes::complete::{tag, take};
+use nom::bytes::complete::{tag, take, take_till};
 use nom::character::complete::{alpha1, alphanumeric1, digit1, multispace0};
-use nom::combinator::{map, map_res, recognize};
+use nom::combinator::{consumed, map, map_res, opt, recognize};
 use nom::multi::many0;
-use nom::sequence::{delimited, pair};
+use nom::sequence::{delimited, pair, tuple};
 use nom::*;
 
+use num_bigint::BigInt;
 use std::str;
 use std::str::FromStr;
 use std::str::Utf8Error;
 
 pub mod token;
 use crate::lexer::token::*;
+use crate::span::Locator;
 
 macro_rules! syntax {
     ($func_name: ident, $tag_string: literal, $output_token: expr) => {
-        fn $func_name<'a>(s: &'a [u8]) -> IResult<&[u8], Token> {
+        fn $func_name(s: &[u8]) -> IResult<&[u8], Token> {
             map(tag($tag_string), |_| $output_token)(s)
         }
     };
@@ -25,10 +27,31 @@ macro_rules! syntax {
 syntax! {equal_operator, "==", Token::Equal}
 syntax! {not_equal_operator, "!=", Token::NotEqual}
 syntax! {assign_operator, "=", Token::Assign}
+syntax! {plus_assign_operator, "+=", Token::PlusAssign}
+syntax! {minus_assign_operator, "-=", Token::MinusAssign}
+syntax! {multiply_assign_operator, "*=", Token::MultiplyAssign}
+syntax! {divide_assign_operator, "/=", Token::DivideAssign}
+syntax! {modulo_assign_operator, "%=", Token::ModuloAssign}
+syntax! {power_assign_operator, "**=", Token::PowerAssign}
+syntax! {bit_and_assign_operator, "&=", Token::BitAndAssign}
+syntax! {bit_or_assign_operator, "|=", Token::BitOrAssign}
+syntax! {bit_xor_assign_operator, "^=", Token::BitXorAssign}
+syntax! {shift_left_assign_operator, "<<=", Token::ShiftLeftAssign}
+syntax! {shift_right_assign_operator, ">>=", Token::ShiftRightAssign}
 syntax! {plus_operator, "+", Token::Plus}
 syntax! {minus_operator, "-", Token::Minus}
 syntax! {multiply_operator, "*", Token::Multiply}
 syntax! {divide_operator, "/", Token::Divide}
+syntax! {modulo_operator, "%", Token::Modulo}
+syntax! {power_operator, "**", Token::Power}
+syntax! {bit_and_operator, "&", Token::BitAnd}
+syntax! {bit_or_operator, "|", Token::BitOr}
+syntax! {bit_xor_operator, "^", Token::BitXor}
+syntax! {shift_left_operator, "<<", Token::ShiftLeft}
+syntax! {shift_right_operator, ">>", Token::ShiftRight}
+syntax! {bit_not_operator, "~", Token::BitNot}
+syntax! {and_operator, "&&", Token::And}
+syntax! {or_operator, "||", Token::Or}
 syntax! {not_operator, "!", Token::Not}
 syntax! {greater_operator_equal, ">=", Token::GreaterThanEqual}
 syntax! {lesser_operator_equal, "<=", Token::LessThanEqual}
@@ -36,19 +59,47 @@ syntax! {greater_operator, ">", Token::GreaterThan}
 syntax! {lesser_operator, "<", Token::LessThan}
 
 pub fn lex_operator(input: &[u8]) -> IResult<&[u8], Token> {
+    // `alt` takes at most 21 parsers
     alt((
-        equal_operator,
-        not_equal_operator,
-        assign_operator,
-        plus_operator,
-        minus_operator,
-        multiply_operator,
-        divide_operator,
-        not_operator,
-        greater_operator_equal,
-        lesser_operator_equal,
-        greater_operator,
-        lesser_operator,
+        alt((
+            plus_assign_operator,
+            minus_assign_operator,
+            multiply_assign_operator,
+            divide_assign_operator,
+            modulo_assign_operator,
+            power_assign_operator,
+            bit_and_assign_operator,
+            bit_or_assign_operator,
+            bit_xor_assign_operator,
+            shift_left_assign_operator,
+            shift_right_assign_operator,
+        )),
+        alt((
+            equal_operator,
+            not_equal_operator,
+            assign_operator,
+            plus_operator,
+            minus_operator,
+            power_operator,
+            multiply_operator,
+            divide_operator,
+            modulo_operator,
+            and_operator,
+            or_operator,
+        )),
+        alt((
+            bit_and_operator,
+            bit_or_operator,
+            bit_xor_operator,
+            bit_not_operator,
+            not_operator,
+            shift_left_operator,
+            shift_right_operator,
+            greater_operator_equal,
+            lesser_operator_equal,
+            greater_operator,
+            lesser_operator,
+        )),
     ))(input)
 }
 
@@ -127,6 +178,11 @@ fn lex_reserved_ident(input: &[u8]) -> IResult<&[u8], Token> {
                 "fn" => Token::Function,
                 "if" => Token::If,
                 "else" => Token::Else,
+                "while" => Token::While,
+                "for" => Token::For,
+                "in" => Token::In,
+                "break" => Token::Break,
+                "continue" => Token::Continue,
                 "return" => Token::Return,
                 "true" => Token::BoolLiteral(true),
                 "false" => Token::BoolLiteral(false),
@@ -136,18 +192,79 @@ fn lex_reserved_ident(input: &[u8]) -> IResult<&[u8], Token> {
     )(input)
 }
 
-fn complete_str_from_str<F: FromStr>(c: &str) -> Result<F, F::Err> {
-    FromStr::from_str(c)
+// Integers parsing. Literals beyond the range of `i64` become big integers.
+fn lex_integer(input: &[u8]) -> IResult<&[u8], Token> {
+    map_res(
+        map_res(digit1, complete_byte_slice_str_from_utf8),
+        |s: &str| match i64::from_str(s) {
+            Ok(i) => Ok(Token::IntLiteral(i)),
+            Err(_) => BigInt::from_str(s).map(Token::BigIntLiteral),
+        },
+    )(input)
 }
 
-// Integers parsing
-fn lex_integer(input: &[u8]) -> IResult<&[u8], Token> {
-    map(
-        map_res(
-            map_res(digit1, complete_byte_slice_str_from_utf8),
-            complete_str_from_str,
-        ),
-        Token::IntLiteral,
+// Comments
+fn line_comment(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    recognize(pair(tag("//"), take_till(|c| c == b'\n')))(input)
+}
+
+/// A `/* */` comment, which may contain other block comments. One that is
+/// never closed runs to the end of the input and yields `None`.
+fn block_comment(input: &[u8]) -> IResult<&[u8], Option<&[u8]>> {
+    tag("/*")(input)?;
+
+    let mut depth = 0;
+    let mut i = 0;
+    while i + 1 < input.len() {
+        match &input[i..i + 2] {
+            b"/*" => {
+                depth += 1;
+                i += 2;
+            }
+            b"*/" => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return Ok((&input[i..], Some(&input[..i])));
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    Ok((&input[input.len()..], None))
+}
+
+fn comment_token(c: &[u8]) -> Token {
+    Token::Comment(String::from_utf8_lossy(c).trim_end().to_owned())
+}
+
+fn lex_comment(input: &[u8]) -> IResult<&[u8], Token> {
+    alt((
+        map(line_comment, comment_token),
+        map(block_comment, |c| c.map_or(Token::Illegal, comment_token)),
+    ))(input)
+}
+
+// Floats: digits with a fractional part, an exponent or both. Literals too
+// large to be represented are illegal.
+fn float_exponent(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    recognize(tuple((
+        alt((tag("e"), tag("E"))),
+        opt(alt((tag("+"), tag("-")))),
+        digit1,
+    )))(input)
+}
+
+fn lex_float(input: &[u8]) -> IResult<&[u8], Token> {
+    let fraction = recognize(pair(pair(tag("."), digit1), opt(float_exponent)));
+    map_res(
+        recognize(pair(digit1, alt((fraction, float_exponent)))),
+        |s| {
+            complete_byte_slice_str_from_utf8(s).map(|s| match f64::from_str(s) {
+                Ok(x) if x.is_finite() => Token::FloatLiteral(x),
+                _ => Token::Illegal,
+            })
+        },
     )(input)
 }
 
@@ -158,36 +275,66 @@ fn lex_illegal(input: &[u8]) -> IResult<&[u8], Token> {
 
 fn lex_token(input: &[u8]) -> IResult<&[u8], Token> {
     alt((
+        lex_comment,
         lex_operator,
         lex_punctuations,
         lex_string,
         lex_reserved_ident,
+        lex_float,
         lex_integer,
         lex_illegal,
     ))(input)
 }
 
-fn lex_tokens(input: &[u8]) -> IResult<&[u8], Vec<Token>> {
-    many0(delimited(multispace0, lex_token, multispace0))(input)
+fn lex_tokens(input: &[u8]) -> IResult<&[u8], Vec<(&[u8], Token)>> {
+    many0(delimited(multispace0, consumed(lex_token), multispace0))(input)
 }
 
 pub struct Lexer;
 
 impl Lexer {
-    pub fn lex_tokens(bytes: &[u8]) -> IResult<&[u8], Vec<Token>> {
-        lex_tokens(bytes)
-            .map(|(slice, result)| (slice, [&result[..], &vec![Token::EOF][..]].concat()))
+    pub fn lex_tokens(bytes: &[u8]) -> IResult<&[u8], Vec<SpannedToken>> {
+        Self::lex_with_trivia(bytes).map(|(slice, mut tokens)| {
+            tokens.retain(|t| !t.token.is_trivia());
+            (slice, tokens)
+        })
+    }
+
+    /// Like `lex_tokens`, but comments are kept in the output as
+    /// `Token::Comment` for tools that need to reproduce them.
+    pub fn lex_with_trivia(bytes: &[u8]) -> IResult<&[u8], Vec<SpannedToken>> {
+        lex_tokens(bytes).map(|(slice, result)| {
+            let mut locator = Locator::new(bytes);
+            let mut tokens = result
+                .into_iter()
+                .map(|(lexeme, token)| {
+                    let start = bytes.offset(lexeme);
+                    SpannedToken::new(token, locator.locate(start, start + lexeme.len()))
+                })
+                .collect::<Vec<_>>();
+
+            let end = bytes.offset(slice);
+            tokens.push(SpannedToken::new(Token::EOF, locator.locate(end, end)));
+
+            (slice, tokens)
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::span::Span;
+
+    fn lex(input: &[u8]) -> Vec<Token> {
+        let (_, result) = Lexer::lex_tokens(input).unwrap();
+        result.into_iter().map(|t| t.token).collect()
+    }
 
     #[test]
     fn test_lexer1() {
         let input = &b"=+(){},;"[..];
-        let (_, result) = Lexer::lex_tokens(input).unwrap();
+        let result = lex(input);
 
         let expected_results = vec![
             Token::Assign,
@@ -214,7 +361,7 @@ mod tests {
              let result = add(five, ten);"
             .as_bytes();
 
-        let (_, result) = Lexer::lex_tokens(input).unwrap();
+        let result = lex(input);
 
         let expected_results = vec![
             Token::Let,
@@ -274,7 +421,7 @@ mod tests {
             "
         .as_bytes();
 
-        let (_, result) = Lexer::lex_tokens(input).unwrap();
+        let result = lex(input);
 
         let expected_results = vec![
             Token::If,
@@ -341,38 +488,37 @@ mod tests {
 
     #[test]
     fn string_literals() {
-        let (_, result) = Lexer::lex_tokens(&b"\"foobar\""[..]).unwrap();
+        let result = lex(&b"\"foobar\""[..]);
         assert_eq!(
             result,
             vec![Token::StringLiteral("foobar".to_owned()), Token::EOF]
         );
 
-        let (_, result) = Lexer::lex_tokens(&b"\"foo bar\""[..]).unwrap();
+        let result = lex(&b"\"foo bar\""[..]);
         assert_eq!(
             result,
             vec![Token::StringLiteral("foo bar".to_owned()), Token::EOF]
         );
 
-        let (_, result) = Lexer::lex_tokens(&b"\"foo\nbar\""[..]).unwrap();
+        let result = lex(&b"\"foo\nbar\""[..]);
         assert_eq!(
             result,
             vec![Token::StringLiteral("foo\nbar".to_owned()), Token::EOF]
         );
 
-        let (_, result) = Lexer::lex_tokens(&b"\"foo\tbar\""[..]).unwrap();
+        let result = lex(&b"\"foo\tbar\""[..]);
         assert_eq!(
             result,
             vec![Token::StringLiteral("foo\tbar".to_owned()), Token::EOF]
         );
 
-        let (_, result) = Lexer::lex_tokens(&b"\"foo\\\"bar\""[..]).unwrap();
+        let result = lex(&b"\"foo\\\"bar\""[..]);
         assert_eq!(
             result,
             vec![Token::StringLiteral("foo\"bar".to_owned()), Token::EOF]
         );
 
-        let (_, result) =
-            Lexer::lex_tokens(&b"\"foo\\\"bar with \xf0\x9f\x92\x96 emojis\""[..]).unwrap();
+        let result = lex(&b"\"foo\\\"bar with \xf0\x9f\x92\x96 emojis\""[..]);
         assert_eq!(
             result,
             vec![
@@ -384,7 +530,7 @@ mod tests {
 
     #[test]
     fn id_with_numbers() {
-        let (_, result) = Lexer::lex_tokens(&b"hello2 hel301oo120"[..]).unwrap();
+        let result = lex(&b"hello2 hel301oo120"[..]);
         let expected = vec![
             Token::Ident("hello2".to_owned()),
             Token::Ident("hel301oo120".to_owned()),
@@ -393,9 +539,110 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn float_literals() {
+        let result = lex(&b"3.25 1e3 2.5E-2 7. 1e400"[..]);
+        let expected = vec![
+            Token::FloatLiteral(3.25),
+            Token::FloatLiteral(1000.0),
+            Token::FloatLiteral(0.025),
+            Token::IntLiteral(7),
+            Token::Illegal,
+            Token::Illegal,
+            Token::EOF,
+        ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn big_integer_literals() {
+        let result = lex(&b"9223372036854775807 9223372036854775808"[..]);
+        let expected = vec![
+            Token::IntLiteral(i64::MAX),
+            Token::BigIntLiteral("9223372036854775808".parse().unwrap()),
+            Token::EOF,
+        ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn arithmetic_and_bitwise_operators() {
+        let result = lex(&b"% ** * & | ^ ~ << >> <= >="[..]);
+        let expected = vec![
+            Token::Modulo,
+            Token::Power,
+            Token::Multiply,
+            Token::BitAnd,
+            Token::BitOr,
+            Token::BitXor,
+            Token::BitNot,
+            Token::ShiftLeft,
+            Token::ShiftRight,
+            Token::LessThanEqual,
+            Token::GreaterThanEqual,
+            Token::EOF,
+        ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn logical_operators() {
+        let result = lex(&b"a && b || c & d | e"[..]);
+        let expected = vec![
+            Token::Ident("a".to_owned()),
+            Token::And,
+            Token::Ident("b".to_owned()),
+            Token::Or,
+            Token::Ident("c".to_owned()),
+            Token::BitAnd,
+            Token::Ident("d".to_owned()),
+            Token::BitOr,
+            Token::Ident("e".to_owned()),
+            Token::EOF,
+        ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn assignment_operators() {
+        let result = lex(&b"= += -= *= /= %= **= &= |= ^= <<= >>= =="[..]);
+        let expected = vec![
+            Token::Assign,
+            Token::PlusAssign,
+            Token::MinusAssign,
+            Token::MultiplyAssign,
+            Token::DivideAssign,
+            Token::ModuloAssign,
+            Token::PowerAssign,
+            Token::BitAndAssign,
+            Token::BitOrAssign,
+            Token::BitXorAssign,
+            Token::ShiftLeftAssign,
+            Token::ShiftRightAssign,
+            Token::Equal,
+            Token::EOF,
+        ];
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn loop_keywords() {
+        let result = lex(&b"while for in break continue inside"[..]);
+        let expected = vec![
+            Token::While,
+            Token::For,
+            Token::In,
+            Token::Break,
+            Token::Continue,
+            Token::Ident("inside".to_owned()),
+            Token::EOF,
+        ];
+        assert_eq!(result, expected);
+    }
+
     #[test]
     fn array_tokens() {
-        let (_, result) = Lexer::lex_tokens(&b"[1, 2];"[..]).unwrap();
+        let result = lex(&b"[1, 2];"[..]);
         let expected = vec![
             Token::LBracket,
             Token::IntLiteral(1),
@@ -410,7 +657,7 @@ mod tests {
 
     #[test]
     fn hash_tokens() {
-        let (_, result) = Lexer::lex_tokens(&b"{\"hello\": \"world\"}"[..]).unwrap();
+        let result = lex(&b"{\"hello\": \"world\"}"[..]);
         let expected = vec![
             Token::LBrace,
             Token::StringLiteral("hello".to_owned()),
@@ -421,4 +668,92 @@ mod tests {
         ];
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn token_spans() {
+        let (_, result) = Lexer::lex_tokens(&b"let x = 10;\n  x >= \"a b\""[..]).unwrap();
+        let spans = result.into_iter().map(|t| t.span).collect::<Vec<_>>();
+        let expected = vec![
+            Span::new(0, 3, 1, 1),
+            Span::new(4, 5, 1, 5),
+            Span::new(6, 7, 1, 7),
+            Span::new(8, 10, 1, 9),
+            Span::new(10, 11, 1, 11),
+            Span::new(14, 15, 2, 3),
+            Span::new(16, 18, 2, 5),
+            Span::new(19, 24, 2, 8),
+            Span::new(24, 24, 2, 13),
+        ];
+        assert_eq!(spans, expected);
+    }
+
+    #[test]
+    fn block_comments() {
+        let input = &b"1 /* one */ + /* a /* nested */ comment\n*/ 2 /**/"[..];
+        let expected = vec![
+            Token::IntLiteral(1),
+            Token::Plus,
+            Token::IntLiteral(2),
+            Token::EOF,
+        ];
+        assert_eq!(lex(input), expected);
+
+        let (_, result) = Lexer::lex_with_trivia(input).unwrap();
+        let comments = result
+            .into_iter()
+            .filter(|t| t.token.is_trivia())
+            .map(|t| (t.token, t.span))
+            .collect::<Vec<_>>();
+        let expected = vec![
+            (
+                Token::Comment("/* one */".to_owned()),
+                Span::new(2, 11, 1, 3),
+            ),
+            (
+                Token::Comment("/* a /* nested */ comment\n*/".to_owned()),
+                Span::new(14, 42, 1, 15),
+            ),
+            (Token::Comment("/**/".to_owned()), Span::new(45, 49, 2, 6)),
+        ];
+        assert_eq!(comments, expected);
+
+        let result = lex(&b"1 /* not /* closed */"[..]);
+        assert_eq!(
+            result,
+            vec![Token::IntLiteral(1), Token::Illegal, Token::EOF]
+        );
+    }
+
+    #[test]
+    fn line_comments() {
+        let input = &b"let a = 1; // one\n// two\na / 2 //"[..];
+        let expected = vec![
+            Token::Let,
+            Token::Ident("a".to_owned()),
+            Token::Assign,
+            Token::IntLiteral(1),
+            Token::SemiColon,
+            Token::Ident("a".to_owned()),
+            Token::Divide,
+            Token::IntLiteral(2),
+            Token::EOF,
+        ];
+        assert_eq!(lex(input), expected);
+
+        let (_, result) = Lexer::lex_with_trivia(input).unwrap();
+        let comments = result
+            .into_iter()
+            .filter(|t| t.token.is_trivia())
+            .map(|t| (t.token, t.span))
+            .collect::<Vec<_>>();
+        let expected = vec![
+            (
+                Token::Comment("// one".to_owned()),
+                Span::new(11, 17, 1, 12),
+            ),
+            (Token::Comment("// two".to_owned()), Span::new(18, 24, 2, 1)),
+            (Token::Comment("//".to_owned()), Span::new(31, 33, 3, 7)),
+        ];
+        assert_eq!(comments, expected);
+    }
 }
@@ -1,25 +1,61 @@
 use nom::*;
+use num_bigint::BigInt;
+use std::fmt;
 use std::iter::Enumerate;
 use std::ops::{Range, RangeFrom, RangeFull, RangeTo};
 
+use crate::span::Span;
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum Token {
     Illegal,
     EOF,
+    /// Source text of a line or block comment, only produced by
+    /// `Lexer::lex_with_trivia`
+    Comment(String),
     // identifier and literals
     Ident(String),
     StringLiteral(String),
     IntLiteral(i64),
+    /// An integer literal too large for `IntLiteral`
+    BigIntLiteral(BigInt),
+    FloatLiteral(f64),
     BoolLiteral(bool),
     // statements
     Assign,
+    PlusAssign,
+    MinusAssign,
+    MultiplyAssign,
+    DivideAssign,
+    ModuloAssign,
+    PowerAssign,
+    BitAndAssign,
+    BitOrAssign,
+    BitXorAssign,
+    ShiftLeftAssign,
+    ShiftRightAssign,
     If,
     Else,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
     // operators
     Plus,
     Minus,
     Divide,
     Multiply,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    BitNot,
+    And,
+    Or,
     Equal,
     NotEqual,
     GreaterThanEqual,
@@ -43,32 +79,127 @@ pub enum Token {
     RBracket,
 }
 
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Illegal => write!(f, "<illegal>"),
+            Token::EOF => write!(f, "<eof>"),
+            Token::Comment(text) => write!(f, "{}", text),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::StringLiteral(s) => {
+                write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            Token::IntLiteral(i) => write!(f, "{}", i),
+            Token::BigIntLiteral(i) => write!(f, "{}", i),
+            Token::FloatLiteral(x) => write!(f, "{:?}", x),
+            Token::BoolLiteral(b) => write!(f, "{}", b),
+            Token::Assign => write!(f, "="),
+            Token::PlusAssign => write!(f, "+="),
+            Token::MinusAssign => write!(f, "-="),
+            Token::MultiplyAssign => write!(f, "*="),
+            Token::DivideAssign => write!(f, "/="),
+            Token::ModuloAssign => write!(f, "%="),
+            Token::PowerAssign => write!(f, "**="),
+            Token::BitAndAssign => write!(f, "&="),
+            Token::BitOrAssign => write!(f, "|="),
+            Token::BitXorAssign => write!(f, "^="),
+            Token::ShiftLeftAssign => write!(f, "<<="),
+            Token::ShiftRightAssign => write!(f, ">>="),
+            Token::If => write!(f, "if"),
+            Token::Else => write!(f, "else"),
+            Token::While => write!(f, "while"),
+            Token::For => write!(f, "for"),
+            Token::In => write!(f, "in"),
+            Token::Break => write!(f, "break"),
+            Token::Continue => write!(f, "continue"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Divide => write!(f, "/"),
+            Token::Multiply => write!(f, "*"),
+            Token::Modulo => write!(f, "%"),
+            Token::Power => write!(f, "**"),
+            Token::BitAnd => write!(f, "&"),
+            Token::BitOr => write!(f, "|"),
+            Token::BitXor => write!(f, "^"),
+            Token::ShiftLeft => write!(f, "<<"),
+            Token::ShiftRight => write!(f, ">>"),
+            Token::BitNot => write!(f, "~"),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Equal => write!(f, "=="),
+            Token::NotEqual => write!(f, "!="),
+            Token::GreaterThanEqual => write!(f, ">="),
+            Token::LessThanEqual => write!(f, "<="),
+            Token::GreaterThan => write!(f, ">"),
+            Token::LessThan => write!(f, "<"),
+            Token::Not => write!(f, "!"),
+            Token::Function => write!(f, "fn"),
+            Token::Let => write!(f, "let"),
+            Token::Return => write!(f, "return"),
+            Token::Comma => write!(f, ","),
+            Token::Colon => write!(f, ":"),
+            Token::SemiColon => write!(f, ";"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::LBrace => write!(f, "{{"),
+            Token::RBrace => write!(f, "}}"),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+        }
+    }
+}
+
+impl Token {
+    /// Whether the token only matters to tools working on the source text
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, Token::Comment(_))
+    }
+}
+
+/// A token together with the source range it was lexed from
+#[derive(PartialEq, Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+impl SpannedToken {
+    pub fn new(token: Token, span: Span) -> Self {
+        Self { token, span }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 #[repr(C)]
 pub struct Tokens<'a> {
-    pub tok: &'a [Token],
+    pub tok: &'a [SpannedToken],
     pub start: usize,
     pub end: usize,
 }
 
 impl<'a> Tokens<'a> {
-    pub fn new(vec: &'a [Token]) -> Self {
+    pub fn new(vec: &'a [SpannedToken]) -> Self {
         Tokens {
             tok: vec,
             start: 0,
             end: vec.len(),
         }
     }
+
+    /// Span of the next token, or an empty span if the input is exhausted
+    pub fn span(&self) -> Span {
+        self.tok.first().map(|t| t.span).unwrap_or_default()
+    }
 }
 
-impl<'a> InputLength for Tokens<'a> {
+impl InputLength for Tokens<'_> {
     #[inline]
     fn input_len(&self) -> usize {
         self.tok.len()
     }
 }
 
-impl<'a> InputTake for Tokens<'a> {
+impl InputTake for Tokens<'_> {
     #[inline]
     fn take(&self, count: usize) -> Self {
         Tokens {
@@ -95,14 +226,14 @@ impl<'a> InputTake for Tokens<'a> {
     }
 }
 
-impl InputLength for Token {
+impl InputLength for SpannedToken {
     #[inline]
     fn input_len(&self) -> usize {
         1
     }
 }
 
-impl<'a> Slice<Range<usize>> for Tokens<'a> {
+impl Slice<Range<usize>> for Tokens<'_> {
     #[inline]
     fn slice(&self, range: Range<usize>) -> Self {
         Tokens {
@@ -113,21 +244,21 @@ impl<'a> Slice<Range<usize>> for Tokens<'a> {
     }
 }
 
-impl<'a> Slice<RangeTo<usize>> for Tokens<'a> {
+impl Slice<RangeTo<usize>> for Tokens<'_> {
     #[inline]
     fn slice(&self, range: RangeTo<usize>) -> Self {
         self.slice(0..range.end)
     }
 }
 
-impl<'a> Slice<RangeFrom<usize>> for Tokens<'a> {
+impl Slice<RangeFrom<usize>> for Tokens<'_> {
     #[inline]
     fn slice(&self, range: RangeFrom<usize>) -> Self {
         self.slice(range.start..self.end - self.start)
     }
 }
 
-impl<'a> Slice<RangeFull> for Tokens<'a> {
+impl Slice<RangeFull> for Tokens<'_> {
     #[inline]
     fn slice(&self, _: RangeFull) -> Self {
         Tokens {
@@ -139,16 +270,16 @@ impl<'a> Slice<RangeFull> for Tokens<'a> {
 }
 
 impl<'a> InputIter for Tokens<'a> {
-    type Item = &'a Token;
-    type Iter = Enumerate<::std::slice::Iter<'a, Token>>;
-    type IterElem = ::std::slice::Iter<'a, Token>;
+    type Item = &'a SpannedToken;
+    type Iter = Enumerate<::std::slice::Iter<'a, SpannedToken>>;
+    type IterElem = ::std::slice::Iter<'a, SpannedToken>;
 
     #[inline]
-    fn iter_indices(&self) -> Enumerate<::std::slice::Iter<'a, Token>> {
+    fn iter_indices(&self) -> Enumerate<::std::slice::Iter<'a, SpannedToken>> {
         self.tok.iter().enumerate()
     }
     #[inline]
-    fn iter_elements(&self) -> ::std::slice::Iter<'a, Token> {
+    fn iter_elements(&self) -> ::std::slice::Iter<'a, SpannedToken> {
         self.tok.iter()
     }
     #[inline]
@@ -3,8 +3,11 @@ extern crate nom;
 pub mod code;
 pub mod common;
 pub mod compiler;
+pub mod engine;
 pub mod error;
 pub mod evaluator;
+pub mod formatter;
 pub mod lexer;
 pub mod parser;
+pub mod span;
 pub mod vm;
@@ -1,14 +1,37 @@
+use num_bigint::BigInt;
+use std::fmt;
+
+use crate::span::Span;
+
 pub type Program = Vec<Stmt>;
 
+/// A statement and the source range it was parsed from. Spans don't take part
+/// in equality, so trees parsed from differently laid out sources compare equal.
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
 #[derive(PartialEq, Debug, Clone)]
-pub enum Stmt {
+pub enum StmtKind {
     LetStmt(Ident, Expr),
     ReturnStmt(Expr),
     ExprStmt(Expr),
+    BreakStmt,
+    ContinueStmt,
+}
+
+/// An expression and the source range it was parsed from. Like `Stmt`, the
+/// span is ignored by `PartialEq`.
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug, Clone)]
-pub enum Expr {
+pub enum ExprKind {
     IdentExpr(Ident),
     LitExpr(Literal),
     PrefixExpr(Prefix, Box<Expr>),
@@ -18,6 +41,17 @@ pub enum Expr {
         consequence: Program,
         alternative: Option<Program>,
     },
+    /// `while (cond) { body }`, which evaluates to null
+    WhileExpr {
+        cond: Box<Expr>,
+        body: Program,
+    },
+    /// `for (ident in iterable) { body }`, which evaluates to null
+    ForExpr {
+        ident: Ident,
+        iterable: Box<Expr>,
+        body: Program,
+    },
     FnExpr {
         params: Vec<Ident>,
         body: Program,
@@ -27,16 +61,24 @@ pub enum Expr {
         arguments: Vec<Expr>,
     },
     ArrayExpr(Vec<Expr>),
-    HashExpr(Vec<(Literal, Expr)>),
+    HashExpr(Vec<(Expr, Expr)>),
     IndexExpr {
         array: Box<Expr>,
         index: Box<Expr>,
     },
+    /// `target = value`, or `target op= value` when there is an `operator`
+    AssignExpr {
+        target: Box<Expr>,
+        operator: Option<Infix>,
+        value: Box<Expr>,
+    },
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Literal {
     IntLiteral(i64),
+    BigIntLiteral(BigInt),
+    FloatLiteral(f64),
     BoolLiteral(bool),
     StringLiteral(String),
 }
@@ -49,6 +91,7 @@ pub enum Prefix {
     PrefixPlus,
     PrefixMinus,
     Not,
+    BitNot,
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -57,6 +100,15 @@ pub enum Infix {
     Minus,
     Divide,
     Multiply,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
     Equal,
     NotEqual,
     GreaterThanEqual,
@@ -68,10 +120,336 @@ pub enum Infix {
 #[derive(PartialEq, PartialOrd, Debug, Clone)]
 pub enum Precedence {
     PLowest,
+    POr,
+    PAnd,
     PEquals,
     PLessGreater,
+    PBitOr,
+    PBitXor,
+    PBitAnd,
+    PShift,
     PSum,
     PProduct,
+    PPrefix,
+    PPower,
     PCall,
     PIndex,
 }
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl PartialEq for Stmt {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<StmtKind> for Stmt {
+    fn from(kind: StmtKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<ExprKind> for Expr {
+    fn from(kind: ExprKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
+impl Infix {
+    /// Binding power of the operator, as used by the parser
+    pub fn precedence(&self) -> Precedence {
+        match self {
+            Infix::Or => Precedence::POr,
+            Infix::And => Precedence::PAnd,
+            Infix::Equal | Infix::NotEqual => Precedence::PEquals,
+            Infix::GreaterThanEqual
+            | Infix::LessThanEqual
+            | Infix::GreaterThan
+            | Infix::LessThan => Precedence::PLessGreater,
+            Infix::BitOr => Precedence::PBitOr,
+            Infix::BitXor => Precedence::PBitXor,
+            Infix::BitAnd => Precedence::PBitAnd,
+            Infix::ShiftLeft | Infix::ShiftRight => Precedence::PShift,
+            Infix::Plus | Infix::Minus => Precedence::PSum,
+            Infix::Multiply | Infix::Divide | Infix::Modulo => Precedence::PProduct,
+            Infix::Power => Precedence::PPower,
+        }
+    }
+
+    /// `a ** b ** c` is `a ** (b ** c)`, every other operator groups to the
+    /// left
+    pub fn is_right_associative(&self) -> bool {
+        *self == Infix::Power
+    }
+
+    /// Whether `left` needs parentheses as the left operand of `self`
+    pub(crate) fn parenthesize_left(&self, left: &Expr) -> bool {
+        match self.is_right_associative() {
+            true => left.precedence() <= self.precedence(),
+            false => left.precedence() < self.precedence(),
+        }
+    }
+
+    /// Whether `right` needs parentheses as the right operand of `self`
+    pub(crate) fn parenthesize_right(&self, right: &Expr) -> bool {
+        match (&right.kind, self.is_right_associative()) {
+            // a prefix operator already takes in everything that binds
+            // tighter than itself, as in `2 ** -x ** 2`
+            (ExprKind::PrefixExpr(_, _), _) => false,
+            (_, true) => right.precedence() < self.precedence(),
+            (_, false) => right.precedence() <= self.precedence(),
+        }
+    }
+}
+
+impl Expr {
+    /// How tightly the expression holds together when it is the operand of
+    /// another one. Everything but operators is atomic.
+    pub(crate) fn precedence(&self) -> Precedence {
+        match &self.kind {
+            ExprKind::InfixExpr(op, _, _) => op.precedence(),
+            ExprKind::PrefixExpr(_, _) => Precedence::PPrefix,
+            ExprKind::AssignExpr { .. } => Precedence::PLowest,
+            _ => Precedence::PIndex,
+        }
+    }
+
+    /// For an expression that can be assigned to, the variable it is rooted
+    /// at and the indices leading from that variable to the assigned element
+    pub fn assignment_path(&self) -> Option<(&Ident, Vec<&Expr>)> {
+        match &self.kind {
+            ExprKind::IdentExpr(ident) => Some((ident, vec![])),
+            ExprKind::IndexExpr { array, index } => {
+                let (ident, mut path) = array.assignment_path()?;
+                path.push(index);
+                Some((ident, path))
+            }
+            _ => None,
+        }
+    }
+
+    /// Write `self` as an operand that needs at least `precedence`
+    fn fmt_operand(&self, f: &mut fmt::Formatter, precedence: Precedence) -> fmt::Result {
+        match self.precedence() < precedence {
+            true => write!(f, "({})", self),
+            false => write!(f, "{}", self),
+        }
+    }
+}
+
+/// Source for a whole program, one statement per line
+pub struct DisplayProgram<'a>(&'a [Stmt]);
+
+/// Print `program` back to canonical source
+pub fn display(program: &[Stmt]) -> DisplayProgram<'_> {
+    DisplayProgram(program)
+}
+
+impl fmt::Display for DisplayProgram<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for stmt in self.0 {
+            writeln!(f, "{}", stmt)?;
+        }
+        Ok(())
+    }
+}
+
+fn fmt_block(f: &mut fmt::Formatter, block: &[Stmt]) -> fmt::Result {
+    if block.is_empty() {
+        return write!(f, "{{}}");
+    }
+    write!(f, "{{")?;
+    for stmt in block {
+        write!(f, " {}", stmt)?;
+    }
+    write!(f, " }}")
+}
+
+fn fmt_list<T: fmt::Display>(f: &mut fmt::Formatter, items: &[T]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            StmtKind::LetStmt(name, value) => write!(f, "let {} = {};", name, value),
+            StmtKind::ReturnStmt(value) => write!(f, "return {};", value),
+            StmtKind::ExprStmt(value) => write!(f, "{};", value),
+            StmtKind::BreakStmt => write!(f, "break;"),
+            StmtKind::ContinueStmt => write!(f, "continue;"),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ExprKind::IdentExpr(ident) => write!(f, "{}", ident),
+            ExprKind::LitExpr(literal) => write!(f, "{}", literal),
+            ExprKind::PrefixExpr(op, right) => {
+                write!(f, "{}", op)?;
+                right.fmt_operand(f, Precedence::PPrefix)
+            }
+            ExprKind::InfixExpr(op, left, right) => {
+                match op.parenthesize_left(left) {
+                    true => write!(f, "({})", left)?,
+                    false => write!(f, "{}", left)?,
+                }
+                write!(f, " {} ", op)?;
+                match op.parenthesize_right(right) {
+                    true => write!(f, "({})", right),
+                    false => write!(f, "{}", right),
+                }
+            }
+            ExprKind::IfExpr {
+                cond,
+                consequence,
+                alternative,
+            } => {
+                write!(f, "if ({}) ", cond)?;
+                fmt_block(f, consequence)?;
+                if let Some(alternative) = alternative {
+                    write!(f, " else ")?;
+                    fmt_block(f, alternative)?;
+                }
+                Ok(())
+            }
+            ExprKind::WhileExpr { cond, body } => {
+                write!(f, "while ({}) ", cond)?;
+                fmt_block(f, body)
+            }
+            ExprKind::ForExpr {
+                ident,
+                iterable,
+                body,
+            } => {
+                write!(f, "for ({} in {}) ", ident, iterable)?;
+                fmt_block(f, body)
+            }
+            ExprKind::FnExpr { params, body } => {
+                write!(f, "fn(")?;
+                fmt_list(f, params)?;
+                write!(f, ") ")?;
+                fmt_block(f, body)
+            }
+            ExprKind::CallExpr {
+                function,
+                arguments,
+            } => {
+                function.fmt_operand(f, Precedence::PCall)?;
+                write!(f, "(")?;
+                fmt_list(f, arguments)?;
+                write!(f, ")")
+            }
+            ExprKind::ArrayExpr(items) => {
+                write!(f, "[")?;
+                fmt_list(f, items)?;
+                write!(f, "]")
+            }
+            ExprKind::HashExpr(pairs) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            ExprKind::IndexExpr { array, index } => {
+                array.fmt_operand(f, Precedence::PCall)?;
+                write!(f, "[{}]", index)
+            }
+            ExprKind::AssignExpr {
+                target,
+                operator,
+                value,
+            } => match operator {
+                Some(op) => write!(f, "{} {}= {}", target, op, value),
+                None => write!(f, "{} = {}", target, value),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Literal::IntLiteral(i) => write!(f, "{}", i),
+            Literal::BigIntLiteral(i) => write!(f, "{}", i),
+            Literal::FloatLiteral(x) => write!(f, "{:?}", x),
+            Literal::BoolLiteral(b) => write!(f, "{}", b),
+            Literal::StringLiteral(s) => {
+                write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            Prefix::PrefixPlus => "+",
+            Prefix::PrefixMinus => "-",
+            Prefix::Not => "!",
+            Prefix::BitNot => "~",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+impl fmt::Display for Infix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            Infix::Plus => "+",
+            Infix::Minus => "-",
+            Infix::Divide => "/",
+            Infix::Multiply => "*",
+            Infix::Modulo => "%",
+            Infix::Power => "**",
+            Infix::BitAnd => "&",
+            Infix::BitOr => "|",
+            Infix::BitXor => "^",
+            Infix::ShiftLeft => "<<",
+            Infix::ShiftRight => ">>",
+            Infix::And => "&&",
+            Infix::Or => "||",
+            Infix::Equal => "==",
+            Infix::NotEqual => "!=",
+            Infix::GreaterThanEqual => ">=",
+            Infix::LessThanEqual => "<=",
+            Infix::GreaterThan => ">",
+            Infix::LessThan => "<",
+        };
+        write!(f, "{}", op)
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+
+use nom::error::{ContextError, ErrorKind};
+
+use crate::lexer::token::{Token, Tokens};
+use crate::span::Span;
+
+/// Something the parser would have accepted at the position of an error
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    Token(Token),
+    Ident,
+    Literal,
+    Expression,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expected::Token(Token::EOF) => write!(f, "end of input"),
+            Expected::Token(t) => write!(f, "`{}`", t),
+            Expected::Ident => write!(f, "identifier"),
+            Expected::Literal => write!(f, "literal"),
+            Expected::Expression => write!(f, "expression"),
+        }
+    }
+}
+
+/// A syntax error: the tokens that would have been accepted, the token that
+/// was found instead, and the innermost construct being parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub expected: Vec<Expected>,
+    pub found: Token,
+    pub span: Span,
+    pub context: Option<&'static str>,
+}
+
+impl ParseError {
+    pub fn new(expected: Vec<Expected>, input: Tokens) -> Self {
+        let (found, span) = match input.tok.first() {
+            Some(t) => (t.token.clone(), t.span),
+            None => (Token::EOF, Span::default()),
+        };
+
+        Self {
+            expected,
+            found,
+            span,
+            context: None,
+        }
+    }
+
+    pub fn expected(expected: Expected, input: Tokens) -> Self {
+        Self::new(vec![expected], input)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.expected.split_last() {
+            None => write!(f, "unexpected ")?,
+            Some((last, [])) => write!(f, "expected {}, found ", last)?,
+            Some((last, init)) => {
+                let init = init.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                write!(f, "expected {} or {}, found ", init.join(", "), last)?
+            }
+        }
+
+        match &self.found {
+            Token::EOF => write!(f, "end of input")?,
+            Token::Illegal => write!(f, "illegal token")?,
+            t => write!(f, "`{}`", t)?,
+        }
+
+        match self.context {
+            Some(context) => write!(f, " in {}", context),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl<'a> nom::error::ParseError<Tokens<'a>> for ParseError {
+    fn from_error_kind(input: Tokens<'a>, _kind: ErrorKind) -> Self {
+        Self::new(vec![], input)
+    }
+
+    fn append(_input: Tokens<'a>, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    /// Keep the error that got furthest; merge the expectations of errors
+    /// raised at the same token.
+    fn or(mut self, other: Self) -> Self {
+        if other.span.start > self.span.start {
+            return other;
+        }
+        if other.span.start == self.span.start {
+            for e in other.expected {
+                if !self.expected.contains(&e) {
+                    self.expected.push(e);
+                }
+            }
+        }
+
+        self
+    }
+}
+
+impl<'a> ContextError<Tokens<'a>> for ParseError {
+    fn add_context(_input: Tokens<'a>, ctx: &'static str, mut other: Self) -> Self {
+        // the innermost construct is the most helpful one to report
+        other.context.get_or_insert(ctx);
+        other
+    }
+}
@@ -1,46 +1,67 @@
 use nom::*;
 
 pub mod ast;
+pub mod error;
 use crate::lexer::token::*;
 use crate::parser::ast::*;
+use crate::parser::error::*;
+use crate::span::Span;
 use nom::branch::*;
 use nom::bytes::complete::take;
-use nom::combinator::{map, opt, verify};
-use nom::error::{Error, ErrorKind};
-use nom::multi::many0;
+use nom::combinator::{cut, map, opt};
+use nom::error::{context, ParseError as _};
 use nom::sequence::*;
 use nom::Err;
 use std::result::Result::*;
 
+type ParseResult<'a, O> = IResult<Tokens<'a>, O, ParseError>;
+
 macro_rules! tag_token (
     ($func_name:ident, $tag: expr) => (
-        fn $func_name(tokens: Tokens) -> IResult<Tokens, Tokens> {
-            verify(take(1usize), |t: &Tokens| t.tok[0] == $tag)(tokens)
+        fn $func_name(tokens: Tokens) -> ParseResult<Tokens> {
+            match tokens.tok.first() {
+                Some(t) if t.token == $tag => Ok(tokens.take_split(1)),
+                _ => Err(Err::Error(ParseError::expected(Expected::Token($tag), tokens))),
+            }
         }
     )
   );
-fn parse_literal(input: Tokens) -> IResult<Tokens, Literal> {
+
+/// Span covering the tokens consumed between `input` and `rest`
+fn consumed_span(input: Tokens, rest: Tokens) -> Span {
+    match input.tok.len() - rest.tok.len() {
+        0 => input.span(),
+        n => input.tok[0].span.to(input.tok[n - 1].span),
+    }
+}
+
+/// Run `parser` and pair its output with the span of the tokens it consumed
+fn spanned<'a, O, F>(mut parser: F) -> impl FnMut(Tokens<'a>) -> ParseResult<'a, (O, Span)>
+where
+    F: FnMut(Tokens<'a>) -> ParseResult<'a, O>,
+{
+    move |input| {
+        let (rest, out) = parser(input)?;
+        Ok((rest, (out, consumed_span(input, rest))))
+    }
+}
+
+fn parse_literal(input: Tokens) -> ParseResult<Literal> {
     let (i1, t1) = take(1usize)(input)?;
-    if t1.tok.is_empty() {
-        Err(Err::Error(Error::new(input, ErrorKind::Tag)))
-    } else {
-        match t1.tok[0].clone() {
-            Token::IntLiteral(name) => Ok((i1, Literal::IntLiteral(name))),
-            Token::StringLiteral(s) => Ok((i1, Literal::StringLiteral(s))),
-            Token::BoolLiteral(b) => Ok((i1, Literal::BoolLiteral(b))),
-            _ => Err(Err::Error(Error::new(input, ErrorKind::Tag))),
-        }
+    match t1.tok[0].token.clone() {
+        Token::IntLiteral(name) => Ok((i1, Literal::IntLiteral(name))),
+        Token::BigIntLiteral(i) => Ok((i1, Literal::BigIntLiteral(i))),
+        Token::FloatLiteral(x) => Ok((i1, Literal::FloatLiteral(x))),
+        Token::StringLiteral(s) => Ok((i1, Literal::StringLiteral(s))),
+        Token::BoolLiteral(b) => Ok((i1, Literal::BoolLiteral(b))),
+        _ => Err(Err::Error(ParseError::expected(Expected::Literal, input))),
     }
 }
-fn parse_ident(input: Tokens) -> IResult<Tokens, Ident> {
+fn parse_ident(input: Tokens) -> ParseResult<Ident> {
     let (i1, t1) = take(1usize)(input)?;
-    if t1.tok.is_empty() {
-        Err(Err::Error(Error::new(input, ErrorKind::Tag)))
-    } else {
-        match t1.tok[0].clone() {
-            Token::Ident(name) => Ok((i1, Ident(name))),
-            _ => Err(Err::Error(Error::new(input, ErrorKind::Tag))),
-        }
+    match t1.tok[0].token.clone() {
+        Token::Ident(name) => Ok((i1, Ident(name))),
+        _ => Err(Err::Error(ParseError::expected(Expected::Ident, input))),
     }
 }
 tag_token!(let_tag, Token::Let);
@@ -58,72 +79,305 @@ tag_token!(colon_tag, Token::Colon);
 tag_token!(plus_tag, Token::Plus);
 tag_token!(minus_tag, Token::Minus);
 tag_token!(not_tag, Token::Not);
+tag_token!(bit_not_tag, Token::BitNot);
 tag_token!(if_tag, Token::If);
 tag_token!(else_tag, Token::Else);
+tag_token!(while_tag, Token::While);
+tag_token!(for_tag, Token::For);
+tag_token!(in_tag, Token::In);
+tag_token!(break_tag, Token::Break);
+tag_token!(continue_tag, Token::Continue);
 tag_token!(function_tag, Token::Function);
 tag_token!(eof_tag, Token::EOF);
 
 fn infix_op(t: &Token) -> (Precedence, Option<Infix>) {
-    match *t {
-        Token::Equal => (Precedence::PEquals, Some(Infix::Equal)),
-        Token::NotEqual => (Precedence::PEquals, Some(Infix::NotEqual)),
-        Token::LessThanEqual => (Precedence::PLessGreater, Some(Infix::LessThanEqual)),
-        Token::GreaterThanEqual => (Precedence::PLessGreater, Some(Infix::GreaterThanEqual)),
-        Token::LessThan => (Precedence::PLessGreater, Some(Infix::LessThan)),
-        Token::GreaterThan => (Precedence::PLessGreater, Some(Infix::GreaterThan)),
-        Token::Plus => (Precedence::PSum, Some(Infix::Plus)),
-        Token::Minus => (Precedence::PSum, Some(Infix::Minus)),
-        Token::Multiply => (Precedence::PProduct, Some(Infix::Multiply)),
-        Token::Divide => (Precedence::PProduct, Some(Infix::Divide)),
-        Token::LParen => (Precedence::PCall, None),
-        Token::LBracket => (Precedence::PIndex, None),
-        _ => (Precedence::PLowest, None),
+    let op = match *t {
+        Token::Equal => Infix::Equal,
+        Token::NotEqual => Infix::NotEqual,
+        Token::LessThanEqual => Infix::LessThanEqual,
+        Token::GreaterThanEqual => Infix::GreaterThanEqual,
+        Token::LessThan => Infix::LessThan,
+        Token::GreaterThan => Infix::GreaterThan,
+        Token::Plus => Infix::Plus,
+        Token::Minus => Infix::Minus,
+        Token::Multiply => Infix::Multiply,
+        Token::Divide => Infix::Divide,
+        Token::Modulo => Infix::Modulo,
+        Token::Power => Infix::Power,
+        Token::BitAnd => Infix::BitAnd,
+        Token::BitOr => Infix::BitOr,
+        Token::BitXor => Infix::BitXor,
+        Token::ShiftLeft => Infix::ShiftLeft,
+        Token::ShiftRight => Infix::ShiftRight,
+        Token::And => Infix::And,
+        Token::Or => Infix::Or,
+        Token::LParen => return (Precedence::PCall, None),
+        Token::LBracket => return (Precedence::PIndex, None),
+        _ => return (Precedence::PLowest, None),
+    };
+    (op.precedence(), Some(op))
+}
+
+/// For a token that assigns, the operator it combines the old value with
+fn assign_op(t: &Token) -> Option<Option<Infix>> {
+    let op = match *t {
+        Token::Assign => return Some(None),
+        Token::PlusAssign => Infix::Plus,
+        Token::MinusAssign => Infix::Minus,
+        Token::MultiplyAssign => Infix::Multiply,
+        Token::DivideAssign => Infix::Divide,
+        Token::ModuloAssign => Infix::Modulo,
+        Token::PowerAssign => Infix::Power,
+        Token::BitAndAssign => Infix::BitAnd,
+        Token::BitOrAssign => Infix::BitOr,
+        Token::BitXorAssign => Infix::BitXor,
+        Token::ShiftLeftAssign => Infix::ShiftLeft,
+        Token::ShiftRightAssign => Infix::ShiftRight,
+        _ => return None,
+    };
+    Some(Some(op))
+}
+
+/// Parse a whole program. A statement that fails to parse is dropped and
+/// its error recorded, and parsing resumes after the next statement boundary.
+fn parse_program(mut input: Tokens) -> (Program, Vec<ParseError>) {
+    let mut stmts = Vec::new();
+    let mut errors = Vec::new();
+
+    loop {
+        let end_err = match eof_tag(input) {
+            Ok(_) => break,
+            Err(Err::Error(e)) => e,
+            Err(_) => break,
+        };
+
+        let err = match parse_stmt(input) {
+            Ok((rest, stmt)) => {
+                stmts.push(stmt);
+                input = rest;
+                continue;
+            }
+            Err(Err::Error(e)) => e.or(end_err),
+            Err(Err::Failure(e)) => e,
+            Err(Err::Incomplete(_)) => ParseError::new(vec![], input),
+        };
+
+        input = synchronize(input, &err);
+        errors.push(err);
     }
+
+    (stmts, errors)
 }
 
-fn parse_program(input: Tokens) -> IResult<Tokens, Program> {
-    terminated(many0(parse_stmt), eof_tag)(input)
+/// Skip the rest of the statement starting at `input` in which `err` was
+/// raised: everything up to and including the next `;` outside of a block,
+/// or the `}` closing the block the error was in.
+fn synchronize<'a>(input: Tokens<'a>, err: &ParseError) -> Tokens<'a> {
+    let error_at = input
+        .tok
+        .iter()
+        .position(|t| t.span.start >= err.span.start)
+        .unwrap_or(0);
+
+    let mut depth = 0usize;
+    for t in &input.tok[..error_at] {
+        match t.token {
+            Token::LBrace => depth += 1,
+            Token::RBrace => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    let mut i = error_at;
+    while let Some(t) = input.tok.get(i) {
+        match t.token {
+            Token::EOF => break,
+            Token::LBrace => depth += 1,
+            Token::RBrace if depth > 1 => depth -= 1,
+            Token::RBrace => {
+                i += 1;
+                if let Some(Token::SemiColon) = input.tok.get(i).map(|t| &t.token) {
+                    i += 1;
+                }
+                break;
+            }
+            Token::SemiColon if depth == 0 => {
+                i += 1;
+                break;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    input.slice(i..)
 }
 
-fn parse_expr(input: Tokens) -> IResult<Tokens, Expr> {
-    parse_pratt_expr(input, Precedence::PLowest)
+/// Parse statements up to and including `end`. When a statement fails to
+/// parse, the error also lists what `end` would have accepted.
+fn parse_stmts_until<'a>(
+    mut input: Tokens<'a>,
+    end: fn(Tokens<'a>) -> ParseResult<'a, Tokens<'a>>,
+) -> ParseResult<'a, Program> {
+    let mut stmts = Vec::new();
+
+    loop {
+        let end_err = match end(input) {
+            Ok((rest, _)) => return Ok((rest, stmts)),
+            Err(Err::Error(e)) => e,
+            Err(e) => return Err(e),
+        };
+
+        match parse_stmt(input) {
+            Ok((rest, stmt)) => {
+                stmts.push(stmt);
+                input = rest;
+            }
+            Err(Err::Error(e)) => return Err(Err::Error(e.or(end_err))),
+            Err(e) => return Err(e),
+        }
+    }
 }
 
-fn parse_stmt(input: Tokens) -> IResult<Tokens, Stmt> {
-    alt((parse_let_stmt, parse_return_stmt, parse_expr_stmt))(input)
+/// Parse comma separated `item`s up to and including `close`. The last item
+/// may be followed by a comma.
+fn parse_list<'a, O>(
+    mut input: Tokens<'a>,
+    item: fn(Tokens<'a>) -> ParseResult<'a, O>,
+    close: fn(Tokens<'a>) -> ParseResult<'a, Tokens<'a>>,
+) -> ParseResult<'a, Vec<O>> {
+    let mut items = Vec::new();
+
+    loop {
+        let close_err = match close(input) {
+            Ok((rest, _)) => return Ok((rest, items)),
+            Err(Err::Error(e)) => e,
+            Err(e) => return Err(e),
+        };
+
+        if !items.is_empty() {
+            match comma_tag(input) {
+                Ok((rest, _)) => input = rest,
+                Err(Err::Error(e)) => return Err(Err::Failure(e.or(close_err))),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let close_err = match items.is_empty() {
+            true => close_err,
+            false => match close(input) {
+                Ok((rest, _)) => return Ok((rest, items)),
+                Err(Err::Error(e)) => e,
+                Err(e) => return Err(e),
+            },
+        };
+
+        match item(input) {
+            Ok((rest, o)) => {
+                items.push(o);
+                input = rest;
+            }
+            Err(Err::Error(e)) => return Err(Err::Failure(e.or(close_err))),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn parse_expr(input: Tokens) -> ParseResult<Expr> {
+    let (i1, target) = parse_pratt_expr(input, Precedence::PLowest)?;
+    let operator = match i1.tok.first().and_then(|t| assign_op(&t.token)) {
+        Some(operator) => operator,
+        None => return Ok((i1, target)),
+    };
+    if target.assignment_path().is_none() {
+        return Err(Err::Failure(ParseError::new(vec![], i1)));
+    }
+
+    // `a = b = c` assigns `c` to `b` and then to `a`
+    let (i2, value) = context("assignment", cut(parse_expr))(i1.slice(1..))?;
+    let span = target.span.to(value.span);
+    Ok((
+        i2,
+        Expr::new(
+            ExprKind::AssignExpr {
+                target: Box::new(target),
+                operator,
+                value: Box::new(value),
+            },
+            span,
+        ),
+    ))
+}
+
+fn parse_stmt(input: Tokens) -> ParseResult<Stmt> {
+    alt((
+        parse_let_stmt,
+        parse_return_stmt,
+        parse_break_stmt,
+        parse_continue_stmt,
+        parse_expr_stmt,
+    ))(input)
 }
 
-fn parse_let_stmt(input: Tokens) -> IResult<Tokens, Stmt> {
+fn parse_let_stmt(input: Tokens) -> ParseResult<Stmt> {
     map(
-        tuple((
+        spanned(preceded(
             let_tag,
-            parse_ident,
-            assign_tag,
-            parse_expr,
+            context(
+                "let statement",
+                cut(tuple((
+                    parse_ident,
+                    assign_tag,
+                    parse_expr,
+                    opt(semicolon_tag),
+                ))),
+            ),
+        )),
+        |((ident, _, expr, _), span)| Stmt::new(StmtKind::LetStmt(ident, expr), span),
+    )(input)
+}
+
+fn parse_return_stmt(input: Tokens) -> ParseResult<Stmt> {
+    map(
+        spanned(delimited(
+            return_tag,
+            context("return statement", cut(parse_expr)),
             opt(semicolon_tag),
         )),
-        |(_, ident, _, expr, _)| Stmt::LetStmt(ident, expr),
+        |(expr, span)| Stmt::new(StmtKind::ReturnStmt(expr), span),
     )(input)
 }
 
-fn parse_return_stmt(input: Tokens) -> IResult<Tokens, Stmt> {
+fn parse_break_stmt(input: Tokens) -> ParseResult<Stmt> {
     map(
-        delimited(return_tag, parse_expr, opt(semicolon_tag)),
-        Stmt::ReturnStmt,
+        spanned(terminated(break_tag, opt(semicolon_tag))),
+        |(_, span)| Stmt::new(StmtKind::BreakStmt, span),
     )(input)
 }
 
-fn parse_expr_stmt(input: Tokens) -> IResult<Tokens, Stmt> {
-    map(terminated(parse_expr, opt(semicolon_tag)), |expr| {
-        Stmt::ExprStmt(expr)
-    })(input)
+fn parse_continue_stmt(input: Tokens) -> ParseResult<Stmt> {
+    map(
+        spanned(terminated(continue_tag, opt(semicolon_tag))),
+        |(_, span)| Stmt::new(StmtKind::ContinueStmt, span),
+    )(input)
 }
 
-fn parse_block_stmt(input: Tokens) -> IResult<Tokens, Program> {
-    delimited(lbrace_tag, many0(parse_stmt), rbrace_tag)(input)
+fn parse_expr_stmt(input: Tokens) -> ParseResult<Stmt> {
+    map(
+        spanned(terminated(parse_expr, opt(semicolon_tag))),
+        |(expr, span)| Stmt::new(StmtKind::ExprStmt(expr), span),
+    )(input)
 }
 
-fn parse_atom_expr(input: Tokens) -> IResult<Tokens, Expr> {
+fn parse_block_stmt(input: Tokens) -> ParseResult<Program> {
+    let (i1, _) = lbrace_tag(input)?;
+    parse_stmts_until(i1, rbrace_tag).map_err(|e| match e {
+        Err::Error(e) => Err::Failure(e),
+        e => e,
+    })
+}
+
+fn parse_atom_expr(input: Tokens) -> ParseResult<Expr> {
     alt((
         parse_lit_expr,
         parse_ident_expr,
@@ -132,92 +386,92 @@ fn parse_atom_expr(input: Tokens) -> IResult<Tokens, Expr> {
         parse_array_expr,
         parse_hash_expr,
         parse_if_expr,
+        parse_while_expr,
+        parse_for_expr,
         parse_fn_expr,
     ))(input)
+    .map_err(|e| match e {
+        Err::Error(_) => Err::Error(ParseError::expected(Expected::Expression, input)),
+        e => e,
+    })
 }
 
-fn parse_paren_expr(input: Tokens) -> IResult<Tokens, Expr> {
-    delimited(lparen_tag, parse_expr, rparen_tag)(input)
-}
-
-fn parse_lit_expr(input: Tokens) -> IResult<Tokens, Expr> {
-    map(parse_literal, Expr::LitExpr)(input)
-}
-fn parse_ident_expr(input: Tokens) -> IResult<Tokens, Expr> {
-    map(parse_ident, Expr::IdentExpr)(input)
-}
-fn parse_comma_exprs(input: Tokens) -> IResult<Tokens, Expr> {
-    preceded(comma_tag, parse_expr)(input)
-}
-fn parse_exprs(input: Tokens) -> IResult<Tokens, Vec<Expr>> {
-    map(
-        pair(parse_expr, many0(parse_comma_exprs)),
-        |(first, second)| [&vec![first][..], &second[..]].concat(),
-    )(input)
-}
-fn empty_boxed_vec(input: Tokens) -> IResult<Tokens, Vec<Expr>> {
-    Ok((input, vec![]))
-}
-fn parse_array_expr(input: Tokens) -> IResult<Tokens, Expr> {
-    map(
-        delimited(
-            lbracket_tag,
-            alt((parse_exprs, empty_boxed_vec)),
-            rbracket_tag,
+fn parse_paren_expr(input: Tokens) -> ParseResult<Expr> {
+    preceded(
+        lparen_tag,
+        context(
+            "parenthesized expression",
+            cut(terminated(parse_expr, rparen_tag)),
         ),
-        Expr::ArrayExpr,
     )(input)
 }
-fn parse_hash_pair(input: Tokens) -> IResult<Tokens, (Literal, Expr)> {
-    separated_pair(parse_literal, colon_tag, parse_expr)(input)
+
+fn parse_lit_expr(input: Tokens) -> ParseResult<Expr> {
+    map(spanned(parse_literal), |(lit, span)| {
+        Expr::new(ExprKind::LitExpr(lit), span)
+    })(input)
 }
-fn parse_hash_comma_expr(input: Tokens) -> IResult<Tokens, (Literal, Expr)> {
-    preceded(comma_tag, parse_hash_pair)(input)
+fn parse_ident_expr(input: Tokens) -> ParseResult<Expr> {
+    map(spanned(parse_ident), |(ident, span)| {
+        Expr::new(ExprKind::IdentExpr(ident), span)
+    })(input)
 }
-
-fn parse_hash_pairs(input: Tokens) -> IResult<Tokens, Vec<(Literal, Expr)>> {
+fn parse_array_expr(input: Tokens) -> ParseResult<Expr> {
     map(
-        pair(parse_hash_pair, many0(parse_hash_comma_expr)),
-        |(first, second)| [&vec![first][..], &second[..]].concat(),
+        spanned(preceded(
+            lbracket_tag,
+            context("array literal", |i| parse_list(i, parse_expr, rbracket_tag)),
+        )),
+        |(exprs, span)| Expr::new(ExprKind::ArrayExpr(exprs), span),
     )(input)
 }
-fn empty_pairs(input: Tokens) -> IResult<Tokens, Vec<(Literal, Expr)>> {
-    Ok((input, vec![]))
+fn parse_hash_pair(input: Tokens) -> ParseResult<(Expr, Expr)> {
+    separated_pair(parse_expr, cut(colon_tag), cut(parse_expr))(input)
 }
-fn parse_hash_expr(input: Tokens) -> IResult<Tokens, Expr> {
+fn parse_hash_expr(input: Tokens) -> ParseResult<Expr> {
     map(
-        delimited(lbrace_tag, alt((parse_hash_pairs, empty_pairs)), rbrace_tag),
-        Expr::HashExpr,
+        spanned(preceded(
+            lbrace_tag,
+            context("hash literal", |i| {
+                parse_list(i, parse_hash_pair, rbrace_tag)
+            }),
+        )),
+        |(pairs, span)| Expr::new(ExprKind::HashExpr(pairs), span),
     )(input)
 }
 
-fn parse_prefix_expr(input: Tokens) -> IResult<Tokens, Expr> {
-    let (i1, t1) = alt((plus_tag, minus_tag, not_tag))(input)?;
-    if t1.tok.is_empty() {
-        Err(Err::Error(error_position!(input, ErrorKind::Tag)))
-    } else {
-        let (i2, e) = parse_atom_expr(i1)?;
-        match t1.tok[0].clone() {
-            Token::Plus => Ok((i2, Expr::PrefixExpr(Prefix::PrefixPlus, Box::new(e)))),
-            Token::Minus => Ok((i2, Expr::PrefixExpr(Prefix::PrefixMinus, Box::new(e)))),
-            Token::Not => Ok((i2, Expr::PrefixExpr(Prefix::Not, Box::new(e)))),
-            _ => Err(Err::Error(error_position!(input, ErrorKind::Tag))),
-        }
-    }
+fn parse_prefix_expr(input: Tokens) -> ParseResult<Expr> {
+    let (i1, t1) = alt((plus_tag, minus_tag, not_tag, bit_not_tag))(input)?;
+    // the operand extends over calls and indexing, so `-f(x)` is `-(f(x))`
+    let (i2, e) = context(
+        "prefix expression",
+        cut(|i| parse_pratt_expr(i, Precedence::PPrefix)),
+    )(i1)?;
+    let prefix = match t1.tok[0].token {
+        Token::Plus => Prefix::PrefixPlus,
+        Token::Minus => Prefix::PrefixMinus,
+        Token::BitNot => Prefix::BitNot,
+        _ => Prefix::Not,
+    };
+    let span = consumed_span(input, i2);
+    Ok((
+        i2,
+        Expr::new(ExprKind::PrefixExpr(prefix, Box::new(e)), span),
+    ))
 }
 
-fn parse_pratt_expr(input: Tokens, precedence: Precedence) -> IResult<Tokens, Expr> {
+fn parse_pratt_expr(input: Tokens, precedence: Precedence) -> ParseResult<Expr> {
     let (i1, left) = parse_atom_expr(input)?;
     go_parse_pratt_expr(i1, precedence, left)
 }
 
-fn go_parse_pratt_expr(input: Tokens, precedence: Precedence, left: Expr) -> IResult<Tokens, Expr> {
+fn go_parse_pratt_expr(input: Tokens, precedence: Precedence, left: Expr) -> ParseResult<Expr> {
     let (i1, t1) = take(1usize)(input)?;
 
     if t1.tok.is_empty() {
         Ok((i1, left))
     } else {
-        let preview = &t1.tok[0];
+        let preview = &t1.tok[0].token;
         let p = infix_op(preview);
         match p {
             (Precedence::PCall, _) if precedence < Precedence::PCall => {
@@ -237,88 +491,194 @@ fn go_parse_pratt_expr(input: Tokens, precedence: Precedence, left: Expr) -> IRe
     }
 }
 
-fn parse_infix_expr(input: Tokens, left: Expr) -> IResult<Tokens, Expr> {
+fn parse_infix_expr(input: Tokens, left: Expr) -> ParseResult<Expr> {
     let (i1, t1) = take(1usize)(input)?;
-    if t1.tok.is_empty() {
-        Err(Err::Error(error_position!(input, ErrorKind::Tag)))
-    } else {
-        let next = &t1.tok[0];
-        let (precedence, maybe_op) = infix_op(next);
-        match maybe_op {
-            None => Err(Err::Error(error_position!(input, ErrorKind::Tag))),
-            Some(op) => {
-                let (i2, right) = parse_pratt_expr(i1, precedence)?;
-                Ok((i2, Expr::InfixExpr(op, Box::new(left), Box::new(right))))
-            }
+    let next = &t1.tok[0].token;
+    let (precedence, maybe_op) = infix_op(next);
+    match maybe_op {
+        None => Err(Err::Error(ParseError::new(vec![], input))),
+        Some(op) => {
+            // the right operand of `**` may itself use `**`, but nothing
+            // looser
+            let precedence = match op.is_right_associative() {
+                true => Precedence::PPrefix,
+                false => precedence,
+            };
+            let (i2, right) = context(
+                "infix expression",
+                cut(|i| parse_pratt_expr(i, precedence.clone())),
+            )(i1)?;
+            let span = left.span.to(right.span);
+            Ok((
+                i2,
+                Expr::new(
+                    ExprKind::InfixExpr(op, Box::new(left), Box::new(right)),
+                    span,
+                ),
+            ))
         }
     }
 }
 
-fn parse_call_expr(input: Tokens, fn_handle: Expr) -> IResult<Tokens, Expr> {
+fn parse_call_expr(input: Tokens, fn_handle: Expr) -> ParseResult<Expr> {
     map(
-        delimited(lparen_tag, alt((parse_exprs, empty_boxed_vec)), rparen_tag),
-        |e| Expr::CallExpr {
-            function: Box::new(fn_handle.clone()),
-            arguments: e,
+        spanned(preceded(
+            lparen_tag,
+            context("argument list of call", |i| {
+                parse_list(i, parse_expr, rparen_tag)
+            }),
+        )),
+        |(e, span)| {
+            Expr::new(
+                ExprKind::CallExpr {
+                    function: Box::new(fn_handle.clone()),
+                    arguments: e,
+                },
+                fn_handle.span.to(span),
+            )
         },
     )(input)
 }
 
-fn parse_index_expr(input: Tokens, arr: Expr) -> IResult<Tokens, Expr> {
-    map(delimited(lbracket_tag, parse_expr, rbracket_tag), |idx| {
-        Expr::IndexExpr {
-            array: Box::new(arr.clone()),
-            index: Box::new(idx),
-        }
-    })(input)
+fn parse_index_expr(input: Tokens, arr: Expr) -> ParseResult<Expr> {
+    map(
+        spanned(preceded(
+            lbracket_tag,
+            context(
+                "index expression",
+                cut(terminated(parse_expr, rbracket_tag)),
+            ),
+        )),
+        |(idx, span)| {
+            Expr::new(
+                ExprKind::IndexExpr {
+                    array: Box::new(arr.clone()),
+                    index: Box::new(idx),
+                },
+                arr.span.to(span),
+            )
+        },
+    )(input)
 }
 
-fn parse_if_expr(input: Tokens) -> IResult<Tokens, Expr> {
+fn parse_if_expr(input: Tokens) -> ParseResult<Expr> {
     map(
-        tuple((
+        spanned(preceded(
             if_tag,
-            lparen_tag,
-            parse_expr,
-            rparen_tag,
-            parse_block_stmt,
-            parse_else_expr,
+            context(
+                "if expression",
+                cut(tuple((
+                    context(
+                        "condition of if",
+                        delimited(lparen_tag, parse_expr, rparen_tag),
+                    ),
+                    parse_block_stmt,
+                    parse_else_expr,
+                ))),
+            ),
         )),
-        |(_, _, expr, _, c, a)| Expr::IfExpr {
-            cond: Box::new(expr),
-            consequence: c,
-            alternative: a,
+        |((expr, c, a), span)| {
+            Expr::new(
+                ExprKind::IfExpr {
+                    cond: Box::new(expr),
+                    consequence: c,
+                    alternative: a,
+                },
+                span,
+            )
         },
     )(input)
 }
-fn parse_else_expr(input: Tokens) -> IResult<Tokens, Option<Program>> {
-    opt(preceded(else_tag, parse_block_stmt))(input)
+fn parse_else_expr(input: Tokens) -> ParseResult<Option<Program>> {
+    opt(preceded(else_tag, cut(parse_block_stmt)))(input)
 }
-fn empty_params(input: Tokens) -> IResult<Tokens, Vec<Ident>> {
-    Ok((input, vec![]))
+fn parse_while_expr(input: Tokens) -> ParseResult<Expr> {
+    map(
+        spanned(preceded(
+            while_tag,
+            context(
+                "while loop",
+                cut(pair(
+                    context(
+                        "condition of while",
+                        delimited(lparen_tag, parse_expr, rparen_tag),
+                    ),
+                    parse_block_stmt,
+                )),
+            ),
+        )),
+        |((cond, body), span)| {
+            Expr::new(
+                ExprKind::WhileExpr {
+                    cond: Box::new(cond),
+                    body,
+                },
+                span,
+            )
+        },
+    )(input)
 }
-fn parse_fn_expr(input: Tokens) -> IResult<Tokens, Expr> {
+fn parse_for_expr(input: Tokens) -> ParseResult<Expr> {
     map(
-        tuple((
-            function_tag,
-            lparen_tag,
-            alt((parse_params, empty_params)),
-            rparen_tag,
-            parse_block_stmt,
+        spanned(preceded(
+            for_tag,
+            context(
+                "for loop",
+                cut(pair(
+                    delimited(
+                        lparen_tag,
+                        separated_pair(parse_ident, in_tag, parse_expr),
+                        rparen_tag,
+                    ),
+                    parse_block_stmt,
+                )),
+            ),
         )),
-        |(_, _, p, _, b)| Expr::FnExpr { params: p, body: b },
+        |(((ident, iterable), body), span)| {
+            Expr::new(
+                ExprKind::ForExpr {
+                    ident,
+                    iterable: Box::new(iterable),
+                    body,
+                },
+                span,
+            )
+        },
     )(input)
 }
-fn parse_params(input: Tokens) -> IResult<Tokens, Vec<Ident>> {
+fn parse_fn_expr(input: Tokens) -> ParseResult<Expr> {
     map(
-        pair(parse_ident, many0(preceded(comma_tag, parse_ident))),
-        |(p, ps)| [&vec![p][..], &ps[..]].concat(),
+        spanned(preceded(
+            function_tag,
+            context(
+                "fn expression",
+                cut(pair(
+                    context(
+                        "parameter list of fn",
+                        preceded(lparen_tag, |i| parse_list(i, parse_ident, rparen_tag)),
+                    ),
+                    parse_block_stmt,
+                )),
+            ),
+        )),
+        |((p, b), span)| Expr::new(ExprKind::FnExpr { params: p, body: b }, span),
     )(input)
 }
 
 pub struct Parser;
 
 impl Parser {
-    pub fn parse_tokens(tokens: Tokens) -> IResult<Tokens, Program> {
+    pub fn parse_tokens(tokens: Tokens) -> Result<Program, ParseError> {
+        let (program, errors) = parse_program(tokens);
+        match errors.into_iter().next() {
+            None => Ok(program),
+            Some(e) => Err(e),
+        }
+    }
+
+    /// Parse as much of the program as possible, returning the statements
+    /// that parsed together with every syntax error found.
+    pub fn parse_recovering(tokens: Tokens) -> (Program, Vec<ParseError>) {
         parse_program(tokens)
     }
 }
@@ -331,18 +691,18 @@ mod tests {
     fn assert_input_with_program(input: &[u8], expected_results: Program) {
         let (_, r) = Lexer::lex_tokens(input).unwrap();
         let tokens = Tokens::new(&r);
-        let (_, result) = Parser::parse_tokens(tokens).unwrap();
+        let result = Parser::parse_tokens(tokens).unwrap();
         assert_eq!(result, expected_results);
     }
 
     fn compare_inputs(input: &[u8], input2: &[u8]) {
         let (_, r) = Lexer::lex_tokens(input).unwrap();
         let tokens = Tokens::new(&r);
-        let (_, result) = Parser::parse_tokens(tokens).unwrap();
+        let result = Parser::parse_tokens(tokens).unwrap();
 
         let (_, r) = Lexer::lex_tokens(input2).unwrap();
         let tokens = Tokens::new(&r);
-        let (_, expected_results) = Parser::parse_tokens(tokens).unwrap();
+        let expected_results = Parser::parse_tokens(tokens).unwrap();
 
         assert_eq!(result, expected_results);
     }
@@ -362,19 +722,26 @@ mod tests {
         .as_bytes();
 
         let program: Program = vec![
-            Stmt::LetStmt(Ident("x".to_owned()), Expr::LitExpr(Literal::IntLiteral(5))),
-            Stmt::LetStmt(
+            StmtKind::LetStmt(
+                Ident("x".to_owned()),
+                ExprKind::LitExpr(Literal::IntLiteral(5)).into(),
+            )
+            .into(),
+            StmtKind::LetStmt(
                 Ident("y".to_owned()),
-                Expr::LitExpr(Literal::IntLiteral(10)),
-            ),
-            Stmt::LetStmt(
+                ExprKind::LitExpr(Literal::IntLiteral(10)).into(),
+            )
+            .into(),
+            StmtKind::LetStmt(
                 Ident("foobar".to_owned()),
-                Expr::LitExpr(Literal::IntLiteral(838383)),
-            ),
-            Stmt::LetStmt(
+                ExprKind::LitExpr(Literal::IntLiteral(838383)).into(),
+            )
+            .into(),
+            StmtKind::LetStmt(
                 Ident("boo".to_owned()),
-                Expr::LitExpr(Literal::BoolLiteral(true)),
-            ),
+                ExprKind::LitExpr(Literal::BoolLiteral(true)).into(),
+            )
+            .into(),
         ];
 
         assert_input_with_program(input, program);
@@ -390,10 +757,10 @@ mod tests {
         .as_bytes();
 
         let program: Program = vec![
-            Stmt::ReturnStmt(Expr::LitExpr(Literal::IntLiteral(5))),
-            Stmt::ReturnStmt(Expr::LitExpr(Literal::IntLiteral(10))),
-            Stmt::ReturnStmt(Expr::LitExpr(Literal::IntLiteral(838383))),
-            Stmt::ReturnStmt(Expr::LitExpr(Literal::BoolLiteral(true))),
+            StmtKind::ReturnStmt(ExprKind::LitExpr(Literal::IntLiteral(5)).into()).into(),
+            StmtKind::ReturnStmt(ExprKind::LitExpr(Literal::IntLiteral(10)).into()).into(),
+            StmtKind::ReturnStmt(ExprKind::LitExpr(Literal::IntLiteral(838383)).into()).into(),
+            StmtKind::ReturnStmt(ExprKind::LitExpr(Literal::BoolLiteral(true)).into()).into(),
         ];
 
         assert_input_with_program(input, program);
@@ -410,14 +777,19 @@ mod tests {
         .as_bytes();
 
         let program: Program = vec![
-            Stmt::LetStmt(Ident("x".to_owned()), Expr::LitExpr(Literal::IntLiteral(5))),
-            Stmt::ReturnStmt(Expr::LitExpr(Literal::IntLiteral(10))),
-            Stmt::ExprStmt(Expr::LitExpr(Literal::IntLiteral(15))),
-            Stmt::LetStmt(
+            StmtKind::LetStmt(
+                Ident("x".to_owned()),
+                ExprKind::LitExpr(Literal::IntLiteral(5)).into(),
+            )
+            .into(),
+            StmtKind::ReturnStmt(ExprKind::LitExpr(Literal::IntLiteral(10)).into()).into(),
+            StmtKind::ExprStmt(ExprKind::LitExpr(Literal::IntLiteral(15)).into()).into(),
+            StmtKind::LetStmt(
                 Ident("y".to_owned()),
-                Expr::LitExpr(Literal::IntLiteral(20)),
-            ),
-            Stmt::ReturnStmt(Expr::LitExpr(Literal::BoolLiteral(false))),
+                ExprKind::LitExpr(Literal::IntLiteral(20)).into(),
+            )
+            .into(),
+            StmtKind::ReturnStmt(ExprKind::LitExpr(Literal::BoolLiteral(false)).into()).into(),
         ];
 
         assert_input_with_program(input, program);
@@ -431,8 +803,8 @@ mod tests {
         .as_bytes();
 
         let program: Program = vec![
-            Stmt::ExprStmt(Expr::IdentExpr(Ident("foobar".to_owned()))),
-            Stmt::ExprStmt(Expr::IdentExpr(Ident("foobar".to_owned()))),
+            StmtKind::ExprStmt(ExprKind::IdentExpr(Ident("foobar".to_owned())).into()).into(),
+            StmtKind::ExprStmt(ExprKind::IdentExpr(Ident("foobar".to_owned())).into()).into(),
         ];
 
         assert_input_with_program(input, program);
@@ -447,18 +819,30 @@ mod tests {
         .as_bytes();
 
         let program: Program = vec![
-            Stmt::ExprStmt(Expr::PrefixExpr(
-                Prefix::PrefixMinus,
-                Box::new(Expr::IdentExpr(Ident("foobar".to_owned()))),
-            )),
-            Stmt::ExprStmt(Expr::PrefixExpr(
-                Prefix::PrefixPlus,
-                Box::new(Expr::LitExpr(Literal::IntLiteral(10))),
-            )),
-            Stmt::ExprStmt(Expr::PrefixExpr(
-                Prefix::Not,
-                Box::new(Expr::LitExpr(Literal::BoolLiteral(true))),
-            )),
+            StmtKind::ExprStmt(
+                ExprKind::PrefixExpr(
+                    Prefix::PrefixMinus,
+                    Box::new(ExprKind::IdentExpr(Ident("foobar".to_owned())).into()),
+                )
+                .into(),
+            )
+            .into(),
+            StmtKind::ExprStmt(
+                ExprKind::PrefixExpr(
+                    Prefix::PrefixPlus,
+                    Box::new(ExprKind::LitExpr(Literal::IntLiteral(10)).into()),
+                )
+                .into(),
+            )
+            .into(),
+            StmtKind::ExprStmt(
+                ExprKind::PrefixExpr(
+                    Prefix::Not,
+                    Box::new(ExprKind::LitExpr(Literal::BoolLiteral(true)).into()),
+                )
+                .into(),
+            )
+            .into(),
         ];
 
         assert_input_with_program(input, program);
@@ -473,18 +857,30 @@ mod tests {
         .as_bytes();
 
         let program: Program = vec![
-            Stmt::ExprStmt(Expr::PrefixExpr(
-                Prefix::PrefixMinus,
-                Box::new(Expr::IdentExpr(Ident("foobar".to_owned()))),
-            )),
-            Stmt::ExprStmt(Expr::PrefixExpr(
-                Prefix::PrefixPlus,
-                Box::new(Expr::LitExpr(Literal::IntLiteral(10))),
-            )),
-            Stmt::ExprStmt(Expr::PrefixExpr(
-                Prefix::Not,
-                Box::new(Expr::LitExpr(Literal::BoolLiteral(true))),
-            )),
+            StmtKind::ExprStmt(
+                ExprKind::PrefixExpr(
+                    Prefix::PrefixMinus,
+                    Box::new(ExprKind::IdentExpr(Ident("foobar".to_owned())).into()),
+                )
+                .into(),
+            )
+            .into(),
+            StmtKind::ExprStmt(
+                ExprKind::PrefixExpr(
+                    Prefix::PrefixPlus,
+                    Box::new(ExprKind::LitExpr(Literal::IntLiteral(10)).into()),
+                )
+                .into(),
+            )
+            .into(),
+            StmtKind::ExprStmt(
+                ExprKind::PrefixExpr(
+                    Prefix::Not,
+                    Box::new(ExprKind::LitExpr(Literal::BoolLiteral(true)).into()),
+                )
+                .into(),
+            )
+            .into(),
         ];
 
         assert_input_with_program(input, program);
@@ -494,21 +890,29 @@ mod tests {
     fn infix_expr() {
         let input = "10 + 20".as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::InfixExpr(
-            Infix::Plus,
-            Box::new(Expr::LitExpr(Literal::IntLiteral(10))),
-            Box::new(Expr::LitExpr(Literal::IntLiteral(20))),
-        ))];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::InfixExpr(
+                Infix::Plus,
+                Box::new(ExprKind::LitExpr(Literal::IntLiteral(10)).into()),
+                Box::new(ExprKind::LitExpr(Literal::IntLiteral(20)).into()),
+            )
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
         let input = "10 * 20".as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::InfixExpr(
-            Infix::Multiply,
-            Box::new(Expr::LitExpr(Literal::IntLiteral(10))),
-            Box::new(Expr::LitExpr(Literal::IntLiteral(20))),
-        ))];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::InfixExpr(
+                Infix::Multiply,
+                Box::new(ExprKind::LitExpr(Literal::IntLiteral(10)).into()),
+                Box::new(ExprKind::LitExpr(Literal::IntLiteral(20)).into()),
+            )
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
@@ -520,26 +924,42 @@ mod tests {
 
         let input = "10 + 5 / -20 - (x + x)".as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::InfixExpr(
-            Infix::Minus,
-            Box::new(Expr::InfixExpr(
-                Infix::Plus,
-                Box::new(Expr::LitExpr(Literal::IntLiteral(10))),
-                Box::new(Expr::InfixExpr(
-                    Infix::Divide,
-                    Box::new(Expr::LitExpr(Literal::IntLiteral(5))),
-                    Box::new(Expr::PrefixExpr(
-                        Prefix::PrefixMinus,
-                        Box::new(Expr::LitExpr(Literal::IntLiteral(20))),
-                    )),
-                )),
-            )),
-            Box::new(Expr::InfixExpr(
-                Infix::Plus,
-                Box::new(Expr::IdentExpr(Ident("x".to_owned()))),
-                Box::new(Expr::IdentExpr(Ident("x".to_owned()))),
-            )),
-        ))];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::InfixExpr(
+                Infix::Minus,
+                Box::new(
+                    ExprKind::InfixExpr(
+                        Infix::Plus,
+                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(10)).into()),
+                        Box::new(
+                            ExprKind::InfixExpr(
+                                Infix::Divide,
+                                Box::new(ExprKind::LitExpr(Literal::IntLiteral(5)).into()),
+                                Box::new(
+                                    ExprKind::PrefixExpr(
+                                        Prefix::PrefixMinus,
+                                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(20)).into()),
+                                    )
+                                    .into(),
+                                ),
+                            )
+                            .into(),
+                        ),
+                    )
+                    .into(),
+                ),
+                Box::new(
+                    ExprKind::InfixExpr(
+                        Infix::Plus,
+                        Box::new(ExprKind::IdentExpr(Ident("x".to_owned())).into()),
+                        Box::new(ExprKind::IdentExpr(Ident("x".to_owned())).into()),
+                    )
+                    .into(),
+                ),
+            )
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
     }
@@ -611,35 +1031,82 @@ mod tests {
         let input2 = "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))".as_bytes();
 
         compare_inputs(input, input2);
+
+        let input = "-f(x) * !a[0]".as_bytes();
+
+        let input2 = "((-(f(x))) * (!(a[0])))".as_bytes();
+
+        compare_inputs(input, input2);
+
+        let input = "-2 ** 2 ** 3 % 4".as_bytes();
+
+        let input2 = "((-(2 ** (2 ** 3))) % 4)".as_bytes();
+
+        compare_inputs(input, input2);
+
+        let input = "a | b ^ c & d << e + f == ~g".as_bytes();
+
+        let input2 = "((a | (b ^ (c & (d << (e + f))))) == (~g))".as_bytes();
+
+        compare_inputs(input, input2);
+
+        let input = "a || b && c == d || !e".as_bytes();
+
+        let input2 = "((a || (b && (c == d))) || (!e))".as_bytes();
+
+        compare_inputs(input, input2);
     }
 
     #[test]
     fn if_expr() {
         let input = "if (x < y) { x }".as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::IfExpr {
-            cond: Box::new(Expr::InfixExpr(
-                Infix::LessThan,
-                Box::new(Expr::IdentExpr(Ident("x".to_owned()))),
-                Box::new(Expr::IdentExpr(Ident("y".to_owned()))),
-            )),
-            consequence: vec![Stmt::ExprStmt(Expr::IdentExpr(Ident("x".to_owned())))],
-            alternative: None,
-        })];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::IfExpr {
+                cond: Box::new(
+                    ExprKind::InfixExpr(
+                        Infix::LessThan,
+                        Box::new(ExprKind::IdentExpr(Ident("x".to_owned())).into()),
+                        Box::new(ExprKind::IdentExpr(Ident("y".to_owned())).into()),
+                    )
+                    .into(),
+                ),
+                consequence: vec![StmtKind::ExprStmt(
+                    ExprKind::IdentExpr(Ident("x".to_owned())).into(),
+                )
+                .into()],
+                alternative: None,
+            }
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
         let input = "if (x < y) { x } else { y }".as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::IfExpr {
-            cond: Box::new(Expr::InfixExpr(
-                Infix::LessThan,
-                Box::new(Expr::IdentExpr(Ident("x".to_owned()))),
-                Box::new(Expr::IdentExpr(Ident("y".to_owned()))),
-            )),
-            consequence: vec![Stmt::ExprStmt(Expr::IdentExpr(Ident("x".to_owned())))],
-            alternative: Some(vec![Stmt::ExprStmt(Expr::IdentExpr(Ident("y".to_owned())))]),
-        })];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::IfExpr {
+                cond: Box::new(
+                    ExprKind::InfixExpr(
+                        Infix::LessThan,
+                        Box::new(ExprKind::IdentExpr(Ident("x".to_owned())).into()),
+                        Box::new(ExprKind::IdentExpr(Ident("y".to_owned())).into()),
+                    )
+                    .into(),
+                ),
+                consequence: vec![StmtKind::ExprStmt(
+                    ExprKind::IdentExpr(Ident("x".to_owned())).into(),
+                )
+                .into()],
+                alternative: Some(vec![StmtKind::ExprStmt(
+                    ExprKind::IdentExpr(Ident("y".to_owned())).into(),
+                )
+                .into()]),
+            }
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
     }
@@ -652,14 +1119,22 @@ mod tests {
             "
         .as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::FnExpr {
-            params: vec![],
-            body: vec![Stmt::ReturnStmt(Expr::InfixExpr(
-                Infix::Plus,
-                Box::new(Expr::IdentExpr(Ident("foobar".to_owned()))),
-                Box::new(Expr::IdentExpr(Ident("barfoo".to_owned()))),
-            ))],
-        })];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::FnExpr {
+                params: vec![],
+                body: vec![StmtKind::ReturnStmt(
+                    ExprKind::InfixExpr(
+                        Infix::Plus,
+                        Box::new(ExprKind::IdentExpr(Ident("foobar".to_owned())).into()),
+                        Box::new(ExprKind::IdentExpr(Ident("barfoo".to_owned())).into()),
+                    )
+                    .into(),
+                )
+                .into()],
+            }
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
@@ -669,14 +1144,22 @@ mod tests {
             "
         .as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::FnExpr {
-            params: vec![Ident("x".to_owned()), Ident("y".to_owned())],
-            body: vec![Stmt::ReturnStmt(Expr::InfixExpr(
-                Infix::Plus,
-                Box::new(Expr::IdentExpr(Ident("x".to_owned()))),
-                Box::new(Expr::IdentExpr(Ident("y".to_owned()))),
-            ))],
-        })];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::FnExpr {
+                params: vec![Ident("x".to_owned()), Ident("y".to_owned())],
+                body: vec![StmtKind::ReturnStmt(
+                    ExprKind::InfixExpr(
+                        Infix::Plus,
+                        Box::new(ExprKind::IdentExpr(Ident("x".to_owned())).into()),
+                        Box::new(ExprKind::IdentExpr(Ident("y".to_owned())).into()),
+                    )
+                    .into(),
+                )
+                .into()],
+            }
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
@@ -686,22 +1169,34 @@ mod tests {
             "
         .as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::FnExpr {
-            params: vec![],
-            body: vec![Stmt::ReturnStmt(Expr::FnExpr {
-                params: vec![
-                    Ident("x".to_owned()),
-                    Ident("y".to_owned()),
-                    Ident("z".to_owned()),
-                    Ident("zz".to_owned()),
-                ],
-                body: vec![Stmt::ReturnStmt(Expr::InfixExpr(
-                    Infix::GreaterThanEqual,
-                    Box::new(Expr::IdentExpr(Ident("x".to_owned()))),
-                    Box::new(Expr::IdentExpr(Ident("y".to_owned()))),
-                ))],
-            })],
-        })];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::FnExpr {
+                params: vec![],
+                body: vec![StmtKind::ReturnStmt(
+                    ExprKind::FnExpr {
+                        params: vec![
+                            Ident("x".to_owned()),
+                            Ident("y".to_owned()),
+                            Ident("z".to_owned()),
+                            Ident("zz".to_owned()),
+                        ],
+                        body: vec![StmtKind::ReturnStmt(
+                            ExprKind::InfixExpr(
+                                Infix::GreaterThanEqual,
+                                Box::new(ExprKind::IdentExpr(Ident("x".to_owned())).into()),
+                                Box::new(ExprKind::IdentExpr(Ident("y".to_owned())).into()),
+                            )
+                            .into(),
+                        )
+                        .into()],
+                    }
+                    .into(),
+                )
+                .into()],
+            }
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
     }
@@ -715,59 +1210,85 @@ mod tests {
         .as_bytes();
 
         let program: Program = vec![
-            Stmt::ExprStmt(Expr::CallExpr {
-                function: Box::new(Expr::IdentExpr(Ident("add".to_owned()))),
-                arguments: vec![
-                    Expr::LitExpr(Literal::IntLiteral(2)),
-                    Expr::LitExpr(Literal::IntLiteral(3)),
-                ],
-            }),
-            Stmt::ExprStmt(Expr::CallExpr {
-                function: Box::new(Expr::IdentExpr(Ident("add".to_owned()))),
-                arguments: vec![
-                    Expr::IdentExpr(Ident("a".to_owned())),
-                    Expr::IdentExpr(Ident("b".to_owned())),
-                    Expr::LitExpr(Literal::IntLiteral(1)),
-                    Expr::InfixExpr(
-                        Infix::Multiply,
-                        Box::new(Expr::LitExpr(Literal::IntLiteral(2))),
-                        Box::new(Expr::LitExpr(Literal::IntLiteral(3))),
-                    ),
-                    Expr::CallExpr {
-                        function: Box::new(Expr::IdentExpr(Ident("other".to_owned()))),
-                        arguments: vec![Expr::InfixExpr(
-                            Infix::Plus,
-                            Box::new(Expr::LitExpr(Literal::IntLiteral(4))),
-                            Box::new(Expr::LitExpr(Literal::IntLiteral(5))),
-                        )],
-                    },
-                    Expr::CallExpr {
-                        function: Box::new(Expr::IdentExpr(Ident("add".to_owned()))),
-                        arguments: vec![
-                            Expr::LitExpr(Literal::IntLiteral(6)),
-                            Expr::InfixExpr(
-                                Infix::Multiply,
-                                Box::new(Expr::LitExpr(Literal::IntLiteral(7))),
-                                Box::new(Expr::LitExpr(Literal::IntLiteral(8))),
+            StmtKind::ExprStmt(
+                ExprKind::CallExpr {
+                    function: Box::new(ExprKind::IdentExpr(Ident("add".to_owned())).into()),
+                    arguments: vec![
+                        ExprKind::LitExpr(Literal::IntLiteral(2)).into(),
+                        ExprKind::LitExpr(Literal::IntLiteral(3)).into(),
+                    ],
+                }
+                .into(),
+            )
+            .into(),
+            StmtKind::ExprStmt(
+                ExprKind::CallExpr {
+                    function: Box::new(ExprKind::IdentExpr(Ident("add".to_owned())).into()),
+                    arguments: vec![
+                        ExprKind::IdentExpr(Ident("a".to_owned())).into(),
+                        ExprKind::IdentExpr(Ident("b".to_owned())).into(),
+                        ExprKind::LitExpr(Literal::IntLiteral(1)).into(),
+                        ExprKind::InfixExpr(
+                            Infix::Multiply,
+                            Box::new(ExprKind::LitExpr(Literal::IntLiteral(2)).into()),
+                            Box::new(ExprKind::LitExpr(Literal::IntLiteral(3)).into()),
+                        )
+                        .into(),
+                        ExprKind::CallExpr {
+                            function: Box::new(
+                                ExprKind::IdentExpr(Ident("other".to_owned())).into(),
                             ),
-                        ],
-                    },
-                ],
-            }),
-            Stmt::ExprStmt(Expr::CallExpr {
-                function: Box::new(Expr::FnExpr {
-                    params: vec![Ident("a".to_owned()), Ident("b".to_owned())],
-                    body: vec![Stmt::ReturnStmt(Expr::InfixExpr(
-                        Infix::Plus,
-                        Box::new(Expr::IdentExpr(Ident("a".to_owned()))),
-                        Box::new(Expr::IdentExpr(Ident("b".to_owned()))),
-                    ))],
-                }),
-                arguments: vec![
-                    Expr::LitExpr(Literal::IntLiteral(1)),
-                    Expr::LitExpr(Literal::IntLiteral(2)),
-                ],
-            }),
+                            arguments: vec![ExprKind::InfixExpr(
+                                Infix::Plus,
+                                Box::new(ExprKind::LitExpr(Literal::IntLiteral(4)).into()),
+                                Box::new(ExprKind::LitExpr(Literal::IntLiteral(5)).into()),
+                            )
+                            .into()],
+                        }
+                        .into(),
+                        ExprKind::CallExpr {
+                            function: Box::new(ExprKind::IdentExpr(Ident("add".to_owned())).into()),
+                            arguments: vec![
+                                ExprKind::LitExpr(Literal::IntLiteral(6)).into(),
+                                ExprKind::InfixExpr(
+                                    Infix::Multiply,
+                                    Box::new(ExprKind::LitExpr(Literal::IntLiteral(7)).into()),
+                                    Box::new(ExprKind::LitExpr(Literal::IntLiteral(8)).into()),
+                                )
+                                .into(),
+                            ],
+                        }
+                        .into(),
+                    ],
+                }
+                .into(),
+            )
+            .into(),
+            StmtKind::ExprStmt(
+                ExprKind::CallExpr {
+                    function: Box::new(
+                        ExprKind::FnExpr {
+                            params: vec![Ident("a".to_owned()), Ident("b".to_owned())],
+                            body: vec![StmtKind::ReturnStmt(
+                                ExprKind::InfixExpr(
+                                    Infix::Plus,
+                                    Box::new(ExprKind::IdentExpr(Ident("a".to_owned())).into()),
+                                    Box::new(ExprKind::IdentExpr(Ident("b".to_owned())).into()),
+                                )
+                                .into(),
+                            )
+                            .into()],
+                        }
+                        .into(),
+                    ),
+                    arguments: vec![
+                        ExprKind::LitExpr(Literal::IntLiteral(1)).into(),
+                        ExprKind::LitExpr(Literal::IntLiteral(2)).into(),
+                    ],
+                }
+                .into(),
+            )
+            .into(),
         ];
 
         assert_input_with_program(input, program);
@@ -777,41 +1298,46 @@ mod tests {
     fn strings() {
         let input = &b"\"foobar\""[..];
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::LitExpr(Literal::StringLiteral(
-            "foobar".to_owned(),
-        )))];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::LitExpr(Literal::StringLiteral("foobar".to_owned())).into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
         let input = &b"\"foo bar\""[..];
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::LitExpr(Literal::StringLiteral(
-            "foo bar".to_owned(),
-        )))];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::LitExpr(Literal::StringLiteral("foo bar".to_owned())).into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
         let input = &b"\"foo\nbar\""[..];
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::LitExpr(Literal::StringLiteral(
-            "foo\nbar".to_owned(),
-        )))];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::LitExpr(Literal::StringLiteral("foo\nbar".to_owned())).into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
         let input = &b"\"foo\tbar\""[..];
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::LitExpr(Literal::StringLiteral(
-            "foo\tbar".to_owned(),
-        )))];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::LitExpr(Literal::StringLiteral("foo\tbar".to_owned())).into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
         let input = &b"\"foo\\\"bar\""[..];
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::LitExpr(Literal::StringLiteral(
-            "foo\"bar".to_owned(),
-        )))];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::LitExpr(Literal::StringLiteral("foo\"bar".to_owned())).into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
     }
@@ -820,32 +1346,45 @@ mod tests {
     fn arrays() {
         let input = &b"[1, 2 * 2, 3 + 3]"[..];
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::ArrayExpr(vec![
-            Expr::LitExpr(Literal::IntLiteral(1)),
-            Expr::InfixExpr(
-                Infix::Multiply,
-                Box::new(Expr::LitExpr(Literal::IntLiteral(2))),
-                Box::new(Expr::LitExpr(Literal::IntLiteral(2))),
-            ),
-            Expr::InfixExpr(
-                Infix::Plus,
-                Box::new(Expr::LitExpr(Literal::IntLiteral(3))),
-                Box::new(Expr::LitExpr(Literal::IntLiteral(3))),
-            ),
-        ]))];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::ArrayExpr(vec![
+                ExprKind::LitExpr(Literal::IntLiteral(1)).into(),
+                ExprKind::InfixExpr(
+                    Infix::Multiply,
+                    Box::new(ExprKind::LitExpr(Literal::IntLiteral(2)).into()),
+                    Box::new(ExprKind::LitExpr(Literal::IntLiteral(2)).into()),
+                )
+                .into(),
+                ExprKind::InfixExpr(
+                    Infix::Plus,
+                    Box::new(ExprKind::LitExpr(Literal::IntLiteral(3)).into()),
+                    Box::new(ExprKind::LitExpr(Literal::IntLiteral(3)).into()),
+                )
+                .into(),
+            ])
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
         let input = &b"myArray[1 + 1]"[..];
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::IndexExpr {
-            array: Box::new(Expr::IdentExpr(Ident("myArray".to_owned()))),
-            index: Box::new(Expr::InfixExpr(
-                Infix::Plus,
-                Box::new(Expr::LitExpr(Literal::IntLiteral(1))),
-                Box::new(Expr::LitExpr(Literal::IntLiteral(1))),
-            )),
-        })];
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::IndexExpr {
+                array: Box::new(ExprKind::IdentExpr(Ident("myArray".to_owned())).into()),
+                index: Box::new(
+                    ExprKind::InfixExpr(
+                        Infix::Plus,
+                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(1)).into()),
+                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(1)).into()),
+                    )
+                    .into(),
+                ),
+            }
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
     }
@@ -869,92 +1408,357 @@ mod tests {
     fn hash() {
         let input = &b"{}"[..];
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::HashExpr(vec![]))];
+        let program: Program = vec![StmtKind::ExprStmt(ExprKind::HashExpr(vec![]).into()).into()];
 
         assert_input_with_program(input, program);
 
         let input = &b"{\"one\": 1, \"two\": 2, \"three\": 3}"[..];
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::HashExpr(vec![
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::HashExpr(vec![
+                (
+                    ExprKind::LitExpr(Literal::StringLiteral("one".to_owned())).into(),
+                    ExprKind::LitExpr(Literal::IntLiteral(1)).into(),
+                ),
+                (
+                    ExprKind::LitExpr(Literal::StringLiteral("two".to_owned())).into(),
+                    ExprKind::LitExpr(Literal::IntLiteral(2)).into(),
+                ),
+                (
+                    ExprKind::LitExpr(Literal::StringLiteral("three".to_owned())).into(),
+                    ExprKind::LitExpr(Literal::IntLiteral(3)).into(),
+                ),
+            ])
+            .into(),
+        )
+        .into()];
+
+        assert_input_with_program(input, program);
+
+        let input = &b"{4: 1, 5: 2, 6: 3}"[..];
+
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::HashExpr(vec![
+                (
+                    ExprKind::LitExpr(Literal::IntLiteral(4)).into(),
+                    ExprKind::LitExpr(Literal::IntLiteral(1)).into(),
+                ),
+                (
+                    ExprKind::LitExpr(Literal::IntLiteral(5)).into(),
+                    ExprKind::LitExpr(Literal::IntLiteral(2)).into(),
+                ),
+                (
+                    ExprKind::LitExpr(Literal::IntLiteral(6)).into(),
+                    ExprKind::LitExpr(Literal::IntLiteral(3)).into(),
+                ),
+            ])
+            .into(),
+        )
+        .into()];
+
+        assert_input_with_program(input, program);
+
+        let input = &b"{true: 1, false: 2}"[..];
+
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::HashExpr(vec![
+                (
+                    ExprKind::LitExpr(Literal::BoolLiteral(true)).into(),
+                    ExprKind::LitExpr(Literal::IntLiteral(1)).into(),
+                ),
+                (
+                    ExprKind::LitExpr(Literal::BoolLiteral(false)).into(),
+                    ExprKind::LitExpr(Literal::IntLiteral(2)).into(),
+                ),
+            ])
+            .into(),
+        )
+        .into()];
+
+        assert_input_with_program(input, program);
+
+        let input = &b"{\"one\": 0 + 1, \"two\": 10 - 8, \"three\": 15/5}"[..];
+
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::HashExpr(vec![
+                (
+                    ExprKind::LitExpr(Literal::StringLiteral("one".to_owned())).into(),
+                    ExprKind::InfixExpr(
+                        Infix::Plus,
+                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(0)).into()),
+                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(1)).into()),
+                    )
+                    .into(),
+                ),
+                (
+                    ExprKind::LitExpr(Literal::StringLiteral("two".to_owned())).into(),
+                    ExprKind::InfixExpr(
+                        Infix::Minus,
+                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(10)).into()),
+                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(8)).into()),
+                    )
+                    .into(),
+                ),
+                (
+                    ExprKind::LitExpr(Literal::StringLiteral("three".to_owned())).into(),
+                    ExprKind::InfixExpr(
+                        Infix::Divide,
+                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(15)).into()),
+                        Box::new(ExprKind::LitExpr(Literal::IntLiteral(5)).into()),
+                    )
+                    .into(),
+                ),
+            ])
+            .into(),
+        )
+        .into()];
+
+        assert_input_with_program(input, program);
+    }
+
+    #[test]
+    fn node_spans() {
+        let input = "let x = 1 + foo(2);\nx[0]".as_bytes();
+        let (_, r) = Lexer::lex_tokens(input).unwrap();
+        let program = Parser::parse_tokens(Tokens::new(&r)).unwrap();
+
+        assert_eq!(program[0].span, Span::new(0, 19, 1, 1));
+        match &program[0].kind {
+            StmtKind::LetStmt(_, expr) => {
+                assert_eq!(expr.span, Span::new(8, 18, 1, 9));
+                match &expr.kind {
+                    ExprKind::InfixExpr(_, left, right) => {
+                        assert_eq!(left.span, Span::new(8, 9, 1, 9));
+                        assert_eq!(right.span, Span::new(12, 18, 1, 13));
+                    }
+                    kind => panic!("expected infix expression, got {:?}", kind),
+                }
+            }
+            kind => panic!("expected let statement, got {:?}", kind),
+        }
+        assert_eq!(program[1].span, Span::new(20, 24, 2, 1));
+    }
+
+    fn parse_error(input: &str) -> ParseError {
+        let (_, r) = Lexer::lex_tokens(input.as_bytes()).unwrap();
+        Parser::parse_tokens(Tokens::new(&r)).unwrap_err()
+    }
+
+    #[test]
+    fn error_messages() {
+        let tests = [
+            ("let x 5;", "expected `=`, found `5` in let statement"),
             (
-                Literal::StringLiteral("one".to_owned()),
-                Expr::LitExpr(Literal::IntLiteral(1)),
+                "foo(1 2)",
+                "expected `,` or `)`, found `2` in argument list of call",
             ),
             (
-                Literal::StringLiteral("two".to_owned()),
-                Expr::LitExpr(Literal::IntLiteral(2)),
+                "if (x) { 1 + }",
+                "expected expression, found `}` in infix expression",
             ),
             (
-                Literal::StringLiteral("three".to_owned()),
-                Expr::LitExpr(Literal::IntLiteral(3)),
+                "fn(a, 1) { a }",
+                "expected identifier or `)`, found `1` in parameter list of fn",
+            ),
+            (
+                "{\"a\": 1",
+                "expected `,` or `}`, found end of input in hash literal",
             ),
-        ]))];
-
-        assert_input_with_program(input, program);
-
-        let input = &b"{4: 1, 5: 2, 6: 3}"[..];
-
-        let program: Program = vec![Stmt::ExprStmt(Expr::HashExpr(vec![
             (
-                Literal::IntLiteral(4),
-                Expr::LitExpr(Literal::IntLiteral(1)),
+                ")",
+                "expected `let`, `return`, `break`, `continue`, expression or end of input, found `)`",
             ),
             (
-                Literal::IntLiteral(5),
-                Expr::LitExpr(Literal::IntLiteral(2)),
+                "x = ",
+                "expected expression, found end of input in assignment",
             ),
+            ("f(x) = 1", "unexpected `=`"),
+            ("a + b -= 1", "unexpected `-=`"),
             (
-                Literal::IntLiteral(6),
-                Expr::LitExpr(Literal::IntLiteral(3)),
+                "while x { 1 }",
+                "expected `(`, found `x` in condition of while",
             ),
-        ]))];
+            ("for (1 in x) {}", "expected identifier, found `1` in for loop"),
+            ("for (x of y) {}", "expected `in`, found `of` in for loop"),
+        ];
+
+        for (input, message) in tests {
+            assert_eq!(parse_error(input).to_string(), message, "{}", input);
+        }
+    }
+
+    #[test]
+    fn assignment() {
+        let input = "x += 1".as_bytes();
+
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::AssignExpr {
+                target: Box::new(ExprKind::IdentExpr(Ident("x".to_owned())).into()),
+                operator: Some(Infix::Plus),
+                value: Box::new(ExprKind::LitExpr(Literal::IntLiteral(1)).into()),
+            }
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
-        let input = &b"{true: 1, false: 2}"[..];
+        let input = "a = b[0] = c || d".as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::HashExpr(vec![
-            (
-                Literal::BoolLiteral(true),
-                Expr::LitExpr(Literal::IntLiteral(1)),
-            ),
-            (
-                Literal::BoolLiteral(false),
-                Expr::LitExpr(Literal::IntLiteral(2)),
-            ),
-        ]))];
+        let input2 = "a = (b[0] = (c || d))".as_bytes();
+
+        compare_inputs(input, input2);
+
+        let input = "a[i][j] <<= f(x) + 1".as_bytes();
+
+        let input2 = "(a[i])[j] <<= (f(x) + 1)".as_bytes();
+
+        compare_inputs(input, input2);
+    }
+
+    #[test]
+    fn loops() {
+        let input = "while (x) { break; continue }".as_bytes();
+
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::WhileExpr {
+                cond: Box::new(ExprKind::IdentExpr(Ident("x".to_owned())).into()),
+                body: vec![StmtKind::BreakStmt.into(), StmtKind::ContinueStmt.into()],
+            }
+            .into(),
+        )
+        .into()];
 
         assert_input_with_program(input, program);
 
-        let input = &b"{\"one\": 0 + 1, \"two\": 10 - 8, \"three\": 15/5}"[..];
+        let input = "for (x in [1]) { x }".as_bytes();
 
-        let program: Program = vec![Stmt::ExprStmt(Expr::HashExpr(vec![
-            (
-                Literal::StringLiteral("one".to_owned()),
-                Expr::InfixExpr(
-                    Infix::Plus,
-                    Box::new(Expr::LitExpr(Literal::IntLiteral(0))),
-                    Box::new(Expr::LitExpr(Literal::IntLiteral(1))),
+        let program: Program = vec![StmtKind::ExprStmt(
+            ExprKind::ForExpr {
+                ident: Ident("x".to_owned()),
+                iterable: Box::new(
+                    ExprKind::ArrayExpr(vec![ExprKind::LitExpr(Literal::IntLiteral(1)).into()])
+                        .into(),
                 ),
+                body: vec![
+                    StmtKind::ExprStmt(ExprKind::IdentExpr(Ident("x".to_owned())).into()).into(),
+                ],
+            }
+            .into(),
+        )
+        .into()];
+
+        assert_input_with_program(input, program);
+    }
+
+    #[test]
+    fn computed_hash_keys() {
+        let input = "{a: 1, f(x): 2, -1: 3}".as_bytes();
+
+        let input2 = "{(a): 1, (f(x)): 2, (-1): 3}".as_bytes();
+
+        compare_inputs(input, input2);
+
+        assert_eq!(
+            parse_error("{a 1}").to_string(),
+            "expected `:`, found `1` in hash literal"
+        );
+    }
+
+    #[test]
+    fn trailing_commas() {
+        let input = "[1, 2,]; {\"a\": 1,}; f(a, b,); fn(x, y,) { x }".as_bytes();
+
+        let input2 = "[1, 2]; {\"a\": 1}; f(a, b); fn(x, y) { x }".as_bytes();
+
+        compare_inputs(input, input2);
+
+        assert_eq!(
+            parse_error("[,]").to_string(),
+            "expected expression or `]`, found `,` in array literal"
+        );
+        assert_eq!(
+            parse_error("f(a,,)").to_string(),
+            "expected expression or `)`, found `,` in argument list of call"
+        );
+    }
+
+    #[test]
+    fn error_position() {
+        let err = parse_error("let a = 1;\nlet b = [1, 2;");
+
+        assert_eq!(err.found, Token::SemiColon);
+        assert_eq!(err.span, Span::new(24, 25, 2, 14));
+        assert_eq!(err.context, Some("array literal"));
+    }
+
+    #[test]
+    fn recover_from_errors() {
+        let input = "let a = 1 +;\n\
+                     let f = fn(x) { let = x; x };\n\
+                     let b = 2;\n\
+                     if (a { 1 };\n\
+                     b";
+        let (_, r) = Lexer::lex_tokens(input.as_bytes()).unwrap();
+        let (program, errors) = Parser::parse_recovering(Tokens::new(&r));
+
+        let lines = errors.iter().map(|e| e.span.line).collect::<Vec<_>>();
+        assert_eq!(lines, [1, 2, 4]);
+
+        let expected: Program = vec![
+            StmtKind::LetStmt(
+                Ident("b".to_owned()),
+                ExprKind::LitExpr(Literal::IntLiteral(2)).into(),
+            )
+            .into(),
+            StmtKind::ExprStmt(ExprKind::IdentExpr(Ident("b".to_owned())).into()).into(),
+        ];
+        assert_eq!(program, expected);
+    }
+
+    #[test]
+    fn display_program() {
+        let tests = [
+            ("-f(x) * !a[0]", "-f(x) * !a[0];"),
+            ("((a + b)) * c", "(a + b) * c;"),
+            ("(a - b) - c; a - (b - c)", "a - b - c;\na - (b - c);"),
+            ("(-a)(b); (a + b)[0]", "(-a)(b);\n(a + b)[0];"),
+            ("1 < 2 == (3 > 4)", "1 < 2 == 3 > 4;"),
+            (
+                "let f = fn(x, y) { return x; }",
+                "let f = fn(x, y) { return x; };",
             ),
             (
-                Literal::StringLiteral("two".to_owned()),
-                Expr::InfixExpr(
-                    Infix::Minus,
-                    Box::new(Expr::LitExpr(Literal::IntLiteral(10))),
-                    Box::new(Expr::LitExpr(Literal::IntLiteral(8))),
-                ),
+                "if (a) { 1 } else { 2; 3 }",
+                "if (a) { 1; } else { 2; 3; };",
             ),
+            ("fn() {}()", "fn() {}();"),
+            ("(-2) ** 2; -(2 ** 2)", "(-2) ** 2;\n-2 ** 2;"),
             (
-                Literal::StringLiteral("three".to_owned()),
-                Expr::InfixExpr(
-                    Infix::Divide,
-                    Box::new(Expr::LitExpr(Literal::IntLiteral(15))),
-                    Box::new(Expr::LitExpr(Literal::IntLiteral(5))),
-                ),
+                "(2 ** 3) ** 2; 2 ** (3 ** 2)",
+                "(2 ** 3) ** 2;\n2 ** 3 ** 2;",
+            ),
+            ("2 ** -x; (a | b) & ~c", "2 ** -x;\n(a | b) & ~c;"),
+            ("(a || b) && c || (d && e)", "(a || b) && c || d && e;"),
+            (
+                "(x = 1) + 2; a[0] += (b = 3)",
+                "(x = 1) + 2;\na[0] += b = 3;",
+            ),
+            (
+                "while (i < 3) { i += 1; continue } for (x in a) { break }",
+                "while (i < 3) { i += 1; continue; };\nfor (x in a) { break; };",
             ),
-        ]))];
+            ("{\"a\\\"\": [1, true]}", "{\"a\\\"\": [1, true]};"),
+        ];
 
-        assert_input_with_program(input, program);
+        for (input, expected) in tests {
+            let (_, r) = Lexer::lex_tokens(input.as_bytes()).unwrap();
+            let program = Parser::parse_tokens(Tokens::new(&r)).unwrap();
+            let printed = display(&program).to_string();
+            assert_eq!(printed.trim_end(), expected, "{}", input);
+
+            let (_, r) = Lexer::lex_tokens(printed.as_bytes()).unwrap();
+            assert_eq!(Parser::parse_tokens(Tokens::new(&r)).unwrap(), program);
+        }
     }
 }
@@ -0,0 +1,101 @@
+use std::fmt;
+
+/// Location of a piece of source code: a byte range plus the 1-based line and
+/// column (in characters) of its first byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// Span covering everything from the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Turns byte ranges into spans by walking the source once. Ranges must be
+/// given in increasing order of their start offset.
+pub struct Locator<'a> {
+    source: &'a [u8],
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Locator<'a> {
+    pub fn new(source: &'a [u8]) -> Self {
+        Self {
+            source,
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    pub fn locate(&mut self, start: usize, end: usize) -> Span {
+        while self.offset < start && self.offset < self.source.len() {
+            match self.source[self.offset] {
+                b'\n' => {
+                    self.line += 1;
+                    self.column = 1;
+                }
+                // UTF-8 continuation bytes don't start a new character
+                b if b & 0xC0 == 0x80 => {}
+                _ => self.column += 1,
+            }
+            self.offset += 1;
+        }
+
+        Span::new(start, end, self.line, self.column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locate_lines_and_columns() {
+        let source = "let a = 1;\n  a + \"💖\";\nb".as_bytes();
+        let mut locator = Locator::new(source);
+
+        assert_eq!(locator.locate(0, 3), Span::new(0, 3, 1, 1));
+        assert_eq!(locator.locate(4, 5), Span::new(4, 5, 1, 5));
+        assert_eq!(locator.locate(13, 14), Span::new(13, 14, 2, 3));
+        assert_eq!(locator.locate(17, 23), Span::new(17, 23, 2, 7));
+        assert_eq!(locator.locate(23, 24), Span::new(23, 24, 2, 10));
+        assert_eq!(locator.locate(25, 26), Span::new(25, 26, 3, 1));
+    }
+
+    #[test]
+    fn merge_spans() {
+        let a = Span::new(4, 5, 1, 5);
+        let b = Span::new(8, 10, 1, 9);
+
+        assert_eq!(a.to(b), Span::new(4, 10, 1, 5));
+    }
+}
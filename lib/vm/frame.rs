@@ -1,30 +1,35 @@
-use std::rc::Rc;
-
-use crate::{code::Instructions, evaluator::object::Object};
+use crate::{
+    code::instruction_start, error::TraceFrame, evaluator::object::CompiledFunction, span::Span,
+};
 
+/// A call in progress. The closure it runs stays on the stack below its
+/// arguments until it returns, so the frame only records where it is.
 #[derive(Debug, Clone)]
 pub struct Frame {
-    pub cl: Object,
-    pub ip: i64,
+    /// Position of the frame's next instruction
+    pub ip: usize,
     pub base_pointer: usize,
 }
 
 impl Frame {
-    pub fn new(cl: Object, base_pointer: usize) -> Self {
+    pub fn new(base_pointer: usize) -> Self {
         Self {
-            cl,
-            ip: -1,
+            ip: 0,
             base_pointer,
         }
     }
 
-    pub fn instructions(&self) -> Instructions {
-        if let Object::Closure(func, _) = &self.cl {
-            if let Object::CompiledFn(ins, _, _) = Rc::as_ref(func) {
-                return ins.to_vec();
-            }
-        }
+    /// Source span of the instruction the frame last executed in `func`
+    pub fn span(&self, func: &CompiledFunction) -> Option<Span> {
+        func.source_map.lookup(self.ip.saturating_sub(1))
+    }
 
-        vec![]
+    /// Describe where execution of this frame of `func` currently is
+    pub fn trace(&self, func: &CompiledFunction) -> TraceFrame {
+        TraceFrame {
+            function: func.name.clone(),
+            offset: instruction_start(&func.instructions, self.ip.saturating_sub(1)),
+            span: self.span(func),
+        }
     }
 }
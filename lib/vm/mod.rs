@@ -1,12 +1,15 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use im_rc::{HashMap, Vector};
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
 
 use crate::{
     code::{read_u16, read_u8, Opcode},
     common::oth,
     compiler::Bytecode,
-    error::MonkeyError,
-    error::Result,
-    evaluator::{builtins::BuiltinsFunctions, object::Object},
+    error::{MonkeyError, Result, RuntimeError, StackTrace},
+    evaluator::{
+        builtins::BuiltinsFunctions,
+        object::{CompiledFunction, Numbers, Object},
+    },
 };
 
 use self::frame::Frame;
@@ -22,135 +25,231 @@ const NULL: Object = Object::Null;
 pub struct VM {
     constants: Vec<Object>,
 
-    stack: RefCell<Vec<Object>>,
-    sp: RefCell<usize>,
-
-    globals: RefCell<Vec<Object>>,
-
-    frames: RefCell<Vec<Frame>>,
-    frame_index: RefCell<usize>,
-
-    curr_frame: RefCell<Frame>, // a workaround for immutable borrow
-    curr_frame_index: RefCell<usize>,
+    /// Values the executing code works on, the top last
+    stack: Vec<Object>,
+    /// Value the last statement of the program left
+    last_popped: Object,
+
+    globals: Vec<Object>,
+
+    /// Function of the main program, which no closure on the stack holds
+    main_fn: Rc<CompiledFunction>,
+    /// Frame of the executing function
+    frame: Frame,
+    /// Frames of the callers waiting for it to return, the innermost last
+    frames: Vec<Frame>,
+    /// Instructions of the executing frame, and the position of the next one
+    /// to run. The frame's own `ip` is only updated when it makes a call.
+    instructions: Rc<[u8]>,
+    ip: usize,
 }
 
 impl VM {
     pub fn new(bytecode: Bytecode) -> Self {
-        let main_fn = Object::CompiledFn(bytecode.instructions.clone(), 0, 0);
-        let main_closure = Object::Closure(Rc::new(main_fn), Vec::new());
-        let main_frame = Frame::new(main_closure, 0);
-
-        let mut frames = Vec::with_capacity(MAX_FRAMES);
-        frames.push(main_frame.clone());
+        let main_fn = Rc::new(CompiledFunction {
+            instructions: bytecode.instructions.into(),
+            source_map: bytecode.source_map,
+            name: Some("<main>".to_string()),
+            ..Default::default()
+        });
+        let instructions = Rc::clone(&main_fn.instructions);
 
         Self {
             constants: bytecode.constants,
-            stack: RefCell::new(vec![Object::Null; STACK_SIZE]),
-            sp: RefCell::new(0),
-            globals: RefCell::new(vec![Object::Null; GLOBAL_SIZE]),
-            frames: RefCell::new(frames),
-            frame_index: RefCell::new(1),
-            curr_frame: RefCell::new(main_frame),
-            curr_frame_index: RefCell::new(0),
+            stack: Vec::with_capacity(STACK_SIZE),
+            last_popped: NULL,
+            globals: vec![Object::Null; GLOBAL_SIZE],
+            main_fn,
+            frame: Frame::new(0),
+            frames: Vec::with_capacity(MAX_FRAMES),
+            instructions,
+            ip: 0,
         }
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        let mut current_frame = self.curr_frame.borrow_mut().clone();
+    pub fn run(&mut self) -> std::result::Result<(), RuntimeError> {
+        let result = self.execute();
+
+        // leave the frame where execution stopped, so that errors can be
+        // located
+        self.frame.ip = self.ip;
+
+        result.map_err(|error| RuntimeError {
+            error,
+            trace: self.stack_trace(),
+        })
+    }
+
+    /// Frames active where execution stopped, innermost first
+    pub fn stack_trace(&self) -> StackTrace {
+        let frames = std::iter::once(&self.frame).chain(self.frames.iter().rev());
+
+        StackTrace(
+            frames
+                .map(|frame| frame.trace(self.function(frame)))
+                .collect(),
+        )
+    }
 
-        while current_frame.ip < current_frame.instructions().len() as i64 - 1 {
-            current_frame.ip += 1;
+    fn execute(&mut self) -> Result<()> {
+        while self.ip < self.instructions.len() {
+            let op = Opcode::try_from(&self.instructions[self.ip])?;
+            self.ip += 1;
 
-            let ip = current_frame.ip as usize;
-            let ins = current_frame.instructions();
-            let op = Opcode::from(&ins[ip as usize]);
             match op {
                 Opcode::OpConstant => {
-                    let const_index = read_u16(&ins[ip + 1..ip + 3]);
-                    current_frame.ip += 2;
-                    self.push(self.constants[const_index as usize].clone())?;
+                    let const_index = self.read_u16_operand();
+
+                    if !self.fold_constant(const_index)? {
+                        self.push(cloned(&self.constants[const_index]))?;
+                    }
                 }
-                Opcode::OpAdd | Opcode::OpDiv | Opcode::OpSub | Opcode::OpMul => {
+                Opcode::OpAdd
+                | Opcode::OpDiv
+                | Opcode::OpSub
+                | Opcode::OpMul
+                | Opcode::OpMod
+                | Opcode::OpPow
+                | Opcode::OpBitAnd
+                | Opcode::OpBitOr
+                | Opcode::OpBitXor
+                | Opcode::OpShiftLeft
+                | Opcode::OpShiftRight => {
                     self.execute_binary_operation(op)?;
                 }
                 Opcode::OpPop => {
-                    self.pop()?;
+                    self.last_popped = self.pop()?;
                 }
                 Opcode::OpTrue => self.push(TRUE)?,
                 Opcode::OpFalse => self.push(FALSE)?,
-                Opcode::OpEqual | Opcode::OpNotEqual | Opcode::OpGreaterThan => {
-                    self.execute_comparison(op)?
-                }
+                Opcode::OpEqual
+                | Opcode::OpNotEqual
+                | Opcode::OpGreaterThan
+                | Opcode::OpGreaterThanEqual
+                | Opcode::OpLessThan
+                | Opcode::OpLessThanEqual => self.execute_comparison(op)?,
                 Opcode::OpMinus => self.execute_minus_operator()?,
+                Opcode::OpBitNot => self.execute_bit_not_operator()?,
                 Opcode::OpBang => self.execute_bang_operator()?,
                 Opcode::OpJumpNotTruthy => {
-                    let pos = read_u16(&ins[ip + 1..ip + 3]) as usize;
-                    current_frame.ip += 2; // continue to consequence
+                    let pos = self.read_u16_operand();
 
-                    let condition = self.pop()?;
+                    let condition = self.stack.last().ok_or(MonkeyError::EmptyStackException)?;
                     if !Self::is_truthy(condition) {
-                        current_frame.ip = pos as i64 - 1; // jump to alternative
+                        self.ip = pos; // jump to alternative
                     }
+                    self.discard_top();
                 }
                 Opcode::OpJump => {
-                    let pos = read_u16(&ins[ip + 1..ip + 3]) as usize;
-                    current_frame.ip = pos as i64 - 1;
+                    self.ip = self.read_u16_operand();
                 }
                 Opcode::OpNull => self.push(NULL)?,
+                Opcode::OpIter => {
+                    let iterable = self.pop()?;
+                    let items = match iterable.iterated() {
+                        Some(items) => items,
+                        None => return Err(MonkeyError::NotIterable(iterable)),
+                    };
+
+                    self.push(Object::Array(items))?;
+                    self.push(Object::Integer(0))?;
+                }
+                Opcode::OpIterNext => {
+                    let pos = self.read_u16_operand();
+
+                    // the items and the index of the next one, left by OpIter
+                    let sp = self.stack.len();
+                    let next = match (&self.stack[sp - 2], &self.stack[sp - 1]) {
+                        (Object::Array(items), Object::Integer(i)) => {
+                            items.get(*i as usize).map(|item| (item.clone(), i + 1))
+                        }
+                        _ => None,
+                    };
+
+                    match next {
+                        Some((item, i)) => {
+                            self.stack[sp - 1] = Object::Integer(i);
+                            self.push(item)?;
+                        }
+                        None => self.ip = pos,
+                    }
+                }
                 Opcode::OpGetGlobal => {
-                    let globals = self.globals.borrow();
-                    let global_index = read_u16(&ins[ip + 1..ip + 3]) as usize;
-                    current_frame.ip += 2;
+                    let global_index = self.read_u16_operand();
 
-                    self.push(globals[global_index].clone())?;
+                    if self.stack.len() >= STACK_SIZE {
+                        return Err(MonkeyError::StackOverflow);
+                    }
+                    // cloned straight onto the stack, where pushing the
+                    // clone would copy it once more
+                    self.stack
+                        .extend_from_slice(&self.globals[global_index..=global_index]);
                 }
                 Opcode::OpSetGlobal => {
-                    let mut globals = self.globals.borrow_mut();
-                    let global_index = read_u16(&ins[ip + 1..ip + 3]) as usize;
-                    current_frame.ip += 2;
+                    let global_index = self.read_u16_operand();
 
+                    // a let gives the program its value, when it comes last
                     let value = self.pop()?;
-                    globals[global_index] = value;
+                    self.globals[global_index] = value.clone();
+                    self.last_popped = value;
                 }
                 Opcode::OpArray => {
-                    let array = {
-                        let mut sp = self.sp.borrow_mut();
-                        let num_ele = read_u16(&ins[ip + 1..ip + 3]) as usize;
-                        current_frame.ip += 2;
-
-                        let array = self.build_array(*sp - num_ele, *sp);
-                        *sp -= num_ele;
+                    let num_ele = self.read_u16_operand();
 
-                        array
-                    };
+                    let start = self.stack.len() - num_ele;
+                    let array = Object::Array(self.stack.drain(start..).collect());
 
                     self.push(array)?;
                 }
                 Opcode::OpHash => {
-                    let hashmap = {
-                        let mut sp = self.sp.borrow_mut();
-                        let num_ele = read_u16(&ins[ip + 1..ip + 3]) as usize;
-                        current_frame.ip += 2;
-
-                        let hash = self.build_hash(*sp - num_ele, *sp);
-                        *sp -= num_ele;
+                    let num_ele = self.read_u16_operand();
 
-                        hash
-                    };
+                    let start = self.stack.len() - num_ele;
+                    let hash = self.build_hash(start)?;
 
-                    self.push(hashmap)?;
+                    self.push(hash)?;
                 }
                 Opcode::OpIndex => {
                     let index = self.pop()?;
                     let left = self.pop()?;
 
-                    self.execute_index_expr(&left, &index)?;
+                    self.push(self.index(&left, &index)?)?;
+                }
+                Opcode::OpPeekIndex => {
+                    let depth = self.read_u16_operand();
+
+                    // the container and keys stay for an OpSetIndex to use
+                    let sp = self.stack.len();
+                    let mut element = self.stack[sp - depth - 1].clone();
+                    for key in &self.stack[sp - depth..sp] {
+                        element = self.index(&element, key)?;
+                    }
+                    self.push(element)?;
+                }
+                Opcode::OpSetIndex => {
+                    let depth = self.read_u16_operand();
+
+                    let value = self.pop()?;
+                    let mut keys = Vec::with_capacity(depth);
+                    for _ in 0..depth {
+                        keys.push(self.pop()?);
+                    }
+                    keys.reverse();
+                    let container = self.pop()?;
+
+                    let updated = self.with_element(container, &keys, value.clone())?;
+                    self.push(value)?;
+                    self.push(updated)?;
                 }
                 Opcode::OpCall => {
-                    let num_args = read_u8(&ins[ip + 1]) as usize;
-                    current_frame.ip += 1;
+                    let num_args = self.read_u8_operand();
+
+                    self.execute_call(num_args)?;
+                }
+                Opcode::OpTailCall => {
+                    let num_args = self.read_u8_operand();
 
-                    current_frame = self.execute_call(num_args, current_frame.clone());
+                    self.execute_tail_call(num_args)?;
                 }
                 Opcode::OpReturnValue | Opcode::OpReturn => {
                     let return_val = match op {
@@ -159,75 +258,76 @@ impl VM {
                         _ => unimplemented!(),
                     };
 
-                    // decrement frame index
-                    let mut frame_index = self.frame_index.borrow_mut();
-                    *frame_index -= 1;
-
-                    // sync current frame to the frames vec
-                    let mut frames = self.frames.borrow_mut();
-                    let mut curr_frame_index = self.curr_frame_index.borrow_mut();
-                    frames[*curr_frame_index] = current_frame.clone();
-
-                    // reset stack pointer after return
-                    {
-                        let mut sp = self.sp.borrow_mut();
-                        *sp = current_frame.base_pointer - 1;
-                    }
+                    let caller = match self.frames.pop() {
+                        Some(caller) => caller,
+                        // returning from the main frame ends the program,
+                        // with the value as its result
+                        None => {
+                            self.last_popped = return_val;
+                            return Ok(());
+                        }
+                    };
 
-                    // update current frame
-                    current_frame = frames[*curr_frame_index - 1].clone();
-                    *curr_frame_index -= 1;
+                    // drop the callee and its locals, and resume the caller
+                    self.stack.truncate(self.frame.base_pointer - 1);
+                    self.instructions = Rc::clone(&self.function(&caller).instructions);
+                    self.ip = caller.ip;
+                    self.frame = caller;
 
                     self.push(return_val)?;
                 }
                 Opcode::OpGetLocal => {
-                    let local_index = read_u8(&ins[ip + 1]);
-                    current_frame.ip += 1;
+                    let local_index = self.read_u8_operand();
 
-                    let local_val = {
-                        let stack = self.stack.borrow();
-                        stack[current_frame.base_pointer + local_index as usize].clone()
-                    };
-                    self.push(local_val)?;
+                    let index = self.frame.base_pointer + local_index;
+
+                    if !self.fold_local(index)? {
+                        self.push(value_of(&self.stack[index]))?;
+                    }
                 }
                 Opcode::OpSetLocal => {
-                    let local_index = read_u8(&ins[ip + 1]);
-                    current_frame.ip += 1;
+                    let local_index = self.read_u8_operand();
 
                     let value = self.pop()?;
-                    let mut stack = self.stack.borrow_mut();
                     // take the value off stack and insert in reserved slot
+                    let index = self.frame.base_pointer + local_index;
+                    store(&mut self.stack[index], value);
+                }
+                Opcode::OpCaptureLocal => {
+                    let local_index = self.read_u8_operand();
 
-                    let index = current_frame.base_pointer + local_index as usize;
-                    stack[index] = value;
+                    let index = self.frame.base_pointer + local_index;
+                    let cell = captured(&mut self.stack[index]);
+                    self.push(cell)?;
                 }
                 Opcode::OpGetBuiltin => {
-                    let builtin_index = read_u8(&ins[ip + 1]) as usize;
-                    current_frame.ip += 1;
+                    let builtin_index = self.read_u8_operand();
 
                     let (_, builtin_fn) = &BuiltinsFunctions.get_builtins()[builtin_index];
                     self.push(builtin_fn.clone())?;
                 }
                 Opcode::OpClosure => {
-                    let fn_index = read_u16(&ins[ip + 1..ip + 3]) as usize;
-                    let num_free = read_u8(&ins[ip + 3]);
-                    current_frame.ip += 3;
+                    let fn_index = self.read_u16_operand();
+                    let num_free = self.read_u8_operand();
 
-                    self.push_closure(fn_index, num_free as usize)?;
+                    self.push_closure(fn_index, num_free)?;
                 }
                 Opcode::OpGetFree => {
-                    let free_index = read_u8(&ins[ip + 1]) as usize;
-                    current_frame.ip += 1;
-
-                    let free = {
-                        if let Object::Closure(_, free) = current_frame.cl.clone() {
-                            free.clone()
-                        } else {
-                            vec![]
-                        }
-                    };
+                    let free_index = self.read_u8_operand();
 
-                    self.push(free[free_index].clone())?;
+                    self.push(value_of(&self.free()[free_index]))?;
+                }
+                Opcode::OpSetFree => {
+                    let free_index = self.read_u8_operand();
+
+                    let value = self.pop()?;
+                    store(&mut self.free()[free_index].clone(), value);
+                }
+                Opcode::OpCaptureFree => {
+                    let free_index = self.read_u8_operand();
+
+                    // already a cell, shared with the enclosing closure
+                    self.push(self.free()[free_index].clone())?;
                 }
             }
         }
@@ -235,120 +335,156 @@ impl VM {
         Ok(())
     }
 
-    fn push_closure(&self, fn_index: usize, num_free: usize) -> Result<()> {
-        let closure = {
-            let func = self.constants[fn_index].clone();
-            let stack = self.stack.borrow();
-            let mut sp = self.sp.borrow_mut();
+    /// Read a two byte operand of the executing instruction
+    fn read_u16_operand(&mut self) -> usize {
+        let operand = read_u16(&self.instructions[self.ip..self.ip + 2]);
+        self.ip += 2;
+
+        operand as usize
+    }
+
+    /// Read a one byte operand of the executing instruction
+    fn read_u8_operand(&mut self) -> usize {
+        let operand = read_u8(&self.instructions[self.ip]);
+        self.ip += 1;
+
+        operand as usize
+    }
 
-            let mut frees = vec![Object::Null; num_free];
-            frees.clone_from_slice(&stack[*sp - num_free..*sp]);
-            *sp -= num_free;
-            Object::Closure(Rc::new(func), frees)
+    fn push_closure(&mut self, fn_index: usize, num_free: usize) -> Result<()> {
+        let func = match &self.constants[fn_index] {
+            Object::CompiledFn(func) => Rc::clone(func),
+            constant => return Err(MonkeyError::NotCallable(constant.clone())),
         };
-        self.push(closure)?;
+
+        let start = self.stack.len() - num_free;
+        let frees = self.stack.drain(start..).collect();
+        self.push(Object::Closure(func, frees))?;
 
         Ok(())
     }
 
-    fn execute_call(&self, num_args: usize, curr_frame: Frame) -> Frame {
-        let (frame, obj) = {
-            let stack = self.stack.borrow();
-            let mut sp = self.sp.borrow_mut();
-            let callee = stack[*sp - 1 - num_args].clone();
-
-            match callee.clone() {
-                Object::Closure(_compiled_fn, _free_vars) => {
-                    let frame = Frame::new(callee.clone(), *sp - num_args);
-                    let base_pointer = frame.base_pointer;
+    /// Function the frame runs: the closure below its arguments, or the main
+    /// program
+    fn function(&self, frame: &Frame) -> &Rc<CompiledFunction> {
+        match frame.base_pointer.checked_sub(1).map(|i| &self.stack[i]) {
+            Some(Object::Closure(func, _)) => func,
+            _ => &self.main_fn,
+        }
+    }
 
-                    // push frame
-                    let mut frames = self.frames.borrow_mut();
-                    let mut frame_index = self.frame_index.borrow_mut();
+    /// Variables the executing closure captured
+    fn free(&self) -> &[Object] {
+        match self
+            .frame
+            .base_pointer
+            .checked_sub(1)
+            .map(|i| &self.stack[i])
+        {
+            Some(Object::Closure(_, free)) => free,
+            _ => &[],
+        }
+    }
 
-                    if *frame_index >= frames.len() {
-                        frames.push(frame);
-                    } else {
-                        frames[*frame_index] = frame;
-                    }
-                    *frame_index += 1;
+    fn execute_call(&mut self, num_args: usize) -> Result<()> {
+        let base_pointer = self.stack.len() - num_args;
+        let func = match &self.stack[base_pointer - 1] {
+            Object::Closure(func, _) => func,
+            Object::Builtin(_, _arg, func) => {
+                let func = *func;
+                let args = self.stack.split_off(base_pointer);
+                self.stack.pop();
 
-                    // starting point is base_pointer, and reserve for locals
-                    if let Object::Closure(func, _) = callee {
-                        if let Object::CompiledFn(_, num_locals, _) = Rc::as_ref(&func) {
-                            *sp = base_pointer + *num_locals as usize;
-                        }
-                    }
+                return self.push(func(args).unwrap_or_else(Object::Error));
+            }
+            callee => return Err(MonkeyError::NotCallable(callee.clone())),
+        };
+        check_argument_count(func.num_params as usize, num_args)?;
+        let (num_locals, instructions) = (func.num_locals as usize, Rc::clone(&func.instructions));
 
-                    // update current frame, and sync it to the frames vec
-                    let mut curr_frame_index = self.curr_frame_index.borrow_mut();
-                    frames[*curr_frame_index] = curr_frame;
+        if self.frames.len() + 1 >= MAX_FRAMES {
+            return Err(MonkeyError::StackOverflow);
+        }
 
-                    *curr_frame_index += 1;
-                    (frames[*curr_frame_index].clone(), None)
-                }
-                Object::Builtin(name, _arg, func) => {
-                    let args = {
-                        let mut vacant = vec![Object::Null; num_args];
-                        vacant.clone_from_slice(&stack[*sp - num_args..*sp]);
-                        vacant
-                    };
+        // the arguments start at base_pointer, and reserve for locals
+        let locals_end = base_pointer + num_locals;
+        if locals_end >= STACK_SIZE {
+            return Err(MonkeyError::StackOverflow);
+        }
+        self.stack.resize_with(locals_end, || NULL);
 
-                    let result = func(args);
-                    *sp = *sp - 1 - num_args;
+        // save where the caller resumes, and switch to the callee
+        self.instructions = instructions;
+        let caller = Frame {
+            ip: std::mem::replace(&mut self.ip, 0),
+            base_pointer: std::mem::replace(&mut self.frame.base_pointer, base_pointer),
+        };
+        self.frames.push(caller);
 
-                    let obj = match result.clone() {
-                        Ok(builtin_ret) => builtin_ret,
-                        Err(_) => Object::Error(format!("invalid arguments for {}", name)),
-                    };
+        Ok(())
+    }
 
-                    (curr_frame, Some(obj))
-                }
-                _ => unimplemented!(),
-            }
+    /// Call a closure in the frame of the calling function, which would only
+    /// return the closure's value. Anything else is called as usual, and the
+    /// OpReturnValue that follows returns its value.
+    fn execute_tail_call(&mut self, num_args: usize) -> Result<()> {
+        let callee_pos = self.stack.len() - 1 - num_args;
+        let func = match &self.stack[callee_pos] {
+            Object::Closure(func, _) => func,
+            _ => return self.execute_call(num_args),
         };
+        check_argument_count(func.num_params as usize, num_args)?;
+        let (num_locals, instructions) = (func.num_locals as usize, Rc::clone(&func.instructions));
 
-        match obj {
-            None => frame,
-            Some(ret) => {
-                let _ = self.push(ret);
-                frame
-            }
+        let base_pointer = self.frame.base_pointer;
+        let locals_end = base_pointer + num_locals;
+        if locals_end >= STACK_SIZE {
+            return Err(MonkeyError::StackOverflow);
         }
-    }
 
-    fn build_array(&self, start_index: usize, end_index: usize) -> Object {
-        let stack = self.stack.borrow();
-        let mut eles = Vec::with_capacity(end_index - start_index);
-        eles.extend_from_slice(&stack[start_index..end_index]);
+        // move the callee and its arguments to where the caller's were
+        for i in 0..=num_args {
+            self.stack.swap(base_pointer - 1 + i, callee_pos + i);
+        }
+        // the caller's locals and the rest of its stack are no longer needed
+        self.stack.truncate(base_pointer + num_args);
+        self.stack.resize_with(locals_end, || NULL);
+
+        self.instructions = instructions;
+        self.ip = 0;
+        self.frame = Frame::new(base_pointer);
 
-        Object::Array(eles)
+        Ok(())
     }
 
-    fn build_hash(&self, start_index: usize, end_index: usize) -> Object {
+    #[allow(clippy::mutable_key_type)]
+    fn build_hash(&mut self, start_index: usize) -> Result<Object> {
         let mut hashed_pairs = HashMap::new();
-        let stack = self.stack.borrow();
+        let mut pairs = self.stack.drain(start_index..);
 
-        for i in (start_index..end_index).step_by(2) {
-            let key = stack[i].clone();
-            let val = stack[i + 1].clone();
-
-            let hash_key = oth(key.clone());
-            hashed_pairs.insert(hash_key, val);
+        while let (Some(key), Some(val)) = (pairs.next(), pairs.next()) {
+            hashed_pairs.insert(hash_key(&key)?, val);
         }
 
-        Object::Hash(hashed_pairs)
+        Ok(Object::Hash(hashed_pairs))
     }
 
-    fn execute_binary_operation(&self, op: Opcode) -> Result<()> {
+    fn execute_binary_operation(&mut self, op: Opcode) -> Result<()> {
+        if let Some(res) = self
+            .integer_operands()
+            .and_then(|(l, r)| integer_operation(op, l, r))
+        {
+            return self.replace_operands(Object::Integer(res));
+        }
+
         let right = self.pop()?;
         let left = self.pop()?;
-        let res = match (left, right) {
-            (Object::Integer(l), Object::Integer(r)) => self.execute_binary_int_operation(op, l, r),
-            (Object::String(l), Object::String(r)) => {
+        let res = match (Numbers::new(&left, &right), left, right) {
+            (Some(numbers), l, r) => self.execute_binary_number_operation(op, numbers, &l, &r),
+            (None, Object::String(l), Object::String(r)) => {
                 self.execute_binary_string_operation(op, l, r)
             }
-            _ => unimplemented!(),
+            (None, l, r) => Err(operator_error(op, &l, &r)),
         }?;
 
         self.push(res)?;
@@ -356,80 +492,207 @@ impl VM {
         Ok(())
     }
 
-    fn execute_binary_int_operation(
+    fn execute_binary_number_operation(
         &self,
         op: Opcode,
-        left_val: i64,
-        right_val: i64,
+        numbers: Numbers,
+        left: &Object,
+        right: &Object,
     ) -> Result<Object> {
-        let res = match op {
-            Opcode::OpAdd => right_val + left_val,
-            Opcode::OpSub => left_val - right_val,
-            Opcode::OpMul => right_val * left_val,
-            Opcode::OpDiv => left_val / right_val,
-            _ => unimplemented!("Unknown integer operator found: {:?}", op),
-        };
-
-        Ok(Object::Integer(res))
+        match op {
+            Opcode::OpAdd => Ok(numbers.add()),
+            Opcode::OpSub => Ok(numbers.sub()),
+            Opcode::OpMul => Ok(numbers.mul()),
+            Opcode::OpDiv => Ok(numbers.div()?),
+            Opcode::OpMod => Ok(numbers.rem()?),
+            Opcode::OpPow => Ok(numbers.pow()?),
+            Opcode::OpBitAnd => numbers
+                .bit_and()
+                .ok_or_else(|| operator_error(op, left, right)),
+            Opcode::OpBitOr => numbers
+                .bit_or()
+                .ok_or_else(|| operator_error(op, left, right)),
+            Opcode::OpBitXor => numbers
+                .bit_xor()
+                .ok_or_else(|| operator_error(op, left, right)),
+            Opcode::OpShiftLeft => match numbers.shift_left() {
+                Some(res) => Ok(res?),
+                None => Err(operator_error(op, left, right)),
+            },
+            Opcode::OpShiftRight => match numbers.shift_right() {
+                Some(res) => Ok(res?),
+                None => Err(operator_error(op, left, right)),
+            },
+            _ => Err(operator_error(op, left, right)),
+        }
     }
 
     fn execute_binary_string_operation(
         &self,
         op: Opcode,
-        left_val: String,
-        right_val: String,
+        left_val: Rc<str>,
+        right_val: Rc<str>,
     ) -> Result<Object> {
         if op != Opcode::OpAdd {
-            return Err(MonkeyError::UnknownOperator);
+            let (l, r) = (Object::String(left_val), Object::String(right_val));
+            return Err(operator_error(op, &l, &r));
         }
 
-        Ok(Object::String(left_val + &right_val))
+        Ok(Object::String(format!("{}{}", left_val, right_val).into()))
     }
 
-    fn execute_comparison(&self, op: Opcode) -> Result<()> {
+    fn execute_comparison(&mut self, op: Opcode) -> Result<()> {
+        if let Some((l, r)) = self.integer_operands() {
+            let res = match integer_comparison(op, l, r) {
+                Some(res) => res,
+                None => return Err(operator_error(op, &Object::Integer(l), &Object::Integer(r))),
+            };
+            self.forget_operands(2);
+            return self.push_condition(res);
+        }
+
         let right = self.pop()?;
         let left = self.pop()?;
 
-        match (&left, &right) {
-            (Object::Integer(_l), Object::Integer(_r)) => {
-                return self.execute_int_comparison(op, &left, &right);
+        match Numbers::new(&left, &right) {
+            Some(numbers) => self.execute_number_comparison(op, numbers.compare(), &left, &right),
+            None => match op {
+                Opcode::OpEqual => self.push(native_to_object(right == left)),
+                Opcode::OpNotEqual => self.push(native_to_object(right != left)),
+                _ => Err(operator_error(op, &left, &right)),
+            },
+        }
+    }
+
+    /// `ordering` is `None` when comparing with NaN, which is unequal to
+    /// everything
+    fn execute_number_comparison(
+        &mut self,
+        op: Opcode,
+        ordering: Option<Ordering>,
+        left: &Object,
+        right: &Object,
+    ) -> Result<()> {
+        let res = match op {
+            Opcode::OpEqual => ordering.is_some_and(|o| o.is_eq()),
+            Opcode::OpNotEqual => !ordering.is_some_and(|o| o.is_eq()),
+            Opcode::OpGreaterThan => ordering.is_some_and(|o| o.is_gt()),
+            Opcode::OpGreaterThanEqual => ordering.is_some_and(|o| o.is_ge()),
+            Opcode::OpLessThan => ordering.is_some_and(|o| o.is_lt()),
+            Opcode::OpLessThanEqual => ordering.is_some_and(|o| o.is_le()),
+            _ => return Err(operator_error(op, left, right)),
+        };
+
+        self.push(native_to_object(res))
+    }
+
+    /// Apply the operation after an integer constant to it and the integer
+    /// on top of the stack, rather than push the constant for the operation
+    /// to take off again. Returns whether it did.
+    fn fold_constant(&mut self, const_index: usize) -> Result<bool> {
+        match (self.stack.last(), &self.constants[const_index]) {
+            (Some(Object::Integer(l)), Object::Integer(r)) => {
+                self.run_integer_operation(self.ip, (*l, *r), 1)
             }
-            _ => {
-                match op {
-                    Opcode::OpEqual => self.push(native_to_object(right == left)),
-                    Opcode::OpNotEqual => self.push(native_to_object(right != left)),
-                    _ => {
-                        unimplemented!("Unknown operator found: {:?} ({:?} {:?})", op, left, right)
-                    }
-                }?;
+            _ => Ok(false),
+        }
+    }
+
+    /// Apply the operation after an integer local and an integer constant to
+    /// them, as in `x - 1`, without pushing either. Returns whether it did.
+    fn fold_local(&mut self, index: usize) -> Result<bool> {
+        match (&self.stack[index], self.constant_at(self.ip)) {
+            (Object::Integer(l), Some(Object::Integer(r))) => {
+                self.run_integer_operation(self.ip + 3, (*l, *r), 0)
             }
+            _ => Ok(false),
         }
+    }
 
-        Ok(())
+    /// The constant of the `OpConstant` at `pos`, if there is one there
+    fn constant_at(&self, pos: usize) -> Option<&Object> {
+        match Opcode::try_from(self.instructions.get(pos)?) {
+            Ok(Opcode::OpConstant) => {
+                let const_index = read_u16(self.instructions.get(pos + 1..pos + 3)?);
+                self.constants.get(const_index as usize)
+            }
+            _ => None,
+        }
     }
 
-    fn execute_int_comparison(&self, op: Opcode, left: &Object, right: &Object) -> Result<()> {
-        let left_val = match left {
-            Object::Integer(v) => v,
-            _ => todo!(),
+    /// Run the operation at `pos` on two integers, of which the last
+    /// `on_stack` are on top of the stack, and continue after it. Only
+    /// comparisons and the operations [integer_operation] handles are run;
+    /// returns whether it was.
+    fn run_integer_operation(
+        &mut self,
+        pos: usize,
+        (left, right): (i64, i64),
+        on_stack: usize,
+    ) -> Result<bool> {
+        let op = match self.instructions.get(pos).map(Opcode::try_from) {
+            Some(Ok(op)) => op,
+            _ => return Ok(false),
         };
 
-        let right_val = match right {
-            Object::Integer(v) => v,
-            _ => todo!(),
-        };
+        if let Some(res) = integer_comparison(op, left, right) {
+            self.ip = pos + 1;
+            self.forget_operands(on_stack);
+            self.push_condition(res)?;
+        } else if let Some(res) = integer_operation(op, left, right) {
+            self.ip = pos + 1;
+            self.forget_operands(on_stack);
+            self.push(Object::Integer(res))?;
+        } else {
+            return Ok(false);
+        }
 
-        match op {
-            Opcode::OpEqual => self.push(native_to_object(right_val == left_val)),
-            Opcode::OpNotEqual => self.push(native_to_object(right_val != left_val)),
-            Opcode::OpGreaterThan => self.push(native_to_object(left_val > right_val)),
-            _ => unimplemented!("Unknown operator found: {:?} ({:?} {:?})", op, left, right),
-        }?;
+        Ok(true)
+    }
+
+    /// Take integer operands off the stack. Integers own nothing, so they
+    /// are forgotten rather than dropped.
+    fn forget_operands(&mut self, count: usize) {
+        for _ in 0..count {
+            std::mem::forget(self.stack.pop());
+        }
+    }
+
+    /// Push the result of a comparison, unless an `OpJumpNotTruthy` follows
+    /// that would take it right off again, and then jump as that would
+    fn push_condition(&mut self, res: bool) -> Result<()> {
+        let ip = self.ip;
+        if let Some(Ok(Opcode::OpJumpNotTruthy)) = self.instructions.get(ip).map(Opcode::try_from) {
+            self.ip = match res {
+                true => ip + 3,
+                false => read_u16(&self.instructions[ip + 1..ip + 3]) as usize,
+            };
+            return Ok(());
+        }
+
+        self.push(native_to_object(res))
+    }
+
+    /// The two operands on top of the stack, if both are `i64` integers
+    fn integer_operands(&self) -> Option<(i64, i64)> {
+        match self.stack[..] {
+            [.., Object::Integer(l), Object::Integer(r)] => Some((l, r)),
+            _ => None,
+        }
+    }
+
+    /// Replace the two integer operands on top of the stack with the result
+    /// of the operation on them
+    fn replace_operands(&mut self, res: Object) -> Result<()> {
+        self.forget_operands(1);
+        if let Some(left) = self.stack.last_mut() {
+            std::mem::forget(std::mem::replace(left, res));
+        }
 
         Ok(())
     }
 
-    fn execute_bang_operator(&self) -> Result<()> {
+    fn execute_bang_operator(&mut self) -> Result<()> {
         let operand = self.pop()?;
 
         match operand {
@@ -444,109 +707,253 @@ impl VM {
         Ok(())
     }
 
-    fn execute_minus_operator(&self) -> Result<()> {
+    fn execute_minus_operator(&mut self) -> Result<()> {
         let operand = self.pop()?;
 
-        match operand {
-            Object::Integer(v) => self.push(Object::Integer(-v)),
-            _ => Err(MonkeyError::UnsupportedType(operand)),
+        match operand.negated() {
+            Some(negated) => self.push(negated),
+            None => Err(MonkeyError::UnsupportedType(operand)),
         }?;
 
         Ok(())
     }
 
-    fn execute_index_expr(&self, left: &Object, index: &Object) -> Result<()> {
-        match (left, index) {
-            (Object::Array(array), Object::Integer(id)) => {
-                self.execute_array_index(array.to_vec(), *id)
-            }
-            (Object::Hash(map), _) => self.execute_hash_index(map.clone(), index),
-            _ => unimplemented!("index operator not supported: {:?}", left),
+    fn execute_bit_not_operator(&mut self) -> Result<()> {
+        let operand = self.pop()?;
+
+        match operand.bit_not() {
+            Some(inverted) => self.push(inverted),
+            None => Err(MonkeyError::UnsupportedType(operand)),
         }?;
 
         Ok(())
     }
 
-    fn execute_array_index(&self, array: Vec<Object>, index: i64) -> Result<()> {
+    fn index(&self, left: &Object, index: &Object) -> Result<Object> {
+        match (left, index) {
+            (Object::Array(array), Object::Integer(id)) => Ok(self.array_index(array, *id)),
+            (Object::Hash(map), _) => self.hash_index(map, index),
+            _ => Err(MonkeyError::IndexNotSupported {
+                left: left.type_name(),
+                index: index.type_name(),
+            }),
+        }
+    }
+
+    fn array_index(&self, array: &Vector<Object>, index: i64) -> Object {
         let max = array.len() as i64 - 1;
 
-        if index < 0 || index > max as i64 {
-            return self.push(NULL);
+        if index < 0 || index > max {
+            return NULL;
         }
 
-        self.push(array[index as usize].clone())?;
-
-        Ok(())
+        array[index as usize].clone()
     }
 
-    fn execute_hash_index(&self, map: HashMap<Object, Object>, index: &Object) -> Result<()> {
-        let key = oth(index.clone());
+    #[allow(clippy::mutable_key_type)]
+    fn hash_index(&self, map: &HashMap<Object, Object>, index: &Object) -> Result<Object> {
+        let key = hash_key(index)?;
         let val = map.get(&key).unwrap_or(&Object::Null);
 
-        self.push(val.clone())?;
+        Ok(val.clone())
+    }
+
+    /// `container` with the element at `keys` replaced by `value`
+    fn with_element(&self, container: Object, keys: &[Object], value: Object) -> Result<Object> {
+        let (key, rest) = match keys.split_first() {
+            Some(split) => split,
+            None => return Ok(value),
+        };
 
-        Ok(())
+        match (container, key) {
+            (Object::Array(mut array), Object::Integer(index)) => {
+                let len = array.len();
+                let i = match usize::try_from(*index) {
+                    Ok(i) if i < len => i,
+                    _ => return Err(MonkeyError::IndexOutOfBounds { index: *index, len }),
+                };
+                let element = std::mem::replace(&mut array[i], NULL);
+                array[i] = self.with_element(element, rest, value)?;
+
+                Ok(Object::Array(array))
+            }
+            (Object::Hash(mut map), _) => {
+                let key = hash_key(key)?;
+                let element = map.remove(&key).unwrap_or(NULL);
+                map.insert(key, self.with_element(element, rest, value)?);
+
+                Ok(Object::Hash(map))
+            }
+            (container, key) => Err(MonkeyError::IndexNotSupported {
+                left: container.type_name(),
+                index: key.type_name(),
+            }),
+        }
     }
 
-    fn is_truthy(obj: Object) -> bool {
+    fn is_truthy(obj: &Object) -> bool {
         match obj {
-            Object::Boolean(v) => v,
+            Object::Boolean(v) => *v,
             Object::Null => false,
             _ => true,
         }
     }
 
     /// Push obj to the top of the stack
-    pub fn push(&self, obj: Object) -> Result<()> {
-        let mut sp = self.sp.borrow_mut();
-        let mut stack = self.stack.borrow_mut();
-
-        if *sp >= STACK_SIZE {
+    pub fn push(&mut self, obj: Object) -> Result<()> {
+        if self.stack.len() >= STACK_SIZE {
             return Err(MonkeyError::StackOverflow);
         }
 
-        stack[*sp] = obj;
-        *sp += 1;
+        self.stack.push(obj);
 
         Ok(())
     }
 
-    /// Decrement stack pointer, return last obj
-    pub fn pop(&self) -> Result<Object> {
-        let mut sp = self.sp.borrow_mut();
-        let stack = self.stack.borrow();
-
-        if stack.len() == 0 {
-            return Err(MonkeyError::EmptyStackException);
+    /// Drop the top obj, without a call for the values that own nothing
+    fn discard_top(&mut self) {
+        match self.stack.last() {
+            Some(Object::Integer(_) | Object::Float(_) | Object::Boolean(_) | Object::Null) => {
+                std::mem::forget(self.stack.pop())
+            }
+            _ => drop(self.stack.pop()),
         }
+    }
 
-        let obj = &stack[*sp - 1];
+    /// Take the top obj off the stack
+    pub fn pop(&mut self) -> Result<Object> {
+        self.stack.pop().ok_or(MonkeyError::EmptyStackException)
+    }
 
-        *sp -= 1;
+    /// Last obj a statement took off the stack
+    pub fn last_popped_stack_ele(&self) -> Object {
+        self.last_popped.clone()
+    }
 
-        Ok(obj.clone())
+    pub fn stack_top(&self) -> Option<Object> {
+        self.stack.last().cloned()
     }
+}
 
-    /// Last ele previously on the stack
-    pub fn last_popped_stack_ele(&self) -> Object {
-        let sp = self.sp.borrow();
-        let stack = self.stack.borrow();
+/// Source form of a binary operator opcode, for error messages
+fn operator(op: Opcode) -> &'static str {
+    match op {
+        Opcode::OpAdd => "+",
+        Opcode::OpSub => "-",
+        Opcode::OpMul => "*",
+        Opcode::OpDiv => "/",
+        Opcode::OpMod => "%",
+        Opcode::OpPow => "**",
+        Opcode::OpBitAnd => "&",
+        Opcode::OpBitOr => "|",
+        Opcode::OpBitXor => "^",
+        Opcode::OpShiftLeft => "<<",
+        Opcode::OpShiftRight => ">>",
+        Opcode::OpEqual => "==",
+        Opcode::OpNotEqual => "!=",
+        Opcode::OpGreaterThan => ">",
+        Opcode::OpGreaterThanEqual => ">=",
+        Opcode::OpLessThan => "<",
+        Opcode::OpLessThanEqual => "<=",
+        _ => "?",
+    }
+}
 
-        stack[*sp].clone()
+/// Result of an operation on two `i64` integers, which is `None` unless it is
+/// an `i64` too. The operations and cases left out go through [Numbers].
+fn integer_operation(op: Opcode, left: i64, right: i64) -> Option<i64> {
+    match op {
+        Opcode::OpAdd => left.checked_add(right),
+        Opcode::OpSub => left.checked_sub(right),
+        Opcode::OpMul => left.checked_mul(right),
+        Opcode::OpDiv => left.checked_div(right),
+        Opcode::OpMod => left.checked_rem(right),
+        Opcode::OpBitAnd => Some(left & right),
+        Opcode::OpBitOr => Some(left | right),
+        Opcode::OpBitXor => Some(left ^ right),
+        _ => None,
     }
+}
 
-    pub fn stack_top(&self) -> Option<Object> {
-        let sp = self.sp.borrow();
-        let stack = self.stack.borrow();
+/// Result of comparing two `i64` integers, or `None` if `op` isn't a
+/// comparison
+fn integer_comparison(op: Opcode, left: i64, right: i64) -> Option<bool> {
+    match op {
+        Opcode::OpEqual => Some(left == right),
+        Opcode::OpNotEqual => Some(left != right),
+        Opcode::OpGreaterThan => Some(left > right),
+        Opcode::OpGreaterThanEqual => Some(left >= right),
+        Opcode::OpLessThan => Some(left < right),
+        Opcode::OpLessThanEqual => Some(left <= right),
+        _ => None,
+    }
+}
 
-        if *sp == 0 {
-            return None;
-        }
+/// Error for a binary operator applied to operands it doesn't support
+fn operator_error(op: Opcode, left: &Object, right: &Object) -> MonkeyError {
+    let (op, left, right) = (operator(op), left.type_name(), right.type_name());
+
+    if left == right {
+        MonkeyError::UnknownOperator { op, left, right }
+    } else {
+        MonkeyError::TypeMismatch { op, left, right }
+    }
+}
+
+fn hash_key(index: &Object) -> Result<Object> {
+    match oth(index.clone()) {
+        Object::Error(_) => Err(MonkeyError::Unhashable(index.clone())),
+        key => Ok(key),
+    }
+}
+
+/// A copy of `obj`. Numbers, booleans and null are copied here rather than
+/// by `Object::clone`, which is too large to be inlined.
+fn cloned(obj: &Object) -> Object {
+    match obj {
+        Object::Integer(i) => Object::Integer(*i),
+        Object::Float(f) => Object::Float(*f),
+        Object::Boolean(b) => Object::Boolean(*b),
+        Object::Null => NULL,
+        obj => obj.clone(),
+    }
+}
 
-        Some(stack[*sp - 1].clone())
+/// The value of a variable, which is in a cell once a closure captured it
+fn value_of(variable: &Object) -> Object {
+    match variable {
+        Object::Cell(cell) => cloned(&cell.borrow()),
+        value => cloned(value),
     }
 }
 
+/// Set a variable, through its cell if a closure captured it
+fn store(variable: &mut Object, value: Object) {
+    match variable {
+        Object::Cell(cell) => *cell.borrow_mut() = value,
+        variable => *variable = value,
+    }
+}
+
+/// Move a local variable into a cell, unless it already is in one, and
+/// return the cell
+fn captured(variable: &mut Object) -> Object {
+    if !matches!(variable, Object::Cell(_)) {
+        let value = std::mem::replace(variable, NULL);
+        *variable = Object::Cell(Rc::new(RefCell::new(value)));
+    }
+
+    variable.clone()
+}
+
+fn check_argument_count(expected: usize, got: usize) -> Result<()> {
+    if expected != got {
+        return Err(MonkeyError::WrongArgumentCount { expected, got });
+    }
+
+    Ok(())
+}
+
 pub(super) fn native_to_object(input: bool) -> Object {
     match input {
         true => TRUE,
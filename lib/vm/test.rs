@@ -1,6 +1,7 @@
-use std::collections::HashMap;
+use im_rc::{hashmap, vector, HashMap};
 
 use crate::{
+    code::SourceMap,
     common::{oth, parse},
     compiler::Compiler,
     evaluator::object::Object,
@@ -45,6 +46,148 @@ fn test_int_arithmetic() {
     run_tests(tests)
 }
 
+#[test]
+fn test_float_arithmetic() {
+    let tests = vec![
+        make_testcase("1.5", Object::Float(1.5)),
+        make_testcase("1.5 + 2.25", Object::Float(3.75)),
+        make_testcase("1 + 0.5", Object::Float(1.5)),
+        make_testcase("3 - 0.5", Object::Float(2.5)),
+        make_testcase("0.5 * 4", Object::Float(2.0)),
+        make_testcase("1 / 4.0", Object::Float(0.25)),
+        make_testcase("-2.5", Object::Float(-2.5)),
+        make_testcase("1.0 / 0", Object::Float(f64::INFINITY)),
+        make_testcase("1 < 1.5", Object::Boolean(true)),
+        make_testcase("2.0 >= 2", Object::Boolean(true)),
+        make_testcase("1 == 1.0", Object::Boolean(true)),
+        make_testcase("0.1 + 0.2 != 0.3", Object::Boolean(true)),
+        make_testcase("float(3)", Object::Float(3.0)),
+        make_testcase("float(\"2.5\")", Object::Float(2.5)),
+        make_testcase("int(-2.75)", Object::Integer(-2)),
+        make_testcase("int(\"42\")", Object::Integer(42)),
+        make_testcase(
+            "int(1.0 / 0)",
+            Object::Error("inf is out of range for int".to_string()),
+        ),
+    ];
+
+    run_tests(tests)
+}
+
+#[test]
+fn test_big_integers() {
+    let big = |digits: &str| Object::BigInt(digits.parse().unwrap());
+    let tests = vec![
+        make_testcase("9223372036854775808", big("9223372036854775808")),
+        make_testcase("-9223372036854775808", Object::Integer(i64::MIN)),
+        make_testcase("9223372036854775807 + 1", big("9223372036854775808")),
+        make_testcase("-9223372036854775807 - 2", big("-9223372036854775809")),
+        make_testcase("4611686018427387904 * 4", big("18446744073709551616")),
+        make_testcase("-(-9223372036854775807 - 1)", big("9223372036854775808")),
+        make_testcase(
+            "(-9223372036854775807 - 1) / -1",
+            big("9223372036854775808"),
+        ),
+        make_testcase("9223372036854775808 - 1", Object::Integer(i64::MAX)),
+        make_testcase(
+            "9223372036854775808 == 9223372036854775807 + 1",
+            Object::Boolean(true),
+        ),
+        make_testcase(
+            "9223372036854775807 < 9223372036854775808",
+            Object::Boolean(true),
+        ),
+        make_testcase(
+            "9223372036854775808 * 1.0",
+            Object::Float(9223372036854775808.0),
+        ),
+        make_testcase(
+            "{9223372036854775808: 1}[9223372036854775807 + 1]",
+            Object::Integer(1),
+        ),
+        make_testcase("int(1e20)", big("100000000000000000000")),
+    ];
+
+    run_tests(tests)
+}
+
+#[test]
+fn test_operations_with_constant_operands() {
+    let big = |digits: &str| Object::BigInt(digits.parse().unwrap());
+    // run without pushing the operands when both are integers, and as usual
+    // otherwise
+    let tests = vec![
+        make_testcase("let f = fn(x) { x - 1 }; f(5)", Object::Integer(4)),
+        make_testcase("let f = fn(x) { x * 2 + 1 }; f(5)", Object::Integer(11)),
+        make_testcase("let f = fn(x) { 10 - x }; f(3)", Object::Integer(7)),
+        make_testcase("let f = fn(x) { x == 1 }; f(1)", Object::Boolean(true)),
+        make_testcase(
+            "let f = fn(x) { if (x < 3) { 1 } else { 2 } }; f(2) * 10 + f(3)",
+            Object::Integer(12),
+        ),
+        make_testcase(
+            "let x = 5; if (x > 4) { x - 1 } else { 0 }",
+            Object::Integer(4),
+        ),
+        make_testcase(
+            "let f = fn(x) { x + 1 }; f(9223372036854775807)",
+            big("9223372036854775808"),
+        ),
+        make_testcase("let f = fn(x) { x + 1 }; f(1.5)", Object::Float(2.5)),
+        make_testcase("let f = fn(x) { x ** 2 }; f(3)", Object::Integer(9)),
+        make_testcase(
+            "let f = fn(x) { let g = fn() { x }; x - 1 + g() }; f(5)",
+            Object::Integer(9),
+        ),
+    ];
+
+    run_tests(tests)
+}
+
+#[test]
+fn test_modulo_power_and_bitwise() {
+    let big = |digits: &str| Object::BigInt(digits.parse().unwrap());
+    let tests = vec![
+        make_testcase("7 % 3", Object::Integer(1)),
+        make_testcase("-7 % 3", Object::Integer(-1)),
+        make_testcase("7.5 % 2", Object::Float(1.5)),
+        make_testcase("2 ** 3 ** 2", Object::Integer(512)),
+        make_testcase("-2 ** 2", Object::Integer(-4)),
+        make_testcase("(-2) ** 3", Object::Integer(-8)),
+        make_testcase("2 ** -1", Object::Float(0.5)),
+        make_testcase("4.0 ** 0.5", Object::Float(2.0)),
+        make_testcase("2 ** 64", big("18446744073709551616")),
+        make_testcase("6 & 3", Object::Integer(2)),
+        make_testcase("6 | 3", Object::Integer(7)),
+        make_testcase("6 ^ 3", Object::Integer(5)),
+        make_testcase("~5", Object::Integer(-6)),
+        make_testcase("1 << 64", big("18446744073709551616")),
+        make_testcase("18446744073709551616 >> 63", Object::Integer(2)),
+        make_testcase("-1 >> 100", Object::Integer(-1)),
+        make_testcase("1 & 3 == 1", Object::Boolean(true)),
+    ];
+
+    run_tests(tests)
+}
+
+#[test]
+fn test_logical_operators() {
+    let tests = vec![
+        make_testcase("true && true", Object::Boolean(true)),
+        make_testcase("true && false", Object::Boolean(false)),
+        make_testcase("false || true", Object::Boolean(true)),
+        make_testcase("false || false", Object::Boolean(false)),
+        make_testcase("1 < 2 && 2 < 3 || false", Object::Boolean(true)),
+        make_testcase("false && 1 / 0 == 1", Object::Boolean(false)),
+        make_testcase("true || 1 / 0 == 1", Object::Boolean(true)),
+        make_testcase("1 && \"a\"", Object::Boolean(true)),
+        make_testcase("[] || false", Object::Boolean(true)),
+        make_testcase("let x = if (false) { 1 }; x || x", Object::Boolean(false)),
+    ];
+
+    run_tests(tests)
+}
+
 #[test]
 fn test_bool_expr() {
     let tests = vec![
@@ -54,6 +197,13 @@ fn test_bool_expr() {
         make_testcase("1 > 2", Object::Boolean(false)),
         make_testcase("1 < 1", Object::Boolean(false)),
         make_testcase("1 > 1", Object::Boolean(false)),
+        make_testcase("1 >= 1", Object::Boolean(true)),
+        make_testcase("1 >= 2", Object::Boolean(false)),
+        make_testcase("2 >= 1", Object::Boolean(true)),
+        make_testcase("1 <= 1", Object::Boolean(true)),
+        make_testcase("1 <= 2", Object::Boolean(true)),
+        make_testcase("2 <= 1", Object::Boolean(false)),
+        make_testcase("(1 <= 2) == (2 >= 1)", Object::Boolean(true)),
         make_testcase("1 == 1", Object::Boolean(true)),
         make_testcase("1 != 1", Object::Boolean(false)),
         make_testcase("1 == 2", Object::Boolean(false)),
@@ -108,6 +258,7 @@ fn test_global_let_stmts() {
             "let one = 1; let two = one + one; one + two",
             Object::Integer(3),
         ),
+        make_testcase("let one = 1; let two = 2;", Object::Integer(2)),
     ];
 
     run_tests(tests);
@@ -116,11 +267,11 @@ fn test_global_let_stmts() {
 #[test]
 fn test_string_expr() {
     let tests = vec![
-        make_testcase("\"monkey\"", Object::String("monkey".to_string())),
-        make_testcase("\"mon\" + \"key\"", Object::String("monkey".to_string())),
+        make_testcase("\"monkey\"", Object::String("monkey".into())),
+        make_testcase("\"mon\" + \"key\"", Object::String("monkey".into())),
         make_testcase(
             "\"mon\" + \"key\" + \"banana\"",
-            Object::String("monkeybanana".to_string()),
+            Object::String("monkeybanana".into()),
         ),
     ];
 
@@ -130,10 +281,10 @@ fn test_string_expr() {
 #[test]
 fn test_array_literals() {
     let tests = vec![
-        make_testcase("[]", Object::Array(vec![])),
+        make_testcase("[]", Object::Array(vector![])),
         make_testcase(
             "[1, 2, 3]",
-            Object::Array(vec![
+            Object::Array(vector![
                 Object::Integer(1),
                 Object::Integer(2),
                 Object::Integer(3),
@@ -141,7 +292,7 @@ fn test_array_literals() {
         ),
         make_testcase(
             "[1 + 2, 3 * 4, 5 + 6]",
-            Object::Array(vec![
+            Object::Array(vector![
                 Object::Integer(3),
                 Object::Integer(12),
                 Object::Integer(11),
@@ -158,19 +309,26 @@ fn test_hash_literals() {
         make_testcase("{}", Object::Hash(HashMap::new())),
         make_testcase(
             "{1: 2, 2: 3}",
-            Object::Hash(HashMap::from([
-                (oth(Object::Integer(1)), Object::Integer(2)),
-                (oth(Object::Integer(2)), Object::Integer(3)),
-            ])),
+            Object::Hash(hashmap! {
+                oth(Object::Integer(1)) => Object::Integer(2),
+                oth(Object::Integer(2)) => Object::Integer(3),
+            }),
+        ),
+        make_testcase(
+            "{1 + 1: 2 * 2, 3 + 3: 4 * 4}",
+            Object::Hash(hashmap! {
+                oth(Object::Integer(2)) => Object::Integer(4),
+                oth(Object::Integer(6)) => Object::Integer(16),
+            }),
+        ),
+        make_testcase(
+            "let name = \"n\"; let f = fn(x) { x * 2 }; let h = {name: 1, f(2): 3, -1: 4}; [h[\"n\"], h[4], h[-1]]",
+            Object::Array(vector![Object::Integer(1), Object::Integer(3), Object::Integer(4)]),
+        ),
+        make_testcase(
+            "let i = 0; let h = {(i += 1): i, (i += 1): i}; [h[1], h[2]]",
+            Object::Array(vector![Object::Integer(1), Object::Integer(2)]),
         ),
-        // parsing error
-        // make_testcase(
-        //     "{1 + 1: 2 * 2, 3 + 3: 4 * 4}",
-        //     Object::Hash(HashMap::from([
-        //         (oth(Object::Integer(2)), Object::Integer(4)),
-        //         (oth(Object::Integer(6)), Object::Integer(16)),
-        //     ])),
-        // ),
     ];
 
     run_tests(tests);
@@ -230,6 +388,24 @@ fn test_call_fn_without_return_val() {
     run_tests(tests);
 }
 
+#[test]
+fn test_top_level_return() {
+    let tests = vec![
+        make_testcase("return 5; 6", Object::Integer(5)),
+        make_testcase(
+            "let a = 1; if (a > 0) { return a + 1; } 3",
+            Object::Integer(2),
+        ),
+        make_testcase("let f = fn() { 2 }; return f(); 3", Object::Integer(2)),
+        make_testcase(
+            "for (x in [1, 2, 3]) { if (x == 2) { return x } } 0",
+            Object::Integer(2),
+        ),
+    ];
+
+    run_tests(tests);
+}
+
 #[test]
 fn test_first_class_func() {
     let tests = vec![make_testcase(
@@ -369,7 +545,7 @@ fn test_builtin_fns() {
         //     "tail(1)",
         //     Object::Error("invalid arguments for tail".to_string()),
         // ),
-        // make_testcase("push([], 1)", Object::Array(vec![Object::Integer(1)])),
+        // make_testcase("push([], 1)", Object::Array(vector![Object::Integer(1)])),
         // make_testcase(
         //     "push(1, 1)",
         //     Object::Error("invalid arguments for push".to_string()),
@@ -379,6 +555,40 @@ fn test_builtin_fns() {
     run_tests(tests);
 }
 
+#[test]
+fn test_persistent_arrays() {
+    let tests = vec![
+        make_testcase(
+            "let a = [1, 2]; let b = push(3, a); let c = cons(0, a); [a, b, c, tail(c)]",
+            Object::Array(vector![
+                Object::Array(vector![Object::Integer(1), Object::Integer(2)]),
+                Object::Array(vector![
+                    Object::Integer(1),
+                    Object::Integer(2),
+                    Object::Integer(3)
+                ]),
+                Object::Array(vector![
+                    Object::Integer(0),
+                    Object::Integer(1),
+                    Object::Integer(2)
+                ]),
+                Object::Array(vector![Object::Integer(1), Object::Integer(2)]),
+            ]),
+        ),
+        // quadratic if push and tail copied the array
+        make_testcase(
+            "let a = []; let i = 0;
+            while (i < 50000) { a = push(i, a); i += 1 };
+            let sum = 0;
+            while (len(a) > 0) { sum += head(a); a = tail(a) };
+            sum",
+            Object::Integer(1249975000),
+        ),
+    ];
+
+    run_tests(tests);
+}
+
 #[test]
 fn test_closures() {
     let tests = vec![
@@ -446,6 +656,187 @@ fn test_closures() {
     run_tests(tests);
 }
 
+#[test]
+fn test_assignment() {
+    let tests = vec![
+        make_testcase("let x = 1; x = 2; x", Object::Integer(2)),
+        make_testcase("let x = 1; x += 2; x *= 3; x", Object::Integer(9)),
+        make_testcase(
+            "let x = 7; x %= 4; x **= 3; x <<= 1; x",
+            Object::Integer(54),
+        ),
+        make_testcase("let x = 1; let y = x = 3; x + y", Object::Integer(6)),
+        make_testcase("let x = 1; (x = 5) + 1", Object::Integer(6)),
+        make_testcase("let s = \"a\"; s += \"b\"; s", Object::String("ab".into())),
+        make_testcase("fn() { let x = 1; x -= 3; x }()", Object::Integer(-2)),
+        make_testcase("fn(x) { x += 1; x }(1)", Object::Integer(2)),
+    ];
+
+    run_tests(tests);
+}
+
+#[test]
+fn test_index_assignment() {
+    let tests = vec![
+        make_testcase(
+            "let a = [1, 2, 3]; a[1] = 5; a",
+            Object::Array(vector![
+                Object::Integer(1),
+                Object::Integer(5),
+                Object::Integer(3),
+            ]),
+        ),
+        make_testcase("let a = [1, 2]; a[0] += 10", Object::Integer(11)),
+        make_testcase(
+            "let a = [[1, 2], [3]]; a[0][1] *= 10; a[1][0] = 4; a",
+            Object::Array(vector![
+                Object::Array(vector![Object::Integer(1), Object::Integer(20)]),
+                Object::Array(vector![Object::Integer(4)]),
+            ]),
+        ),
+        make_testcase(
+            "let h = {\"a\": 1}; h[\"b\"] = 2; h[\"a\"] -= 5; h[\"a\"] + h[\"b\"]",
+            Object::Integer(-2),
+        ),
+        make_testcase(
+            "let h = {}; h[1] = {}; h[1][true] = 3; h[1][true]",
+            Object::Integer(3),
+        ),
+        // arrays and hashes are values, so a copy doesn't see the change
+        make_testcase("let a = [1]; let b = a; a[0] = 2; b[0]", Object::Integer(1)),
+        make_testcase(
+            "let f = fn(a) { a[0] = 9; a }; let a = [1]; f(a)[0] + a[0]",
+            Object::Integer(10),
+        ),
+        make_testcase(
+            "let a = [0, 0]; let i = 0; a[i = 1] = i; a",
+            Object::Array(vector![Object::Integer(0), Object::Integer(1)]),
+        ),
+    ];
+
+    run_tests(tests);
+}
+
+#[test]
+fn test_closure_assignment() {
+    let tests = vec![
+        make_testcase(
+            "let counter = fn() {
+                let n = 0;
+                fn() { n += 1; n }
+            };
+            let c = counter();
+            c(); c();
+            c()",
+            Object::Integer(3),
+        ),
+        make_testcase(
+            "let counter = fn() {
+                let n = 0;
+                fn() { n += 1; n }
+            };
+            let c = counter();
+            let d = counter();
+            c(); c();
+            d()",
+            Object::Integer(1),
+        ),
+        make_testcase(
+            "let f = fn() {
+                let a = 1;
+                let g = fn() {
+                    a = a + 1;
+                    fn() { a += 10; a }
+                };
+                g()() + a
+            };
+            f()",
+            Object::Integer(24),
+        ),
+        make_testcase(
+            "let f = fn() {
+                let x = 1;
+                let get = fn() { x };
+                let x = 5;
+                get()
+            };
+            f()",
+            Object::Integer(5),
+        ),
+        make_testcase(
+            "let x = 1; let set = fn(v) { x = v }; set(3); x",
+            Object::Integer(3),
+        ),
+        make_testcase(
+            "let f = fn() {
+                let sum = fn(n) { if (n == 0) { 0 } else { n + sum(n - 1) } };
+                sum(4)
+            };
+            f()",
+            Object::Integer(10),
+        ),
+        // each call starts with fresh locals, not the cells of the last one
+        make_testcase(
+            "let f = fn(v) { let x = v; fn() { x } };
+            let a = f(1);
+            let b = f(2);
+            a() * 10 + b()",
+            Object::Integer(12),
+        ),
+    ];
+
+    run_tests(tests);
+}
+
+#[test]
+fn test_loops() {
+    let tests = vec![
+        make_testcase(
+            "let i = 0; let s = 0; while (i < 10) { i += 1; if (i % 2 == 0) { continue; } s += i; } s",
+            Object::Integer(25),
+        ),
+        make_testcase(
+            "let s = 0; for (x in [1, 2, 3, 4]) { if (x == 3) { break } s += x } s",
+            Object::Integer(3),
+        ),
+        make_testcase(
+            "let r = \"\"; for (c in \"abc\") { r = c + r } r",
+            Object::String("cba".into()),
+        ),
+        make_testcase(
+            "let r = []; for (k in {\"b\": 1, 2: 0, true: 0, \"a\": 2, 10: 0}) { r = push(k, r) } r",
+            Object::Array(vector![
+                Object::Integer(2),
+                Object::Integer(10),
+                Object::Boolean(true),
+                Object::String("a".into()),
+                Object::String("b".into()),
+            ]),
+        ),
+        make_testcase(
+            "let s = 0; for (x in [[1, 2], [3]]) { for (y in x) { if (y == 2) { break } s += y } } s",
+            Object::Integer(4),
+        ),
+        make_testcase(
+            "let f = fn(xs) { for (x in xs) { if (x > 1) { return x } } }; f([1, 5, 7])",
+            Object::Integer(5),
+        ),
+        make_testcase("while (false) { 1 }", Object::Null),
+        make_testcase("let a = if (true) { for (x in []) {} }; a", Object::Null),
+        // far more iterations than recursion could go deep
+        make_testcase(
+            "let i = 0; while (i < 100000) { i += 1 } i",
+            Object::Integer(100000),
+        ),
+        make_testcase(
+            "let f = fn() { let n = 0; for (x in [1, 2, 3]) { let g = fn() { n += x } g() } n }; f()",
+            Object::Integer(6),
+        ),
+    ];
+
+    run_tests(tests);
+}
+
 #[test]
 fn test_recursive_fibonacci() {
     let tests = vec![make_testcase(
@@ -468,6 +859,169 @@ fn test_recursive_fibonacci() {
     run_tests(tests);
 }
 
+#[test]
+fn test_tail_calls() {
+    let tests = vec![
+        // far deeper than the frame limit
+        make_testcase(
+            "let count = fn(n) { if (n == 0) { 0 } else { count(n - 1) } }; count(100000)",
+            Object::Integer(0),
+        ),
+        make_testcase(
+            "let a = []; let i = 0;
+            while (i < 100000) { a = push(i, a); i += 1 };
+            let sum = fn(arr, acc) {
+                if (len(arr) == 0) { return acc; }
+                sum(tail(arr), acc + head(arr))
+            };
+            sum(a, 0)",
+            Object::Integer(4999950000),
+        ),
+        make_testcase(
+            "let total = fn() {
+                let t = 0;
+                let add = fn(n) { if (n == 0) { t } else { t += n; add(n - 1) } };
+                add(100000)
+            };
+            total()",
+            Object::Integer(5000050000),
+        ),
+        // the callee's closure, not the caller's, is used after the call
+        make_testcase(
+            "let f = fn(x) { let y = x * 2; let g = fn() { y }; if (x > 3) { g() } else { f(x + 1) } }; f(0)",
+            Object::Integer(8),
+        ),
+        make_testcase("let f = fn(a) { len(a) }; f([1, 2])", Object::Integer(2)),
+        make_testcase(
+            "let f = fn(a) { push(1, a) }; len(f([])) + 1",
+            Object::Integer(2),
+        ),
+    ];
+
+    run_tests(tests);
+}
+
+#[test]
+fn test_runtime_errors() {
+    let tests = [
+        ("1 + true", "Type mismatch: integer + boolean"),
+        ("\"a\" - \"b\"", "Unknown operator: string - string"),
+        ("true > false", "Unknown operator: boolean > boolean"),
+        ("1 > \"a\"", "Type mismatch: integer > string"),
+        ("1 < \"a\"", "Type mismatch: integer < string"),
+        ("[] <= []", "Unknown operator: array <= array"),
+        ("1(2)", "Not a function: 1"),
+        (
+            "fn(a) { a }(1, 2)",
+            "Wrong number of arguments: 1 expected but 2 given",
+        ),
+        ("1[0]", "Index operator not supported: integer[integer]"),
+        ("[1][\"a\"]", "Index operator not supported: array[string]"),
+        ("-true", "Unsupported type for negation: true"),
+        ("1.5 + true", "Type mismatch: float + boolean"),
+        ("1 / 0", "division by zero"),
+        ("let x = 0; 5 / x", "division by zero"),
+        ("let f = fn(x) { x / 0 }; f(1)", "division by zero"),
+        ("9223372036854775808 / 0", "division by zero"),
+        ("5 % 0", "division by zero"),
+        ("1 << -1", "negative shift count"),
+        ("2 ** 100000000", "result too large"),
+        ("1.5 & 1", "Type mismatch: float & integer"),
+        ("~true", "Unsupported type for negation: true"),
+        ("{1: 2}[[1]]", "Unusable as hash key: [1]"),
+        (
+            "let a = [1]; a[3] = 1",
+            "Index out of bounds: 3 for array of length 1",
+        ),
+        (
+            "let a = [[1]]; a[0][-1] = 1",
+            "Index out of bounds: -1 for array of length 1",
+        ),
+        (
+            "let x = 1; x[0] = 2",
+            "Index operator not supported: integer[integer]",
+        ),
+        ("let h = {}; h[[]] = 1", "Unusable as hash key: []"),
+        ("for (x in 5) { x }", "Not iterable: 5"),
+        ("{[1]: 2}", "Unusable as hash key: [1]"),
+        ("{1: 2, fn() { 1 }: 3}", "Unusable as hash key: [closure]"),
+        ("let f = fn() { 1 + f() }; f()", "Max stack size reached"),
+    ];
+
+    for (input, expected) in tests {
+        let mut compiler = Compiler::new();
+        compiler.compile(parse(input.to_string())).unwrap();
+
+        let mut vm = VM::new(compiler.bytecode());
+        match vm.run() {
+            Ok(()) => panic!("expected error for {}", input),
+            Err(err) => assert_eq!(expected, err.to_string()),
+        }
+    }
+}
+
+#[test]
+fn test_invalid_opcode() {
+    let bytecode = Bytecode {
+        instructions: vec![255],
+        constants: vec![],
+        source_map: SourceMap::new(),
+    };
+
+    let mut vm = VM::new(bytecode);
+    match vm.run() {
+        Err(RuntimeError {
+            error: MonkeyError::InvalidOpcode(255),
+            ..
+        }) => {}
+        other => panic!("expected invalid opcode error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_stack_trace() {
+    let input = "let inner = fn(x) { x + true };\nlet outer = fn() { inner(1) + 1 };\nouter();";
+    let mut compiler = Compiler::new();
+    compiler.compile(parse(input.to_string())).unwrap();
+
+    let mut vm = VM::new(compiler.bytecode());
+    let err = vm.run().unwrap_err();
+
+    let frames = err
+        .trace
+        .0
+        .iter()
+        .map(|f| (f.function.clone().unwrap(), f.span.unwrap().line))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        frames,
+        [
+            ("inner".to_string(), 1),
+            ("outer".to_string(), 2),
+            ("<main>".to_string(), 3)
+        ]
+    );
+    assert_eq!(err.trace.0[0].offset, 3);
+    assert_eq!(err.span(), err.trace.0[0].span);
+}
+
+#[test]
+fn test_recover_after_runtime_error() {
+    // as the repl does, each line compiled on top of the ones before
+    let mut compiler = Compiler::new();
+    compiler.compile(parse("let a = 1;".to_string())).unwrap();
+
+    let checkpoint = compiler.checkpoint();
+    compiler.compile(parse("a / 0".to_string())).unwrap();
+    assert!(VM::new(compiler.bytecode()).run().is_err());
+    compiler.restore(checkpoint);
+
+    compiler.compile(parse("a + 1".to_string())).unwrap();
+    let mut vm = VM::new(compiler.bytecode());
+    vm.run().unwrap();
+    test_int_obj(2, vm.last_popped_stack_ele());
+}
+
 fn test_int_obj(expected: i64, actual: Object) {
     match actual {
         Object::Integer(v) => {
@@ -492,7 +1046,7 @@ fn test_bool_obj(expected: bool, actual: Object) {
     }
 }
 
-fn test_string_obj(expected: String, actual: Object) {
+fn test_string_obj(expected: Rc<str>, actual: Object) {
     match actual {
         Object::String(v) => assert_eq!(
             expected, v,
@@ -507,7 +1061,7 @@ fn run_tests(tests: Vec<TestCase>) {
     for test in tests {
         let program = parse(test.input);
         let mut compiler = Compiler::new();
-        compiler.compile(program);
+        compiler.compile(program).unwrap();
 
         let bytecode = compiler.bytecode();
 
@@ -523,6 +1077,8 @@ fn run_tests(tests: Vec<TestCase>) {
 fn test_expected(expected: Object, actual: &Object) {
     match expected {
         Object::Integer(v) => test_int_obj(v, actual.clone()),
+        Object::Float(v) => assert_eq!(Object::Float(v), *actual),
+        Object::BigInt(v) => assert_eq!(Object::BigInt(v), *actual),
         Object::Boolean(v) => test_bool_obj(v, actual.clone()),
         Object::String(v) => test_string_obj(v, actual.clone()),
         Object::Array(arr) => {
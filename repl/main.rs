@@ -1,12 +1,16 @@
 extern crate monkey_lib;
+#[macro_use]
+extern crate clap;
 extern crate nom;
 extern crate rustyline;
 extern crate rustyline_derive;
 
 use monkey_lib::compiler::Compiler;
-// use monkey_lib::evaluator::*;
+use monkey_lib::engine::Engine;
+use monkey_lib::evaluator::Evaluator;
 use monkey_lib::lexer::token::*;
 use monkey_lib::lexer::*;
+use monkey_lib::parser::ast::Program;
 use monkey_lib::parser::*;
 use monkey_lib::vm::VM;
 use nom::Err;
@@ -89,7 +93,67 @@ impl Validator for MyHelper {
     }
 }
 
+/// State kept between lines by the selected engine
+enum Session {
+    Eval(Evaluator),
+    Vm(Compiler),
+}
+
+impl Session {
+    fn new(engine: Engine) -> Self {
+        match engine {
+            Engine::Eval => Session::Eval(Evaluator::new()),
+            Engine::Vm => Session::Vm(Compiler::new()),
+        }
+    }
+
+    fn run(&mut self, program: Program) {
+        match self {
+            Session::Eval(evaluator) => println!("{}", evaluator.eval_program(program)),
+            Session::Vm(compiler) => run_vm(compiler, program),
+        }
+    }
+}
+
+/// Compile `program` on top of everything entered so far and run the result.
+/// A program that fails at runtime is dropped, so later lines don't rerun it.
+fn run_vm(compiler: &mut Compiler, program: Program) {
+    let checkpoint = compiler.checkpoint();
+    if let Err(err) = compiler.compile(program) {
+        println!("Compile error at {}: {}", err.span(), err);
+        return;
+    }
+
+    let mut machine = VM::new(compiler.bytecode());
+    match machine.run() {
+        Ok(()) => {
+            let last_popped = machine.last_popped_stack_ele();
+            println!("{}", last_popped);
+        }
+        Err(err) => {
+            compiler.restore(checkpoint);
+            match err.span() {
+                Some(span) => println!("Runtime error at {}: {}", span, err),
+                None => println!("Runtime error: {}", err),
+            }
+            print!("{}", err.trace);
+        }
+    }
+}
+
 fn main() -> rustyline::Result<()> {
+    let matches = clap_app!(monkey_repl =>
+        (version: "0.5.0")
+        (about: "The Monkey programming language repl")
+        (@arg engine: -e --engine +takes_value possible_values(&Engine::NAMES)
+            "Run with the tree-walking evaluator or the bytecode vm (default: vm)")
+    )
+    .get_matches();
+    let engine = matches
+        .value_of("engine")
+        .and_then(|e| e.parse().ok())
+        .unwrap_or(Engine::Vm);
+
     let config = Config::builder()
         .history_ignore_space(true)
         .completion_type(CompletionType::List)
@@ -116,8 +180,7 @@ fn main() -> rustyline::Result<()> {
     println!("Press Ctrl-D or enter \"quit\" to exit.");
     println!();
 
-    // let mut evaluator = Evaluator::new();
-    let mut compiler = Compiler::new();
+    let mut session = Session::new(engine);
     let mut count = 1;
 
     loop {
@@ -133,18 +196,8 @@ fn main() -> rustyline::Result<()> {
                         let tokens = Tokens::new(&r);
                         let parsed = Parser::parse_tokens(tokens);
                         match parsed {
-                            Ok((_, program)) => {
-                                compiler.compile(program);
-                                let mut machine = VM::new(compiler.bytecode());
-                                machine.run().unwrap();
-
-                                let last_popped = machine.last_popped_stack_ele();
-                                // let eval = evaluator.eval_program(program);
-                                println!("{}", last_popped);
-                            }
-                            Err(Err::Error(_)) => println!("Parser error"),
-                            Err(Err::Failure(_)) => println!("Parser failure"),
-                            Err(Err::Incomplete(_)) => println!("Incomplete parsing"),
+                            Ok(program) => session.run(program),
+                            Err(e) => println!("Parse error at {}: {}", e.span, e),
                         }
                     }
                     Err(Err::Error(_)) => println!("Lexer error"),
@@ -1,3 +1,5 @@
+use monkey_lib::engine::Engine;
+
 #[derive(Debug)]
 pub enum Command {
     FileRead(String),
@@ -5,7 +7,7 @@ pub enum Command {
     Noop,
 }
 
-pub fn read_command() -> Command {
+pub fn read_command() -> (Command, Engine) {
     let matches = clap_app!(monkey =>
         (version: "0.5.0")
         (author: "Jérôme Mahuet <jerome.mahuet@gmail.com>")
@@ -13,14 +15,23 @@ pub fn read_command() -> Command {
         (@setting ArgRequiredElseHelp)
         (@arg src: -s --src +takes_value "Path of the source file")
         (@arg run: -r --run +takes_value "Code you want to run inline")
+        (@arg engine: -e --engine +takes_value possible_values(&Engine::NAMES)
+            "Run with the tree-walking evaluator or the bytecode vm (default: vm)")
     )
     .get_matches();
 
     let src_path = matches.value_of("src").map(|s| s.to_string());
     let run_string = matches.value_of("run").map(|s| s.to_string());
-    match (src_path, run_string) {
+    let engine = matches
+        .value_of("engine")
+        .and_then(|e| e.parse().ok())
+        .unwrap_or(Engine::Vm);
+
+    let command = match (src_path, run_string) {
         (Some(s), _) => Command::FileRead(s),
         (_, Some(s)) => Command::RunInlineCode(s),
         _ => Command::Noop,
-    }
+    };
+
+    (command, engine)
 }
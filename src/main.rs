@@ -3,10 +3,14 @@ extern crate monkey_lib;
 extern crate clap;
 extern crate nom;
 
+use monkey_lib::compiler::Compiler;
+use monkey_lib::engine::Engine;
 use monkey_lib::evaluator::*;
 use monkey_lib::lexer::token::*;
 use monkey_lib::lexer::*;
+use monkey_lib::parser::ast::Program;
 use monkey_lib::parser::*;
+use monkey_lib::vm::VM;
 use nom::Err;
 use std::fs::File;
 use std::io::prelude::*;
@@ -21,28 +25,54 @@ fn read_file(file_path: String) -> Result<String, ::std::io::Error> {
     Ok(contents)
 }
 
+fn run(engine: Engine, program: Program) {
+    match engine {
+        Engine::Eval => {
+            let mut evaluator = Evaluator::new();
+            let eval = evaluator.eval_program(program);
+            println!("{}", eval);
+        }
+        Engine::Vm => {
+            let mut compiler = Compiler::new();
+            if let Err(err) = compiler.compile(program) {
+                println!("Compile error at {}: {}", err.span(), err);
+                return;
+            }
+
+            let mut machine = VM::new(compiler.bytecode());
+            match machine.run() {
+                Ok(()) => println!("{}", machine.last_popped_stack_ele()),
+                Err(err) => {
+                    match err.span() {
+                        Some(span) => println!("Runtime error at {}: {}", span, err),
+                        None => println!("Runtime error: {}", err),
+                    }
+                    print!("{}", err.trace);
+                }
+            }
+        }
+    }
+}
+
 fn main() {
-    let code_string = match cmd::read_command() {
+    let (command, engine) = cmd::read_command();
+    let code_string = match command {
         Command::FileRead(file_path) => read_file(file_path).ok(),
         Command::RunInlineCode(code) => Some(code),
         Command::Noop => None,
     };
 
     if let Some(code_string) = code_string {
-        let mut evaluator = Evaluator::new();
         let lex_tokens = Lexer::lex_tokens(code_string.as_bytes());
         match lex_tokens {
             Ok((_, r)) => {
                 let tokens = Tokens::new(&r);
-                let parsed = Parser::parse_tokens(tokens);
-                match parsed {
-                    Ok((_, program)) => {
-                        let eval = evaluator.eval_program(program);
-                        println!("{}", eval);
-                    }
-                    Err(Err::Error(_)) => println!("Parser error"),
-                    Err(Err::Failure(_)) => println!("Parser failure"),
-                    Err(Err::Incomplete(_)) => println!("Incomplete parsing"),
+                let (program, errors) = Parser::parse_recovering(tokens);
+                if errors.is_empty() {
+                    run(engine, program);
+                }
+                for e in errors {
+                    println!("Parse error at {}: {}", e.span, e);
                 }
             }
             Err(Err::Error(_)) => println!("Lexer error"),
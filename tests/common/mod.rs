@@ -0,0 +1,23 @@
+use monkey_lib::engine::{Engine, Outcome};
+use monkey_lib::parser::ast::Program;
+
+/// Run `program` on both engines, returning their outcomes when they disagree
+pub fn compare_engines(program: &Program) -> Option<(Outcome, Outcome)> {
+    let eval = Engine::Eval.run(program.clone());
+    let vm = Engine::Vm.run(program.clone());
+    match eval.agrees_with(&vm) || known_divergence(&eval, &vm) {
+        true => None,
+        false => Some((eval, vm)),
+    }
+}
+
+/// Differences between the engines that are known and accepted for now.
+/// They are recognised by outcome, so any other drift still fails.
+fn known_divergence(eval: &Outcome, vm: &Outcome) -> bool {
+    match (eval, vm) {
+        // the evaluator only accepts booleans in conditions and `!`, while the
+        // VM treats everything but `false` and `null` as truthy
+        (Outcome::Error(msg), Outcome::Value(_)) => msg.ends_with(" is not a bool"),
+        _ => false,
+    }
+}
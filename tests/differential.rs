@@ -0,0 +1,163 @@
+extern crate monkey_lib;
+
+use monkey_lib::lexer::token::*;
+use monkey_lib::lexer::*;
+use monkey_lib::parser::*;
+use std::fs;
+
+mod common;
+
+/// Run every program through both engines, returning a report of each
+/// program they disagree on.
+fn disagreements(programs: &[String]) -> Vec<String> {
+    programs
+        .iter()
+        .filter_map(|input| {
+            let (_, lex_tokens) = Lexer::lex_tokens(input.as_bytes()).unwrap();
+            let program = Parser::parse_tokens(Tokens::new(&lex_tokens)).unwrap();
+            common::compare_engines(&program)
+                .map(|(eval, vm)| format!("{}\n    eval: {:?}\n    vm:   {:?}", input, eval, vm))
+        })
+        .collect()
+}
+
+const VALUES: [&str; 17] = [
+    "0",
+    "7",
+    "-3",
+    "9223372036854775807",
+    "-9223372036854775808",
+    "2.5",
+    "0.0",
+    "true",
+    "false",
+    "\"ab\"",
+    "\"\"",
+    "[]",
+    "[1, \"x\"]",
+    "{}",
+    "{\"a\": 1, 2: true}",
+    "fn(x) { x }",
+    "len",
+];
+
+/// Binary operators, with the ones that have a compound assignment first
+const INFIX: [&str; 19] = [
+    "+", "-", "*", "/", "%", "**", "&", "|", "^", "<<", ">>", "&&", "||", "==", "!=", "<", ">",
+    "<=", ">=",
+];
+
+/// Every operator applied to every combination of a set of values
+fn generated_corpus() -> Vec<String> {
+    let mut programs = Vec::new();
+
+    for a in VALUES {
+        programs.push(format!("-({})", a));
+        programs.push(format!("!({})", a));
+        programs.push(format!("~({})", a));
+        programs.push(format!("if ({}) {{ 1 }} else {{ 2 }}", a));
+        programs.push(format!("let v = {}; v", a));
+        programs.push(format!("fn() {{ {} }}()", a));
+        programs.push(format!(
+            "let r = []; for (x in {}) {{ r = push(x, r) }} r",
+            a
+        ));
+        programs.push(format!("while ({}) {{ break }}", a));
+        programs.push(format!("{{({}): 1}}[{}]", a, a));
+
+        for b in VALUES {
+            for op in INFIX {
+                // functions are equal by structure in the evaluator, but by
+                // compiled code and source position in the VM
+                if a.starts_with("fn") && b.starts_with("fn") && op.ends_with('=') {
+                    continue;
+                }
+                programs.push(format!("({}) {} ({})", a, op, b));
+            }
+            programs.push(format!("({})[{}]", a, b));
+            programs.push(format!("({})({})", a, b));
+            // a function bound in the evaluator's environment refers back to
+            // it, which its Debug and PartialEq don't handle
+            if a.starts_with("fn") {
+                continue;
+            }
+            for op in &INFIX[..11] {
+                programs.push(format!("let v = {}; v {}= ({}); v", a, op, b));
+            }
+            programs.push(format!("let v = {}; v[{}] = 1; v", a, b));
+        }
+    }
+
+    programs
+}
+
+#[test]
+fn examples_agree() {
+    let mut programs = Vec::new();
+    for entry in fs::read_dir("examples").unwrap() {
+        let path = entry.unwrap().path();
+        programs.push(fs::read_to_string(path).unwrap());
+    }
+
+    let failures = disagreements(&programs);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn top_level_return_agrees() {
+    let programs = [
+        "return 5; 6",
+        "let a = 1; if (a > 0) { return a + 1; } 3",
+        "let f = fn() { 2 }; return f(); 3",
+        "for (x in [1, 2, 3]) { if (x == 2) { return x } } 0",
+        "while (true) { return 1 }",
+    ]
+    .map(String::from);
+
+    let failures = disagreements(&programs);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn loop_control_agrees() {
+    let programs = [
+        "let r = 0; for (x in [1, 2, 3]) { if (x == 2) { continue } r += x }; r",
+        "let i = 0; while (true) { if (i > 2) { if (true) { break } } i += 1 }; i",
+        "let r = []; for (x in [1, 2, 3]) { r = push(if (x == 2) { continue } else { x }, r) }; r",
+        "while (true) { 1 + if (true) { break } else { 2 } }",
+        "for (x in [1]) { let y = if (true) { break } else { 1 }; }",
+        "let f = fn() { if (true) { break } }; while (true) { f() }",
+    ]
+    .map(String::from);
+
+    let failures = disagreements(&programs);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// Operands with side effects, which the values of the generated corpus
+/// can't tell apart: both engines evaluate them left to right
+#[test]
+fn evaluation_order_agrees() {
+    let mut programs = Vec::new();
+    for op in INFIX {
+        programs.push(format!("let x = 0; let r = (x = 1) {} (x = 2); x", op));
+        programs.push(format!(
+            "let s = \"\"; let f = fn(v) {{ s += v; 1 }}; f(\"a\") {} f(\"b\"); s",
+            op
+        ));
+    }
+
+    let failures = disagreements(&programs);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn generated_corpus_agrees() {
+    let failures = disagreements(&generated_corpus());
+    assert!(
+        failures.is_empty(),
+        "{} disagreements:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
@@ -21,7 +21,7 @@ fn test_example_hash() {
     let mut evaluator = Evaluator::new();
     let (_, lex_tokens) = Lexer::lex_tokens(code_string.as_bytes()).unwrap();
     let tokens = Tokens::new(&lex_tokens);
-    let (_, program) = Parser::parse_tokens(tokens).unwrap();
+    let program = Parser::parse_tokens(tokens).unwrap();
     let eval = evaluator.eval_program(program);
     assert_eq!(eval, Object::Null);
 }
@@ -32,7 +32,7 @@ fn test_reduce() {
     let mut evaluator = Evaluator::new();
     let (_, lex_tokens) = Lexer::lex_tokens(code_string.as_bytes()).unwrap();
     let tokens = Tokens::new(&lex_tokens);
-    let (_, program) = Parser::parse_tokens(tokens).unwrap();
+    let program = Parser::parse_tokens(tokens).unwrap();
     let eval = evaluator.eval_program(program);
     assert_eq!(eval, Object::Null);
 }
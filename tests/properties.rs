@@ -0,0 +1,436 @@
+extern crate monkey_lib;
+
+use monkey_lib::compiler::Compiler;
+use monkey_lib::error::MonkeyError;
+use monkey_lib::formatter::format_source;
+use monkey_lib::lexer::token::*;
+use monkey_lib::lexer::*;
+use monkey_lib::parser::ast::*;
+use monkey_lib::parser::*;
+use monkey_lib::span::Span;
+use monkey_lib::vm::VM;
+use proptest::prelude::*;
+use proptest::strategy::Union;
+use std::rc::Rc;
+
+mod common;
+
+/// Nesting allowed in a generated expression, function bodies included
+const MAX_DEPTH: u32 = 3;
+
+/// Static type of a generated expression. Programs are generated well typed
+/// so that they run to completion instead of stopping at the first type error.
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Int,
+    Bool,
+    Str,
+    Fn(Vec<Ty>, Box<Ty>),
+}
+
+/// Variables visible at some point of a generated program
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    vars: Vec<(String, Ty)>,
+    next: usize,
+}
+
+impl Scope {
+    /// A name that shadows nothing visible from here
+    fn fresh(&mut self) -> String {
+        self.next += 1;
+        format!("v{}", self.next - 1)
+    }
+
+    fn bind(&mut self, name: String, ty: Ty) {
+        self.vars.push((name, ty));
+    }
+
+    fn of_type(&self, ty: &Ty) -> impl Iterator<Item = &String> {
+        let ty = ty.clone();
+        self.vars
+            .iter()
+            .filter(move |(_, t)| *t == ty)
+            .map(|(n, _)| n)
+    }
+
+    /// Functions in scope returning `ty`, with their parameter types
+    fn returning(&self, ty: &Ty) -> Vec<(String, Vec<Ty>)> {
+        self.vars
+            .iter()
+            .filter_map(|(name, t)| match t {
+                Ty::Fn(params, ret) if **ret == *ty => Some((name.clone(), params.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn expr(kind: ExprKind) -> Expr {
+    Expr {
+        kind,
+        span: Span::default(),
+    }
+}
+
+fn stmt(kind: StmtKind) -> Stmt {
+    Stmt {
+        kind,
+        span: Span::default(),
+    }
+}
+
+fn ident(name: &str) -> Expr {
+    expr(ExprKind::IdentExpr(Ident(name.to_owned())))
+}
+
+fn infix(op: Infix, left: Expr, right: Expr) -> Expr {
+    expr(ExprKind::InfixExpr(op, Box::new(left), Box::new(right)))
+}
+
+fn call(function: Expr, arguments: Vec<Expr>) -> Expr {
+    expr(ExprKind::CallExpr {
+        function: Box::new(function),
+        arguments,
+    })
+}
+
+fn value_ty() -> impl Strategy<Value = Ty> {
+    prop_oneof![Just(Ty::Int), Just(Ty::Bool), Just(Ty::Str)]
+}
+
+fn fn_ty() -> impl Strategy<Value = Ty> {
+    (prop::collection::vec(value_ty(), 0..3), value_ty())
+        .prop_map(|(params, ret)| Ty::Fn(params, Box::new(ret)))
+}
+
+fn string_literal() -> impl Strategy<Value = String> {
+    "[a-z]{0,3}"
+}
+
+/// An expression of type `ty` using only the variables in `scope`
+fn gen_expr(ty: Ty, scope: Rc<Scope>, depth: u32) -> BoxedStrategy<Expr> {
+    let mut options: Vec<BoxedStrategy<Expr>> = Vec::new();
+
+    match &ty {
+        Ty::Int => options.push(
+            (0..10i64)
+                .prop_map(|i| expr(ExprKind::LitExpr(Literal::IntLiteral(i))))
+                .boxed(),
+        ),
+        Ty::Bool => options.push(
+            any::<bool>()
+                .prop_map(|b| expr(ExprKind::LitExpr(Literal::BoolLiteral(b))))
+                .boxed(),
+        ),
+        Ty::Str => options.push(
+            string_literal()
+                .prop_map(|s| expr(ExprKind::LitExpr(Literal::StringLiteral(s))))
+                .boxed(),
+        ),
+        Ty::Fn(params, ret) => options.push(gen_fn(params, (**ret).clone(), scope.clone(), depth)),
+    }
+    for name in scope.of_type(&ty) {
+        options.push(Just(ident(name)).boxed());
+    }
+
+    if depth == 0 {
+        return Union::new(options).boxed();
+    }
+    let d = depth - 1;
+    let sub = |ty: Ty| gen_expr(ty, scope.clone(), d);
+
+    match &ty {
+        Ty::Int => {
+            options.push(
+                sub(Ty::Int)
+                    .prop_map(|e| expr(ExprKind::PrefixExpr(Prefix::PrefixMinus, Box::new(e))))
+                    .boxed(),
+            );
+            options.push(
+                sub(Ty::Int)
+                    .prop_map(|e| expr(ExprKind::PrefixExpr(Prefix::BitNot, Box::new(e))))
+                    .boxed(),
+            );
+            // no `**`: it turns integers into floats for negative exponents
+            let op = prop_oneof![
+                Just(Infix::Plus),
+                Just(Infix::Minus),
+                Just(Infix::Multiply),
+                Just(Infix::Divide),
+                Just(Infix::Modulo),
+                Just(Infix::BitAnd),
+                Just(Infix::BitOr),
+                Just(Infix::BitXor),
+                Just(Infix::ShiftLeft),
+                Just(Infix::ShiftRight),
+            ];
+            options.push(
+                (op, sub(Ty::Int), sub(Ty::Int))
+                    .prop_map(|(op, l, r)| infix(op, l, r))
+                    .boxed(),
+            );
+            options.push(
+                prop::collection::vec(sub(Ty::Int), 1..4)
+                    .prop_flat_map(|items| {
+                        let len = items.len() as i64;
+                        (Just(items), 0..len)
+                    })
+                    .prop_map(|(items, i)| {
+                        expr(ExprKind::IndexExpr {
+                            array: Box::new(expr(ExprKind::ArrayExpr(items))),
+                            index: Box::new(expr(ExprKind::LitExpr(Literal::IntLiteral(i)))),
+                        })
+                    })
+                    .boxed(),
+            );
+            options.push(
+                (string_literal(), sub(Ty::Int))
+                    .prop_map(|(key, value)| {
+                        let hash = vec![(
+                            expr(ExprKind::LitExpr(Literal::StringLiteral(key.clone()))),
+                            value,
+                        )];
+                        expr(ExprKind::IndexExpr {
+                            array: Box::new(expr(ExprKind::HashExpr(hash))),
+                            index: Box::new(expr(ExprKind::LitExpr(Literal::StringLiteral(key)))),
+                        })
+                    })
+                    .boxed(),
+            );
+            options.push(
+                sub(Ty::Str)
+                    .prop_map(|s| call(ident("len"), vec![s]))
+                    .boxed(),
+            );
+        }
+        Ty::Bool => {
+            options.push(
+                sub(Ty::Bool)
+                    .prop_map(|e| expr(ExprKind::PrefixExpr(Prefix::Not, Box::new(e))))
+                    .boxed(),
+            );
+            let op = prop_oneof![
+                Just(Infix::Equal),
+                Just(Infix::NotEqual),
+                Just(Infix::LessThan),
+                Just(Infix::LessThanEqual),
+                Just(Infix::GreaterThan),
+                Just(Infix::GreaterThanEqual),
+            ];
+            options.push(
+                (op, sub(Ty::Int), sub(Ty::Int))
+                    .prop_map(|(op, l, r)| infix(op, l, r))
+                    .boxed(),
+            );
+            let op = prop_oneof![Just(Infix::And), Just(Infix::Or)];
+            options.push(
+                (op, sub(Ty::Bool), sub(Ty::Bool))
+                    .prop_map(|(op, l, r)| infix(op, l, r))
+                    .boxed(),
+            );
+            let op = prop_oneof![Just(Infix::Equal), Just(Infix::NotEqual)];
+            let operand = prop_oneof![Just(Ty::Bool), Just(Ty::Str)];
+            let scope = scope.clone();
+            options.push(
+                (op, operand)
+                    .prop_flat_map(move |(op, ty)| {
+                        let operand = gen_expr(ty, scope.clone(), d);
+                        (Just(op), operand.clone(), operand)
+                    })
+                    .prop_map(|(op, l, r)| infix(op, l, r))
+                    .boxed(),
+            );
+        }
+        Ty::Str => {
+            options.push(
+                (sub(Ty::Str), sub(Ty::Str))
+                    .prop_map(|(l, r)| infix(Infix::Plus, l, r))
+                    .boxed(),
+            );
+        }
+        Ty::Fn(_, _) => {}
+    }
+
+    options.push(
+        (sub(Ty::Bool), sub(ty.clone()), sub(ty.clone()))
+            .prop_map(|(cond, consequence, alternative)| {
+                expr(ExprKind::IfExpr {
+                    cond: Box::new(cond),
+                    consequence: vec![stmt(StmtKind::ExprStmt(consequence))],
+                    alternative: Some(vec![stmt(StmtKind::ExprStmt(alternative))]),
+                })
+            })
+            .boxed(),
+    );
+
+    for (name, params) in scope.returning(&ty) {
+        let arguments: Vec<_> = params.into_iter().map(sub).collect();
+        options.push(
+            arguments
+                .prop_map(move |arguments| call(ident(&name), arguments))
+                .boxed(),
+        );
+    }
+
+    if !matches!(ty, Ty::Fn(_, _)) {
+        let scope = scope.clone();
+        options.push(
+            prop::collection::vec(value_ty(), 0..3)
+                .prop_flat_map(move |params| {
+                    let arguments: Vec<_> = params
+                        .iter()
+                        .map(|p| gen_expr(p.clone(), scope.clone(), d))
+                        .collect();
+                    (gen_fn(&params, ty.clone(), scope.clone(), d), arguments)
+                })
+                .prop_map(|(function, arguments)| call(function, arguments))
+                .boxed(),
+        );
+    }
+
+    Union::new(options).boxed()
+}
+
+/// A function literal taking `params` and returning `ret`, which may close
+/// over anything in `scope`
+fn gen_fn(params: &[Ty], ret: Ty, scope: Rc<Scope>, depth: u32) -> BoxedStrategy<Expr> {
+    let mut inner = (*scope).clone();
+    let names: Vec<_> = params
+        .iter()
+        .map(|ty| {
+            let name = inner.fresh();
+            inner.bind(name.clone(), ty.clone());
+            Ident(name)
+        })
+        .collect();
+
+    (0..3u32)
+        .prop_flat_map(move |lets| gen_block(ret.clone(), Rc::new(inner.clone()), depth, lets))
+        .prop_map(move |body| {
+            expr(ExprKind::FnExpr {
+                params: names.clone(),
+                body,
+            })
+        })
+        .boxed()
+}
+
+/// `let` statement binding a fresh name, with the scope that follows it
+fn gen_let(scope: Rc<Scope>, depth: u32) -> BoxedStrategy<(Stmt, Rc<Scope>)> {
+    prop_oneof![3 => value_ty(), 1 => fn_ty()]
+        .prop_flat_map(move |ty| {
+            let mut scope = (*scope).clone();
+            let name = scope.fresh();
+            let value = gen_expr(ty.clone(), Rc::new(scope.clone()), depth);
+            value.prop_map(move |value| {
+                let mut scope = scope.clone();
+                scope.bind(name.clone(), ty.clone());
+                let stmt = stmt(StmtKind::LetStmt(Ident(name.clone()), value));
+                (stmt, Rc::new(scope))
+            })
+        })
+        .boxed()
+}
+
+/// Function body: `lets` bindings, then an expression or `return` of type `ret`
+fn gen_block(ret: Ty, scope: Rc<Scope>, depth: u32, lets: u32) -> BoxedStrategy<Program> {
+    if lets == 0 {
+        let value = gen_expr(ret, scope, depth);
+        return prop_oneof![
+            value
+                .clone()
+                .prop_map(|e| vec![stmt(StmtKind::ExprStmt(e))]),
+            value.prop_map(|e| vec![stmt(StmtKind::ReturnStmt(e))]),
+        ]
+        .boxed();
+    }
+
+    gen_let(scope, depth)
+        .prop_flat_map(move |(first, scope)| {
+            gen_block(ret.clone(), scope, depth, lets - 1).prop_map(move |mut rest| {
+                rest.insert(0, first.clone());
+                rest
+            })
+        })
+        .boxed()
+}
+
+/// Top level statements, ending with an expression so the program has a value
+fn gen_program(scope: Rc<Scope>, stmts: u32) -> BoxedStrategy<Program> {
+    let value = value_ty().prop_flat_map({
+        let scope = scope.clone();
+        move |ty| gen_expr(ty, scope.clone(), MAX_DEPTH)
+    });
+    if stmts == 0 {
+        return value
+            .prop_map(|e| vec![stmt(StmtKind::ExprStmt(e))])
+            .boxed();
+    }
+
+    let value = value.prop_map({
+        let scope = scope.clone();
+        move |e| (stmt(StmtKind::ExprStmt(e)), scope.clone())
+    });
+    prop_oneof![3 => gen_let(scope, MAX_DEPTH), 1 => value]
+        .prop_flat_map(move |(first, scope)| {
+            gen_program(scope, stmts - 1).prop_map(move |mut rest| {
+                rest.insert(0, first.clone());
+                rest
+            })
+        })
+        .boxed()
+}
+
+fn program() -> impl Strategy<Value = Program> {
+    (0..6u32).prop_flat_map(|stmts| gen_program(Rc::default(), stmts))
+}
+
+fn parse(input: &str) -> Program {
+    let (_, lex_tokens) = Lexer::lex_tokens(input.as_bytes()).unwrap();
+    Parser::parse_tokens(Tokens::new(&lex_tokens)).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn printed_programs_parse_back(program in program()) {
+        let source = display(&program).to_string();
+        prop_assert_eq!(parse(&source), program, "{}", source);
+    }
+
+    #[test]
+    fn formatting_keeps_the_program(program in program(), width in 20..100usize) {
+        let formatted = format_source(&display(&program).to_string(), width).unwrap();
+        prop_assert_eq!(parse(&formatted), program, "{}", formatted);
+        prop_assert_eq!(format_source(&formatted, width).unwrap(), formatted);
+    }
+
+    #[test]
+    fn vm_runs_without_error(program in program()) {
+        let source = display(&program).to_string();
+        let mut compiler = Compiler::new();
+        compiler.compile(parse(&source)).unwrap();
+        let mut machine = VM::new(compiler.bytecode());
+        let result = machine.run();
+        // well typed programs can still divide by zero or shift too far
+        let ok = match &result {
+            Ok(()) => true,
+            Err(err) => matches!(err.error, MonkeyError::Arithmetic(_)),
+        };
+        prop_assert!(ok, "{}\n{:?}", source, result);
+    }
+
+    #[test]
+    fn engines_agree(program in program()) {
+        let source = display(&program).to_string();
+        let disagreement = common::compare_engines(&parse(&source));
+        prop_assert!(disagreement.is_none(), "{}\n{:?}", source, disagreement);
+    }
+
+    #[test]
+    fn arbitrary_input_does_not_panic(input in "\\PC{0,40}") {
+        if let Ok((_, lex_tokens)) = Lexer::lex_tokens(input.as_bytes()) {
+            let _ = Parser::parse_recovering(Tokens::new(&lex_tokens));
+        }
+    }
+}